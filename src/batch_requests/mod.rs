@@ -1,2 +1,236 @@
 pub mod uniswap_v2;
 pub mod uniswap_v3;
+
+use std::sync::Arc;
+
+use ethers::{
+    abi::{Token, Tokenize},
+    providers::{JsonRpcError, Middleware, MiddlewareError, RpcError},
+    types::{
+        transaction::eip2718::TypedTransaction, BlockId, Bytes, TransactionRequest, H160, U64,
+    },
+};
+
+use crate::errors::CFMMError;
+
+//Contract that serves a batch request in place of the deployless bytecode embedded in the crate
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BatchContract {
+    //Deployless bytecode, called with the ABI encoded constructor arguments appended, e.g. the same contract compiled for an
+    //older EVM version
+    Bytecode(Bytes),
+    //Contract deployed at the address, called with the ABI encoded constructor arguments as calldata. It must return the same
+    //data as the deployless contract's constructor.
+    Deployed(H160),
+}
+
+//Overrides for the batch request contracts, for chains where the embedded bytecode does not run.
+//Requests without an override use the embedded bytecode.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BatchConfig {
+    pub pool_data: Option<BatchContract>,
+    pub tick_data: Option<BatchContract>,
+}
+
+impl BatchContract {
+    pub(crate) async fn call<M: Middleware>(
+        &self,
+        constructor_args: Token,
+        block_number: Option<U64>,
+        middleware: Arc<M>,
+    ) -> Result<Bytes, CFMMError<M>> {
+        let encoded_args = ethers::abi::encode(&constructor_args.into_tokens());
+
+        let tx: TypedTransaction = match self {
+            BatchContract::Bytecode(bytecode) => TransactionRequest::new()
+                .data([bytecode.to_vec(), encoded_args].concat())
+                .into(),
+            BatchContract::Deployed(address) => TransactionRequest::new()
+                .to(*address)
+                .data(encoded_args)
+                .into(),
+        };
+
+        middleware
+            .call(&tx, block_number.map(BlockId::from))
+            .await
+            .map_err(CFMMError::MiddlewareError)
+    }
+}
+
+//Returns true if the batch request failed because the contract reverted rather than because of the node or transport
+pub(crate) fn is_revert<M: Middleware>(error: &CFMMError<M>) -> bool {
+    match error {
+        CFMMError::ProviderError(provider_error) => {
+            RpcError::as_error_response(provider_error).is_some_and(JsonRpcError::is_revert)
+        }
+        CFMMError::MiddlewareError(middleware_error) => middleware_error
+            .as_error_response()
+            .is_some_and(JsonRpcError::is_revert),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use ethers::{
+        abi::Token,
+        providers::{JsonRpcClient, MockError, MockProvider, Provider},
+        types::{Bytes, H160, I256, U256},
+    };
+    use serde::{de::DeserializeOwned, Serialize};
+
+    use super::{BatchConfig, BatchContract};
+    use crate::{
+        batch_requests::uniswap_v3::get_uniswap_v3_tick_data_batch_request_with_config,
+        pool::{uniswap_v3::encode_i24, UniswapV3Pool},
+    };
+
+    type RecordedCalls = Arc<Mutex<Vec<serde_json::Value>>>;
+
+    //Records the transaction of every eth_call served by a MockProvider
+    #[derive(Debug)]
+    struct RecordingClient {
+        mock: MockProvider,
+        calls: RecordedCalls,
+    }
+
+    #[async_trait::async_trait]
+    impl JsonRpcClient for RecordingClient {
+        type Error = MockError;
+
+        async fn request<T, R>(&self, method: &str, params: T) -> Result<R, MockError>
+        where
+            T: Serialize + Send + Sync + std::fmt::Debug,
+            R: DeserializeOwned + Send,
+        {
+            if method == "eth_call" {
+                let params = serde_json::to_value(&params)?;
+                self.calls.lock().unwrap().push(params[0].clone());
+            }
+
+            self.mock.request(method, params).await
+        }
+    }
+
+    fn recording_middleware() -> (Arc<Provider<RecordingClient>>, MockProvider, RecordedCalls) {
+        let mock = MockProvider::new();
+        let calls = Arc::new(Mutex::new(vec![]));
+        let middleware = Arc::new(Provider::new(RecordingClient {
+            mock: mock.clone(),
+            calls: calls.clone(),
+        }));
+
+        (middleware, mock, calls)
+    }
+
+    fn call_data(call: &serde_json::Value) -> Bytes {
+        serde_json::from_value(call["data"].clone()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_tick_data_with_bytecode_override() {
+        let (middleware, mock, calls) = recording_middleware();
+        mock.push::<Bytes, _>(Bytes::from(ethers::abi::encode(&[
+            Token::Array(vec![Token::Tuple(vec![
+                Token::Bool(true),
+                Token::Int(encode_i24(-10)),
+                Token::Int(I256::from(5).into_raw()),
+            ])]),
+            Token::Uint(U256::from(7)),
+        ])))
+        .unwrap();
+
+        let bytecode = Bytes::from(vec![0x60, 0x80, 0x60, 0x40]);
+        let config = BatchConfig {
+            tick_data: Some(BatchContract::Bytecode(bytecode.clone())),
+            ..Default::default()
+        };
+        let pool = UniswapV3Pool {
+            address: H160::from_low_u64_be(1),
+            tick_spacing: 10,
+            ..Default::default()
+        };
+
+        let (tick_data, block_number) = get_uniswap_v3_tick_data_batch_request_with_config(
+            &pool, 0, true, 1, None, &config, middleware,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(tick_data.len(), 1);
+        assert_eq!((tick_data[0].tick, tick_data[0].liquidity_net), (-10, 5));
+        assert_eq!(block_number.as_u64(), 7);
+
+        //The custom bytecode is deployed with the constructor arguments appended
+        let calls = calls.lock().unwrap();
+        assert_eq!(calls.len(), 1);
+        assert!(calls[0]["to"].is_null());
+
+        let data = call_data(&calls[0]);
+        assert!(data.starts_with(&bytecode));
+        assert_eq!(
+            data[bytecode.len()..],
+            ethers::abi::encode(&[
+                Token::Address(pool.address),
+                Token::Bool(true),
+                Token::Int(encode_i24(0)),
+                Token::Uint(U256::one()),
+                Token::Int(encode_i24(10)),
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_pool_data_with_deployed_override() {
+        let (middleware, mock, calls) = recording_middleware();
+
+        mock.push::<Bytes, _>(Bytes::from(ethers::abi::encode(&[Token::Array(vec![
+            Token::Tuple(vec![
+                Token::Address(H160::from_low_u64_be(2)),
+                Token::Uint(U256::from(18)),
+                Token::Address(H160::from_low_u64_be(3)),
+                Token::Uint(U256::from(6)),
+                Token::Uint(U256::from(1_000_000)),
+                Token::Uint(U256::one() << 96),
+                Token::Int(U256::zero()),
+                Token::Int(U256::from(10)),
+                Token::Uint(U256::from(500)),
+                Token::Int(U256::zero()),
+            ]),
+        ])])))
+        .unwrap();
+
+        let batch_address = H160::from_low_u64_be(0xba7c);
+        let config = BatchConfig {
+            pool_data: Some(BatchContract::Deployed(batch_address)),
+            ..Default::default()
+        };
+        let mut pool = UniswapV3Pool {
+            address: H160::from_low_u64_be(1),
+            ..Default::default()
+        };
+
+        pool.get_pool_data_with_config(&config, middleware)
+            .await
+            .unwrap();
+
+        assert_eq!(pool.token_a, H160::from_low_u64_be(2));
+        assert_eq!(pool.token_b_decimals, 6);
+        assert_eq!(pool.fee, 500);
+        assert_eq!(pool.tick_spacing, 10);
+
+        //The deployed contract is called with the constructor arguments as calldata
+        let calls = calls.lock().unwrap();
+        assert_eq!(
+            serde_json::from_value::<H160>(calls[0]["to"].clone()).unwrap(),
+            batch_address
+        );
+        assert_eq!(
+            call_data(&calls[0]).to_vec(),
+            ethers::abi::encode(&[Token::Array(vec![Token::Address(pool.address)])])
+        );
+    }
+}
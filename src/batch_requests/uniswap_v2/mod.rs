@@ -18,6 +18,10 @@ abigen!(
     "src/batch_requests/uniswap_v2/GetUniswapV2PoolDataBatchRequest.json";
 );
 
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(skip_all, fields(?factory, %from, %step))
+)]
 pub async fn get_pairs_batch_request<M: Middleware>(
     factory: H160,
     from: U256,
@@ -55,6 +59,10 @@ pub async fn get_pairs_batch_request<M: Middleware>(
     Ok(pairs)
 }
 
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(skip_all, fields(num_pools = pools.len()))
+)]
 pub async fn get_pool_data_batch_request<M: Middleware>(
     pools: &mut [Pool],
     middleware: Arc<M>,
@@ -117,6 +125,10 @@ pub async fn get_pool_data_batch_request<M: Middleware>(
     Ok(())
 }
 
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(skip_all, fields(pool = ?pool.address))
+)]
 pub async fn get_v2_pool_data_batch_request<M: Middleware>(
     pool: &mut UniswapV2Pool,
     middleware: Arc<M>,
@@ -1,5 +1,7 @@
 use std::{sync::Arc, vec};
 
+use futures::future;
+
 use ethers::{
     abi::{ParamType, Token},
     prelude::abigen,
@@ -8,8 +10,12 @@ use ethers::{
 };
 
 use crate::{
+    batch_requests::{is_revert, BatchConfig},
     errors::CFMMError,
-    pool::{Pool, UniswapV3Pool},
+    pool::{
+        uniswap_v3::{decode_i24, encode_i24},
+        Pool, UniswapV3Pool,
+    },
 };
 
 abigen!(
@@ -24,9 +30,33 @@ abigen!(
     "src/batch_requests/uniswap_v3/GetUniswapV3TickDataBatchRequest.json";
 );
 
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(skip_all, fields(num_pools = pools.len()))
+)]
 pub async fn get_pool_data_batch_request<M: Middleware>(
     pools: &mut [Pool],
     middleware: Arc<M>,
+) -> Result<(), CFMMError<M>> {
+    pool_data_batch_request(pools, None, middleware).await
+}
+
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(skip_all, fields(num_pools = pools.len(), %block_number))
+)]
+pub async fn get_pool_data_batch_request_at_block<M: Middleware>(
+    pools: &mut [Pool],
+    block_number: U64,
+    middleware: Arc<M>,
+) -> Result<(), CFMMError<M>> {
+    pool_data_batch_request(pools, Some(block_number), middleware).await
+}
+
+async fn pool_data_batch_request<M: Middleware>(
+    pools: &mut [Pool],
+    block_number: Option<U64>,
+    middleware: Arc<M>,
 ) -> Result<(), CFMMError<M>> {
     let mut target_addresses = vec![];
 
@@ -38,7 +68,11 @@ pub async fn get_pool_data_batch_request<M: Middleware>(
     let deployer =
         GetUniswapV3PoolDataBatchRequest::deploy(middleware.clone(), constructor_args).unwrap();
 
-    let return_data: Bytes = deployer.call_raw().await?;
+    let return_data: Bytes = if let Some(block_number) = block_number {
+        deployer.block(block_number).call_raw().await?
+    } else {
+        deployer.call_raw().await?
+    };
 
     let return_data_tokens = ethers::abi::decode(
         &[ParamType::Array(Box::new(ParamType::Tuple(vec![
@@ -86,12 +120,10 @@ pub async fn get_pool_data_batch_request<M: Middleware>(
                                 pool_data[5].to_owned().into_uint().unwrap();
 
                             uniswap_v3_pool.tick =
-                                I256::from_raw(pool_data[6].to_owned().into_int().unwrap())
-                                    .as_i32();
+                                decode_i24(pool_data[6].to_owned().into_int().unwrap());
 
                             uniswap_v3_pool.tick_spacing =
-                                I256::from_raw(pool_data[7].to_owned().into_int().unwrap())
-                                    .as_i32();
+                                decode_i24(pool_data[7].to_owned().into_int().unwrap());
 
                             uniswap_v3_pool.fee =
                                 pool_data[8].to_owned().into_uint().unwrap().as_u64() as u32;
@@ -112,13 +144,49 @@ pub async fn get_pool_data_batch_request<M: Middleware>(
 pub async fn get_v3_pool_data_batch_request<M: Middleware>(
     pool: &mut UniswapV3Pool,
     middleware: Arc<M>,
+) -> Result<(), CFMMError<M>> {
+    get_v3_pool_data_batch_request_with_config(pool, &BatchConfig::default(), middleware).await
+}
+
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(skip_all, fields(pool = ?pool.address))
+)]
+pub async fn get_v3_pool_data_batch_request_with_config<M: Middleware>(
+    pool: &mut UniswapV3Pool,
+    config: &BatchConfig,
+    middleware: Arc<M>,
 ) -> Result<(), CFMMError<M>> {
     let constructor_args = Token::Tuple(vec![Token::Array(vec![Token::Address(pool.address())])]);
 
-    let deployer =
-        GetUniswapV3PoolDataBatchRequest::deploy(middleware.clone(), constructor_args).unwrap();
+    let return_data = if let Some(batch_contract) = &config.pool_data {
+        batch_contract
+            .call(constructor_args, None, middleware.clone())
+            .await
+    } else {
+        GetUniswapV3PoolDataBatchRequest::deploy(middleware.clone(), constructor_args)
+            .unwrap()
+            .call_raw()
+            .await
+            .map_err(CFMMError::from)
+    };
 
-    let return_data: Bytes = deployer.call_raw().await?;
+    let return_data: Bytes = match return_data {
+        Ok(return_data) => return_data,
+
+        //The deployless batch request can revert on chains whose EVM differs from mainnet, in which case each field is fetched separately
+        Err(error) if is_revert(&error) => {
+            #[cfg(feature = "tracing")]
+            tracing::warn!(
+                pool = ?pool.address,
+                "pool data batch request reverted, falling back to individual calls"
+            );
+
+            return pool.get_pool_data_individually(middleware).await;
+        }
+
+        Err(error) => return Err(error),
+    };
 
     let return_data_tokens = ethers::abi::decode(
         &[ParamType::Array(Box::new(ParamType::Tuple(vec![
@@ -158,11 +226,9 @@ pub async fn get_v3_pool_data_batch_request<M: Middleware>(
 
                         pool.sqrt_price = pool_data[5].to_owned().into_uint().unwrap();
 
-                        pool.tick =
-                            I256::from_raw(pool_data[6].to_owned().into_int().unwrap()).as_i32();
+                        pool.tick = decode_i24(pool_data[6].to_owned().into_int().unwrap());
 
-                        pool.tick_spacing =
-                            I256::from_raw(pool_data[7].to_owned().into_int().unwrap()).as_i32();
+                        pool.tick_spacing = decode_i24(pool_data[7].to_owned().into_int().unwrap());
 
                         pool.fee = pool_data[8].to_owned().into_uint().unwrap().as_u64() as u32;
 
@@ -176,6 +242,7 @@ pub async fn get_v3_pool_data_batch_request<M: Middleware>(
     Ok(())
 }
 
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
 pub struct UniswapV3TickData {
     pub initialized: bool,
     pub tick: i32,
@@ -189,22 +256,53 @@ pub async fn get_uniswap_v3_tick_data_batch_request<M: Middleware>(
     num_ticks: u16,
     block_number: Option<U64>,
     middleware: Arc<M>,
+) -> Result<(Vec<UniswapV3TickData>, U64), CFMMError<M>> {
+    get_uniswap_v3_tick_data_batch_request_with_config(
+        pool,
+        tick_start,
+        zero_for_one,
+        num_ticks,
+        block_number,
+        &BatchConfig::default(),
+        middleware,
+    )
+    .await
+}
+
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(skip_all, fields(pool = ?pool.address, %tick_start, %zero_for_one, %num_ticks, ?block_number))
+)]
+pub async fn get_uniswap_v3_tick_data_batch_request_with_config<M: Middleware>(
+    pool: &UniswapV3Pool,
+    tick_start: i32,
+    zero_for_one: bool,
+    num_ticks: u16,
+    block_number: Option<U64>,
+    config: &BatchConfig,
+    middleware: Arc<M>,
 ) -> Result<(Vec<UniswapV3TickData>, U64), CFMMError<M>> {
     let constructor_args = Token::Tuple(vec![
         Token::Address(pool.address()),
         Token::Bool(zero_for_one),
-        Token::Int(I256::from(tick_start).into_raw()),
+        Token::Int(encode_i24(tick_start)),
         Token::Uint(U256::from(num_ticks)),
-        Token::Int(I256::from(pool.tick_spacing).into_raw()),
+        Token::Int(encode_i24(pool.tick_spacing)),
     ]);
 
-    let deployer =
-        GetUniswapV3TickDataBatchRequest::deploy(middleware.clone(), constructor_args).unwrap();
-
-    let return_data: Bytes = if block_number.is_some() {
-        deployer.block(block_number.unwrap()).call_raw().await?
+    let return_data: Bytes = if let Some(batch_contract) = &config.tick_data {
+        batch_contract
+            .call(constructor_args, block_number, middleware.clone())
+            .await?
     } else {
-        deployer.call_raw().await?
+        let deployer =
+            GetUniswapV3TickDataBatchRequest::deploy(middleware.clone(), constructor_args).unwrap();
+
+        if block_number.is_some() {
+            deployer.block(block_number.unwrap()).call_raw().await?
+        } else {
+            deployer.call_raw().await?
+        }
     };
 
     let return_data_tokens = ethers::abi::decode(
@@ -234,13 +332,12 @@ pub async fn get_uniswap_v3_tick_data_batch_request<M: Middleware>(
                 .into_bool()
                 .expect("Could not convert token to bool");
 
-            let initialized_tick = I256::from_raw(
+            let initialized_tick = decode_i24(
                 tick_data_tuple[1]
                     .to_owned()
                     .into_int()
                     .expect("Could not convert token to int"),
-            )
-            .as_i32();
+            );
 
             let liquidity_net = I256::from_raw(
                 tick_data_tuple[2]
@@ -263,19 +360,96 @@ pub async fn get_uniswap_v3_tick_data_batch_request<M: Middleware>(
         .into_uint()
         .expect("Failed to convert block_number from Token to U64");
 
+    #[cfg(feature = "tracing")]
+    tracing::debug!(
+        num_ticks = tick_data.len(),
+        block_number = block_number.as_u64(),
+        "fetched tick data"
+    );
+
     Ok((tick_data, U64::from(block_number.as_u64())))
 }
 
+//Fetches num_ticks of tick data in each direction from the current tick of every pool, pinned to the same block so the windows are consistent
+//with each other. Results are aligned with pools, with the tick data of each pool sorted by tick along with the block it was fetched at.
+//Each pool and direction is its own tick data batch request, so this makes one block number request and two calls per pool, run concurrently.
+pub async fn get_tick_data_concurrently<M: Middleware>(
+    pools: &[UniswapV3Pool],
+    num_ticks: u16,
+    middleware: Arc<M>,
+) -> Result<Vec<(Vec<UniswapV3TickData>, U64)>, CFMMError<M>> {
+    let block_number = middleware
+        .get_block_number()
+        .await
+        .map_err(CFMMError::MiddlewareError)?;
+
+    future::try_join_all(pools.iter().map(|pool| {
+        let middleware = middleware.clone();
+        async move {
+            let (mut tick_data, block_number) = get_uniswap_v3_tick_data_batch_request(
+                pool,
+                pool.tick,
+                true,
+                num_ticks,
+                Some(block_number),
+                middleware.clone(),
+            )
+            .await?;
+
+            let (tick_data_above, _) = get_uniswap_v3_tick_data_batch_request(
+                pool,
+                pool.tick,
+                false,
+                num_ticks,
+                Some(block_number),
+                middleware,
+            )
+            .await?;
+
+            tick_data.extend(tick_data_above);
+            tick_data.sort_unstable_by_key(|tick_data| tick_data.tick);
+            tick_data.dedup_by_key(|tick_data| tick_data.tick);
+
+            Ok((tick_data, block_number))
+        }
+    }))
+    .await
+}
+
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(skip_all, fields(pool = ?pool.address))
+)]
 pub async fn sync_v3_pool_batch_request<M: Middleware>(
     pool: &mut UniswapV3Pool,
     middleware: Arc<M>,
+) -> Result<(), CFMMError<M>> {
+    sync_pool_batch_request(pool, None, middleware).await
+}
+
+pub async fn sync_v3_pool_batch_request_at_block<M: Middleware>(
+    pool: &mut UniswapV3Pool,
+    block_number: U64,
+    middleware: Arc<M>,
+) -> Result<(), CFMMError<M>> {
+    sync_pool_batch_request(pool, Some(block_number), middleware).await
+}
+
+async fn sync_pool_batch_request<M: Middleware>(
+    pool: &mut UniswapV3Pool,
+    block_number: Option<U64>,
+    middleware: Arc<M>,
 ) -> Result<(), CFMMError<M>> {
     let constructor_args = Token::Tuple(vec![Token::Address(pool.address())]);
 
     let deployer =
         SyncUniswapV3PoolBatchRequest::deploy(middleware.clone(), constructor_args).unwrap();
 
-    let return_data: Bytes = deployer.call_raw().await?;
+    let return_data: Bytes = if let Some(block_number) = block_number {
+        deployer.block(block_number).call_raw().await?
+    } else {
+        deployer.call_raw().await?
+    };
     let return_data_tokens = ethers::abi::decode(
         &[ParamType::Tuple(vec![
             ParamType::Uint(128), // liquidity
@@ -293,7 +467,7 @@ pub async fn sync_v3_pool_batch_request<M: Middleware>(
                 //Update the pool data
                 pool.liquidity = pool_data[0].to_owned().into_uint().unwrap().as_u128();
                 pool.sqrt_price = pool_data[1].to_owned().into_uint().unwrap();
-                pool.tick = I256::from_raw(pool_data[2].to_owned().into_int().unwrap()).as_i32();
+                pool.tick = decode_i24(pool_data[2].to_owned().into_int().unwrap());
                 pool.liquidity_net =
                     I256::from_raw(pool_data[3].to_owned().into_int().unwrap()).as_i128();
             } else {
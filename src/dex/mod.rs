@@ -9,7 +9,7 @@ use indicatif::ProgressBar;
 use crate::{
     abi, batch_requests,
     errors::CFMMError,
-    pool::{Pool, UniswapV2Pool, UniswapV3Pool},
+    pool::{uniswap_v3::FeeTier, Pool, UniswapV2Pool, UniswapV3Pool},
     throttle::RequestThrottle,
 };
 
@@ -198,9 +198,9 @@ impl Dex {
                 let mut best_liquidity = 0;
                 let mut best_pool_address = H160::zero();
 
-                for fee in [100, 300, 500, 1000] {
+                for fee_tier in FeeTier::CANONICAL {
                     let pool_address = match uniswap_v3_factory
-                        .get_pool(token_a, token_b, fee)
+                        .get_pool(token_a, token_b, fee_tier.to_u32())
                         .call()
                         .await
                     {
@@ -267,9 +267,9 @@ impl Dex {
 
                 let mut pools = vec![];
 
-                for fee in [100, 300, 500, 1000] {
+                for fee_tier in FeeTier::CANONICAL {
                     match uniswap_v3_factory
-                        .get_pool(token_a, token_b, fee)
+                        .get_pool(token_a, token_b, fee_tier.to_u32())
                         .call()
                         .await
                     {
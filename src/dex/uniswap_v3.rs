@@ -71,6 +71,9 @@ impl UniswapV3Dex {
             tick_spacing: 0,
             tick: 0,
             liquidity_net: 0,
+            last_synced_block: 0,
+            created_at_block: log.block_number.map(|block_number| block_number.as_u64()),
+            synced_at: None,
         }))
     }
 
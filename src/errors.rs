@@ -1,8 +1,8 @@
 use std::fmt;
 
 use ethers::prelude::{AbiError, ContractError};
-use ethers::providers::{Middleware, ProviderError};
-use ethers::types::{H160, U256};
+use ethers::providers::{JsonRpcError, Middleware, ProviderError, RpcError};
+use ethers::types::{H160, H256, U256};
 use thiserror::Error;
 use tokio::task::JoinError;
 use uniswap_v3_math::error::UniswapV3MathError;
@@ -40,6 +40,36 @@ where
     NoInitializedTicks,
     #[error("No liquidity net found during v3 swap simulation")]
     NoLiquidityNet,
+    #[error("Liquidity net overflowed during v3 swap simulation")]
+    LiquidityNetOverflow(H160),
+    #[error("Could not decode log data")]
+    AbiDecode(H160, H256),
+    #[error("No route found between token_in and token_out")]
+    NoRouteFound(H160, H160),
+    #[error("Sqrt price limit is outside of the valid range for the swap direction")]
+    InvalidSqrtPriceLimit(U256),
+    #[error("Pool token_a and token_b are identical")]
+    IdenticalTokens(H160),
+    #[error("Token symbol is not in the registry")]
+    UnknownTokenSymbol(String),
+    #[error("Token is not in the pool")]
+    TokenNotInPool(H160, H160),
+    #[error("Pool has not been initialized")]
+    PoolNotInitialized(H160),
+    #[error("Amount in is larger than I256::MAX")]
+    AmountTooLarge(U256),
+    #[error("Pool address is the zero address")]
+    ZeroAddress,
+    #[error("Could not find the PoolCreated log for the pool")]
+    CreationBlockNotFound(H160),
+    #[error("Pool does not have enough liquidity to fill the amount out")]
+    InsufficientLiquidity(H160),
+    #[error("Swap simulation did not converge within the maximum number of steps")]
+    SimulationDidNotConverge(H160),
+    #[error("Pool had no liquidity at any of the sampled blocks")]
+    NoSampledLiquidity(H160),
+    #[error("Swap runs past the cached tick data")]
+    TickDataExceeded(H160),
 }
 
 #[derive(Error, Debug)]
@@ -48,6 +78,61 @@ pub enum ArithmeticError {
     RoundingError,
     YIsZero,
     SqrtPriceOverflow,
+    TickInconsistency(i32, i32),
+    LiquidityNetOverflow,
+    AmountOverflow(U256),
+    UninitializedTickSpacing,
+    InvalidFee(u32),
+    LiquidityOverflow(U256),
+    InvalidTickRange(i32, i32),
+    AmountTooLarge(U256),
+    UniswapV3MathError(#[from] UniswapV3MathError),
+}
+
+//Error when constructing a pool from its raw values, see UniswapV3Pool::try_new
+#[derive(Error, Debug)]
+pub enum PoolError {
+    #[error("Pool token_a and token_b are identical")]
+    IdenticalTokens(H160),
+}
+
+//Error of a PoolProviderClient, see crate::provider
+#[derive(Error, Debug)]
+pub enum PoolProviderError {
+    #[error("Could not (de)serialize the request")]
+    SerdeJson(#[from] serde_json::Error),
+    #[error("Pool provider error")]
+    Provider(Box<dyn std::error::Error + Send + Sync>),
+    #[error("Method is not supported by the pool provider")]
+    UnsupportedMethod(String),
+}
+
+impl RpcError for PoolProviderError {
+    fn as_error_response(&self) -> Option<&JsonRpcError> {
+        None
+    }
+
+    fn as_serde_error(&self) -> Option<&serde_json::Error> {
+        match self {
+            PoolProviderError::SerdeJson(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<PoolProviderError> for ProviderError {
+    fn from(error: PoolProviderError) -> Self {
+        ProviderError::JsonRpcClientError(Box::new(error))
+    }
+}
+
+#[cfg(feature = "protobuf")]
+#[derive(Error, Debug)]
+pub enum ProtoError {
+    #[error("Could not decode protobuf message")]
+    Decode(#[from] prost::DecodeError),
+    #[error("Protobuf field does not fit in the type it encodes")]
+    FieldOutOfRange(&'static str),
 }
 
 impl std::fmt::Display for ArithmeticError {
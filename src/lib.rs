@@ -3,8 +3,14 @@ pub mod checkpoint;
 pub mod dex;
 pub mod errors;
 pub mod pool;
+pub mod provider;
+pub mod reference;
+pub mod registry;
+pub mod routing;
 pub mod sync;
 pub mod throttle;
 pub use pool::simulate_route;
 pub use pool::simulate_route_mut;
 pub mod batch_requests;
+#[cfg(test)]
+mod test_utils;
@@ -0,0 +1,576 @@
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use ethers::{
+    providers::Middleware,
+    types::{H160, U256},
+};
+use tokio::sync::RwLock;
+
+use crate::{
+    errors::CFMMError,
+    pool::{uniswap_v3::FrozenPool, UniswapV3Pool},
+};
+
+//Pool fetched by the cache along with when it was fetched and when it was last looked up
+#[derive(Clone, Copy, Debug)]
+struct CacheEntry {
+    pool: UniswapV3Pool,
+    fetched_at: Instant,
+    last_used: u64,
+}
+
+//Least recently used cache of pools by address, so that repeated lookups of the same pool do not refetch its data.
+//Pools expire ttl after they were fetched, and once the cache holds capacity pools the least recently used one is evicted.
+#[derive(Clone, Debug)]
+pub struct PoolCache {
+    entries: HashMap<H160, CacheEntry>,
+    capacity: usize,
+    ttl: Duration,
+    //Incremented on every lookup and insert to order entries by use
+    clock: u64,
+}
+
+impl PoolCache {
+    pub fn new(capacity: usize, ttl: Duration) -> PoolCache {
+        PoolCache {
+            entries: HashMap::new(),
+            capacity,
+            ttl,
+            clock: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    //Returns the cached pool for the address if it has not expired, removing it if it has
+    pub fn get(&mut self, address: H160) -> Option<UniswapV3Pool> {
+        self.clock += 1;
+
+        let entry = self.entries.get_mut(&address)?;
+        if entry.fetched_at.elapsed() >= self.ttl {
+            self.entries.remove(&address);
+            return None;
+        }
+
+        entry.last_used = self.clock;
+        Some(entry.pool)
+    }
+
+    //Caches the pool, evicting the least recently used pool if the cache is full
+    pub fn insert(&mut self, pool: UniswapV3Pool) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        self.clock += 1;
+
+        if !self.entries.contains_key(&pool.address) && self.entries.len() >= self.capacity {
+            if let Some(least_recently_used) = self
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(address, _)| *address)
+            {
+                self.entries.remove(&least_recently_used);
+            }
+        }
+
+        self.entries.insert(
+            pool.address,
+            CacheEntry {
+                pool,
+                fetched_at: Instant::now(),
+                last_used: self.clock,
+            },
+        );
+    }
+
+    //Returns the cached pool for the address, or fetches it with UniswapV3Pool::new_from_address and caches it
+    pub async fn get_or_fetch<M: Middleware>(
+        &mut self,
+        address: H160,
+        middleware: Arc<M>,
+    ) -> Result<UniswapV3Pool, CFMMError<M>> {
+        if let Some(pool) = self.get(address) {
+            return Ok(pool);
+        }
+
+        let pool = UniswapV3Pool::new_from_address(address, middleware).await?;
+        self.insert(pool);
+
+        Ok(pool)
+    }
+}
+
+//Memoizes simulated swaps within a block, so that a quoting server answering identical requests in the same block does not
+//simulate them again. Quotes are keyed by (pool, token_in, amount_in, block) and are all dropped once a quote for a later block is cached.
+#[derive(Clone, Debug, Default)]
+pub struct QuoteCache {
+    block_number: u64,
+    quotes: HashMap<(H160, H160, U256), U256>,
+}
+
+impl QuoteCache {
+    pub fn new() -> QuoteCache {
+        QuoteCache::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.quotes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.quotes.is_empty()
+    }
+
+    //Returns the cached amount out of the swap at block_number, if there is one
+    pub fn get(
+        &self,
+        pool: H160,
+        token_in: H160,
+        amount_in: U256,
+        block_number: u64,
+    ) -> Option<U256> {
+        if block_number != self.block_number {
+            return None;
+        }
+
+        self.quotes.get(&(pool, token_in, amount_in)).copied()
+    }
+
+    //Caches the amount out of the swap at block_number. Quotes from earlier blocks are dropped, and quotes for them are not cached.
+    pub fn insert(
+        &mut self,
+        pool: H160,
+        token_in: H160,
+        amount_in: U256,
+        block_number: u64,
+        amount_out: U256,
+    ) {
+        if block_number < self.block_number {
+            return;
+        }
+
+        if block_number > self.block_number {
+            self.quotes.clear();
+            self.block_number = block_number;
+        }
+
+        self.quotes.insert((pool, token_in, amount_in), amount_out);
+    }
+
+    //Returns the cached amount out of the swap at block_number, or simulates it with UniswapV3Pool::simulate_swap and caches it.
+    //block_number is the block the caller is quoting at, e.g. from a block subscription, and is not checked against the node.
+    pub async fn simulate_swap<M: Middleware>(
+        &mut self,
+        pool: &UniswapV3Pool,
+        token_in: H160,
+        amount_in: U256,
+        block_number: u64,
+        middleware: Arc<M>,
+    ) -> Result<U256, CFMMError<M>> {
+        if let Some(amount_out) = self.get(pool.address, token_in, amount_in, block_number) {
+            return Ok(amount_out);
+        }
+
+        let amount_out = pool.simulate_swap(token_in, amount_in, middleware).await?;
+        self.insert(pool.address, token_in, amount_in, block_number, amount_out);
+
+        Ok(amount_out)
+    }
+}
+
+//Pool that fetches its tick data once and shares it between clones, so that a SharedPool behind an Arc can be quoted
+//concurrently from many tasks without each quote refetching tick data.
+//The pool and its tick data are held in an Arc<RwLock<..>>: the first quote fetches the tick data under the write lock while any
+//concurrent quotes wait, and every quote after that only takes the read lock, so cached quotes never block each other. The lock is
+//not held while simulating. Quotes are against the pool state and tick data at the time of the fetch until invalidate is called.
+//Only num_ticks of tick data are cached in each direction, so a swap that runs past them returns TickDataExceeded rather than a
+//quote that assumes the liquidity stays the same up to the tick bounds. Use UniswapV3Pool::simulate_swap for such swaps.
+#[derive(Clone, Debug)]
+pub struct SharedPool {
+    num_ticks: u16,
+    state: Arc<RwLock<SharedPoolState>>,
+}
+
+#[derive(Debug)]
+struct SharedPoolState {
+    pool: UniswapV3Pool,
+    frozen: Option<Arc<FrozenPool>>,
+}
+
+impl SharedPool {
+    //num_ticks of tick data are fetched in each direction from the current tick
+    pub fn new(pool: UniswapV3Pool, num_ticks: u16) -> SharedPool {
+        SharedPool {
+            num_ticks,
+            state: Arc::new(RwLock::new(SharedPoolState { pool, frozen: None })),
+        }
+    }
+
+    pub async fn pool(&self) -> UniswapV3Pool {
+        self.state.read().await.pool
+    }
+
+    //Simulates the swap against the cached tick data, fetching it first if it is not cached yet
+    pub async fn simulate_swap<M: Middleware>(
+        &self,
+        token_in: H160,
+        amount_in: U256,
+        middleware: Arc<M>,
+    ) -> Result<U256, CFMMError<M>> {
+        let frozen = self.frozen_pool(middleware).await?;
+        let swap_result = frozen.simulate_swap_detailed(token_in, amount_in)?;
+
+        if !frozen.is_within_tick_data(token_in, &swap_result) {
+            return Err(CFMMError::TickDataExceeded(self.pool().await.address));
+        }
+
+        Ok(swap_result.amount_out)
+    }
+
+    //Replaces the pool with the freshly synced pool and drops the cached tick data, so that the next quote refetches it for the new state
+    pub async fn invalidate(&self, pool: UniswapV3Pool) {
+        *self.state.write().await = SharedPoolState { pool, frozen: None };
+    }
+
+    async fn frozen_pool<M: Middleware>(
+        &self,
+        middleware: Arc<M>,
+    ) -> Result<Arc<FrozenPool>, CFMMError<M>> {
+        if let Some(frozen) = self.state.read().await.frozen.as_ref() {
+            return Ok(frozen.clone());
+        }
+
+        let mut state = self.state.write().await;
+
+        //Another task may have fetched the tick data while this one was waiting on the write lock
+        if let Some(frozen) = state.frozen.as_ref() {
+            return Ok(frozen.clone());
+        }
+
+        let fetched = Arc::new(state.pool.freeze(self.num_ticks, middleware).await?);
+        state.frozen = Some(fetched.clone());
+
+        Ok(fetched)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use ethers::{
+        abi::Token,
+        providers::{JsonRpcClient, MockError, MockProvider, Provider},
+        types::{Bytes, H160, U256, U64},
+    };
+    use serde::{de::DeserializeOwned, Serialize};
+
+    use super::{PoolCache, QuoteCache, SharedPool};
+    use crate::{
+        errors::CFMMError,
+        pool::UniswapV3Pool,
+        test_utils::{mock_pool, mock_tick_data_response},
+    };
+
+    //Counts the requests served by a MockProvider
+    #[derive(Debug)]
+    struct CountingClient {
+        mock: MockProvider,
+        requests: Arc<AtomicUsize>,
+    }
+
+    #[async_trait::async_trait]
+    impl JsonRpcClient for CountingClient {
+        type Error = MockError;
+
+        async fn request<T, R>(&self, method: &str, params: T) -> Result<R, MockError>
+        where
+            T: Serialize + Send + Sync + std::fmt::Debug,
+            R: DeserializeOwned + Send,
+        {
+            self.requests.fetch_add(1, Ordering::SeqCst);
+            self.mock.request(method, params).await
+        }
+    }
+
+    //Pushes the response to the pool data batch request made by UniswapV3Pool::new_from_address
+    fn push_pool_data(mock: &MockProvider, address: H160) {
+        mock.push::<Bytes, _>(Bytes::from(ethers::abi::encode(&[Token::Array(vec![
+            Token::Tuple(vec![
+                Token::Address(H160::from_low_u64_be(address.to_low_u64_be() + 100)),
+                Token::Uint(U256::from(18)),
+                Token::Address(H160::from_low_u64_be(address.to_low_u64_be() + 200)),
+                Token::Uint(U256::from(18)),
+                Token::Uint(U256::from(1_000_000)),
+                Token::Uint(U256::one() << 96),
+                Token::Int(U256::zero()),
+                Token::Int(U256::from(10)),
+                Token::Uint(U256::from(500)),
+                Token::Int(U256::zero()),
+            ]),
+        ])])))
+        .unwrap();
+    }
+
+    fn counting_middleware() -> (
+        Arc<Provider<CountingClient>>,
+        MockProvider,
+        Arc<AtomicUsize>,
+    ) {
+        let mock = MockProvider::new();
+        let requests = Arc::new(AtomicUsize::new(0));
+        let middleware = Arc::new(Provider::new(CountingClient {
+            mock: mock.clone(),
+            requests: requests.clone(),
+        }));
+
+        (middleware, mock, requests)
+    }
+
+    #[tokio::test]
+    async fn test_get_or_fetch_within_ttl() {
+        let (middleware, mock, requests) = counting_middleware();
+        let address = H160::from_low_u64_be(1);
+        push_pool_data(&mock, address);
+
+        let mut cache = PoolCache::new(10, Duration::from_secs(60));
+
+        let pool = cache
+            .get_or_fetch(address, middleware.clone())
+            .await
+            .unwrap();
+        let fetch_requests = requests.load(Ordering::SeqCst);
+        assert_eq!(fetch_requests, 1);
+
+        //The second lookup is served from the cache without any requests
+        assert_eq!(cache.get_or_fetch(address, middleware).await.unwrap(), pool);
+        assert_eq!(requests.load(Ordering::SeqCst), fetch_requests);
+    }
+
+    #[tokio::test]
+    async fn test_get_or_fetch_after_ttl() {
+        let (middleware, mock, requests) = counting_middleware();
+        let address = H160::from_low_u64_be(1);
+        push_pool_data(&mock, address);
+        push_pool_data(&mock, address);
+
+        let mut cache = PoolCache::new(10, Duration::ZERO);
+
+        cache
+            .get_or_fetch(address, middleware.clone())
+            .await
+            .unwrap();
+        cache.get_or_fetch(address, middleware).await.unwrap();
+
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_evicts_least_recently_used() {
+        let pool = |address: u64| UniswapV3Pool {
+            address: H160::from_low_u64_be(address),
+            ..Default::default()
+        };
+
+        let mut cache = PoolCache::new(2, Duration::from_secs(60));
+        cache.insert(pool(1));
+        cache.insert(pool(2));
+
+        //Using pool 1 leaves pool 2 as the least recently used
+        assert!(cache.get(H160::from_low_u64_be(1)).is_some());
+        cache.insert(pool(3));
+
+        assert_eq!(cache.len(), 2);
+        assert!(cache.get(H160::from_low_u64_be(1)).is_some());
+        assert!(cache.get(H160::from_low_u64_be(2)).is_none());
+        assert!(cache.get(H160::from_low_u64_be(3)).is_some());
+    }
+
+    #[tokio::test]
+    async fn test_quote_cache_within_block() {
+        let (middleware, mock, requests) = counting_middleware();
+        for _ in 0..2 {
+            mock.push::<Bytes, _>(mock_tick_data_response(
+                &[(true, -10, 0), (false, -887270, 0)],
+                7,
+            ))
+            .unwrap();
+        }
+
+        let pool = mock_pool();
+        let amount_in = U256::from(5_000_000_000_000_000_u128);
+        let mut quote_cache = QuoteCache::new();
+
+        let amount_out = quote_cache
+            .simulate_swap(&pool, pool.token_a, amount_in, 7, middleware.clone())
+            .await
+            .unwrap();
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+
+        //The identical quote in the same block is served from the cache without any requests
+        assert_eq!(
+            quote_cache
+                .simulate_swap(&pool, pool.token_a, amount_in, 7, middleware.clone())
+                .await
+                .unwrap(),
+            amount_out
+        );
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+        assert_eq!(quote_cache.len(), 1);
+
+        //A new block invalidates every quote from the previous one
+        assert_eq!(
+            quote_cache.get(pool.address, pool.token_a, amount_in, 8),
+            None
+        );
+        quote_cache
+            .simulate_swap(&pool, pool.token_a, amount_in, 8, middleware)
+            .await
+            .unwrap();
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+        assert_eq!(
+            quote_cache.get(pool.address, pool.token_a, amount_in, 7),
+            None
+        );
+
+        //Quotes for earlier blocks are not cached
+        quote_cache.insert(pool.address, pool.token_b, amount_in, 7, U256::one());
+        assert_eq!(quote_cache.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_shared_pool_fetches_tick_data_once() {
+        let (middleware, mock, requests) = counting_middleware();
+
+        //Responses are popped last in first out: the block number, then the tick data below and above the current tick
+        mock.push::<Bytes, _>(mock_tick_data_response(&[(false, 887270, 0)], 7))
+            .unwrap();
+        mock.push::<Bytes, _>(mock_tick_data_response(
+            &[(true, -10, 500_000_000_000_000_000), (false, -887270, 0)],
+            7,
+        ))
+        .unwrap();
+        mock.push(U64::from(7)).unwrap();
+
+        let shared_pool = Arc::new(SharedPool::new(mock_pool(), 150));
+        let token_in = shared_pool.pool().await.token_a;
+        let amount_in = U256::from(5_000_000_000_000_000_u128);
+
+        let handles = (0..8)
+            .map(|_| {
+                let shared_pool = shared_pool.clone();
+                let middleware = middleware.clone();
+                tokio::spawn(async move {
+                    shared_pool
+                        .simulate_swap(token_in, amount_in, middleware)
+                        .await
+                        .unwrap()
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let mut amounts_out = vec![];
+        for handle in handles {
+            amounts_out.push(handle.await.unwrap());
+        }
+
+        //Every task gets the same quote from a single fetch of the block number and the tick data in each direction
+        assert!(!amounts_out[0].is_zero());
+        assert!(amounts_out
+            .iter()
+            .all(|amount_out| *amount_out == amounts_out[0]));
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+
+        //Once invalidated with the synced pool the next quote refetches the tick data for the new state
+        let synced_pool = UniswapV3Pool {
+            liquidity: 2_000_000_000_000_000_000,
+            ..mock_pool()
+        };
+        shared_pool.invalidate(synced_pool).await;
+        assert_eq!(shared_pool.pool().await, synced_pool);
+
+        mock.push::<Bytes, _>(mock_tick_data_response(&[(false, 887270, 0)], 7))
+            .unwrap();
+        mock.push::<Bytes, _>(mock_tick_data_response(
+            &[(true, -10, 500_000_000_000_000_000), (false, -887270, 0)],
+            7,
+        ))
+        .unwrap();
+        mock.push(U64::from(8)).unwrap();
+
+        let amount_out = shared_pool
+            .simulate_swap(token_in, amount_in, middleware)
+            .await
+            .unwrap();
+        assert!(amount_out > amounts_out[0]);
+        assert_eq!(requests.load(Ordering::SeqCst), 6);
+    }
+
+    #[tokio::test]
+    async fn test_shared_pool_rejects_swap_past_tick_data() {
+        let (middleware, mock, _) = counting_middleware();
+
+        //The tick data below the current tick ends at a word boundary well before the min tick
+        mock.push::<Bytes, _>(mock_tick_data_response(&[(false, 887270, 0)], 7))
+            .unwrap();
+        mock.push::<Bytes, _>(mock_tick_data_response(
+            &[(true, -10, 500_000_000_000_000_000), (false, -2560, 0)],
+            7,
+        ))
+        .unwrap();
+        mock.push(U64::from(7)).unwrap();
+
+        let pool = mock_pool();
+        let shared_pool = SharedPool::new(pool, 150);
+
+        //A swap that stays above tick -2560 is quoted from the cached tick data
+        assert!(shared_pool
+            .simulate_swap(
+                pool.token_a,
+                U256::from(5_000_000_000_000_000_u128),
+                middleware.clone()
+            )
+            .await
+            .is_ok());
+
+        //A swap that crosses the whole window down to tick -2560 can not be quoted exactly
+        let result = shared_pool
+            .simulate_swap(
+                pool.token_a,
+                U256::from(1_000_000_000_000_000_000_u128),
+                middleware.clone(),
+            )
+            .await;
+        assert!(
+            matches!(result, Err(CFMMError::TickDataExceeded(address)) if address == pool.address)
+        );
+
+        //The tick data above the current tick runs to the max tick, so any swap up is within it
+        assert!(shared_pool
+            .simulate_swap(
+                pool.token_b,
+                U256::from(1_000_000_000_000_000_000_u128),
+                middleware
+            )
+            .await
+            .is_ok());
+    }
+}
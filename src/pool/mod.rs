@@ -2,7 +2,7 @@ use std::{cmp::Ordering, sync::Arc};
 
 use ethers::{
     providers::Middleware,
-    types::{Log, H160, U256},
+    types::{Log, H160, I256, U256},
 };
 
 use crate::{
@@ -10,7 +10,10 @@ use crate::{
     errors::{ArithmeticError, CFMMError},
 };
 
+pub mod cache;
 pub mod fixed_point_math;
+#[cfg(feature = "protobuf")]
+pub mod proto;
 pub mod uniswap_v2;
 pub mod uniswap_v3;
 use serde::{Deserialize, Serialize};
@@ -99,7 +102,7 @@ impl Pool {
     pub fn calculate_price(&self, base_token: H160) -> Result<f64, ArithmeticError> {
         match self {
             Pool::UniswapV2(pool) => pool.calculate_price(base_token),
-            Pool::UniswapV3(pool) => Ok(pool.calculate_price(base_token)),
+            Pool::UniswapV3(pool) => pool.try_calculate_price(base_token),
         }
     }
 
@@ -121,6 +124,22 @@ impl Pool {
         }
     }
 
+    //Returns (token_a, token_b)
+    pub fn tokens(&self) -> (H160, H160) {
+        match self {
+            Pool::UniswapV2(pool) => (pool.token_a, pool.token_b),
+            Pool::UniswapV3(pool) => (pool.token_a, pool.token_b),
+        }
+    }
+
+    //Returns the other token in the pool, or None if token is not in the pool
+    pub fn get_other_token(&self, token: H160) -> Option<H160> {
+        match self {
+            Pool::UniswapV2(pool) => pool.get_other_token(token),
+            Pool::UniswapV3(pool) => pool.get_other_token(token),
+        }
+    }
+
     pub async fn simulate_swap<M: Middleware>(
         &self,
         token_in: H160,
@@ -133,6 +152,24 @@ impl Pool {
         }
     }
 
+    //Returns the amount of token_in needed to receive exactly amount_out from the pool
+    pub async fn simulate_swap_exact_output<M: Middleware>(
+        &self,
+        token_in: H160,
+        amount_out: U256,
+        middleware: Arc<M>,
+    ) -> Result<U256, CFMMError<M>> {
+        match self {
+            Pool::UniswapV2(pool) => pool
+                .simulate_swap_exact_output(token_in, amount_out)
+                .ok_or(CFMMError::InsufficientLiquidity(pool.address)),
+            Pool::UniswapV3(pool) => {
+                pool.simulate_swap_exact_output(token_in, amount_out, middleware)
+                    .await
+            }
+        }
+    }
+
     pub async fn simulate_swap_mut<M: Middleware>(
         &mut self,
         token_in: H160,
@@ -157,6 +194,66 @@ pub fn convert_to_decimals(amount: U256, decimals: u8, target_decimals: u8) -> U
     }
 }
 
+//Formats a signed raw token amount as a human readable decimal string, e.g. -1500000000000000000 with 18 decimals is "-1.5"
+pub fn format_amount(amount: I256, decimals: u8) -> String {
+    let sign = if amount.is_negative() { "-" } else { "" };
+    format!(
+        "{sign}{}",
+        format_unsigned_amount(amount.unsigned_abs(), decimals)
+    )
+}
+
+pub fn format_amount_f64(amount: I256, decimals: u8) -> f64 {
+    format_amount(amount, decimals)
+        .parse()
+        .expect("Could not parse formatted amount")
+}
+
+fn format_unsigned_amount(amount: U256, decimals: u8) -> String {
+    let base = U256::exp10(decimals as usize);
+
+    let integer = amount / base;
+    let fraction = amount % base;
+
+    if fraction.is_zero() {
+        return integer.to_string();
+    }
+
+    let fraction = format!(
+        "{:0>width$}",
+        fraction.to_string(),
+        width = decimals as usize
+    );
+    format!("{integer}.{}", fraction.trim_end_matches('0'))
+}
+
+//Converts a human readable token amount into its raw amount, e.g. 100.0 with 6 decimals is 100000000.
+//Digits beyond the token's decimals are truncated. Returns zero if the amount is not a finite positive number.
+pub fn to_wei(human: f64, decimals: u8) -> U256 {
+    if !(human.is_finite() && human > 0.0) {
+        return U256::zero();
+    }
+
+    //The shortest representation that round trips avoids picking up the f64 error in the trailing digits
+    let human = human.to_string();
+    let (integer, fraction) = human.split_once('.').unwrap_or((&human, ""));
+    //Tokens without decimals have no fractional part, so anything after the decimal point is dropped
+    let fraction = if decimals == 0 {
+        String::new()
+    } else {
+        format!("{:0<width$.width$}", fraction, width = decimals as usize)
+    };
+
+    U256::from_dec_str(&format!("{integer}{fraction}")).unwrap_or(U256::MAX)
+}
+
+//Converts a raw token amount into a human readable amount, e.g. 100000000 with 6 decimals is 100.0
+pub fn from_wei(amount: U256, decimals: u8) -> f64 {
+    format_unsigned_amount(amount, decimals)
+        .parse()
+        .expect("Could not parse formatted amount")
+}
+
 pub fn convert_to_common_decimals(
     amount_a: U256,
     a_decimals: u8,
@@ -249,3 +346,84 @@ pub async fn simulate_route_mut<M: Middleware>(
 
     Ok(amount_out)
 }
+
+#[cfg(test)]
+mod tests {
+    use ethers::types::{H160, I256, U256};
+
+    use super::{
+        format_amount, format_amount_f64, from_wei, to_wei, Pool, UniswapV2Pool, UniswapV3Pool,
+    };
+
+    #[test]
+    fn test_format_amount() {
+        //-1.5 WETH
+        let amount = -I256::from_raw(U256::from(1_500_000_000_000_000_000_u128));
+        assert_eq!(format_amount(amount, 18), "-1.5");
+        assert_eq!(format_amount_f64(amount, 18), -1.5);
+
+        //1000.25 USDC
+        let amount = I256::from(1_000_250_000);
+        assert_eq!(format_amount(amount, 6), "1000.25");
+        assert_eq!(format_amount_f64(amount, 6), 1000.25);
+
+        assert_eq!(format_amount(I256::from(-1), 6), "-0.000001");
+        assert_eq!(format_amount(I256::from(42), 0), "42");
+        assert_eq!(format_amount(I256::zero(), 18), "0");
+    }
+
+    #[test]
+    fn test_to_wei_and_from_wei() {
+        //100 USDC
+        let amount = to_wei(100.0, 6);
+        assert_eq!(amount, U256::from(100_000_000));
+        assert_eq!(from_wei(amount, 6), 100.0);
+
+        //1.1 WETH
+        let amount = to_wei(1.1, 18);
+        assert_eq!(amount, U256::from(1_100_000_000_000_000_000_u128));
+        assert_eq!(from_wei(amount, 18), 1.1);
+
+        for (human, decimals) in [(0.000001, 6), (123456.789, 6), (0.5, 18), (2500.25, 18)] {
+            assert_eq!(from_wei(to_wei(human, decimals), decimals), human);
+        }
+
+        //Digits beyond the token's decimals are truncated
+        assert_eq!(to_wei(1.0000005, 6), U256::from(1_000_000));
+        assert_eq!(to_wei(-1.0, 18), U256::zero());
+        assert_eq!(from_wei(U256::zero(), 18), 0.0);
+
+        //Tokens with zero decimals
+        assert_eq!(to_wei(42.0, 0), U256::from(42));
+        assert_eq!(to_wei(42.9, 0), U256::from(42));
+        assert_eq!(to_wei(0.5, 0), U256::zero());
+        assert_eq!(from_wei(U256::from(42), 0), 42.0);
+        assert_eq!(from_wei(to_wei(1e15, 0), 0), 1e15);
+    }
+
+    #[test]
+    fn test_get_other_token() {
+        let token_a = H160::from_low_u64_be(1);
+        let token_b = H160::from_low_u64_be(2);
+        let unrelated_token = H160::from_low_u64_be(3);
+
+        let pools = [
+            Pool::UniswapV2(UniswapV2Pool {
+                token_a,
+                token_b,
+                ..Default::default()
+            }),
+            Pool::UniswapV3(UniswapV3Pool {
+                token_a,
+                token_b,
+                ..Default::default()
+            }),
+        ];
+
+        for pool in pools {
+            assert_eq!(pool.get_other_token(token_a), Some(token_b));
+            assert_eq!(pool.get_other_token(token_b), Some(token_a));
+            assert_eq!(pool.get_other_token(unrelated_token), None);
+        }
+    }
+}
@@ -0,0 +1,224 @@
+use std::time::{Duration, UNIX_EPOCH};
+
+use ethers::types::{H160, U256};
+use prost::Message;
+
+use crate::errors::ProtoError;
+
+use super::UniswapV3Pool;
+
+//Compact binary representation of a UniswapV3Pool, equivalent to the following .proto definition:
+//
+//message UniswapV3Pool {
+//    bytes address = 1;
+//    bytes token_a = 2;
+//    uint32 token_a_decimals = 3;
+//    bytes token_b = 4;
+//    uint32 token_b_decimals = 5;
+//    bytes liquidity = 6;
+//    bytes sqrt_price = 7;
+//    uint32 fee = 8;
+//    sint32 tick = 9;
+//    sint32 tick_spacing = 10;
+//    bytes liquidity_net = 11;
+//    uint64 last_synced_block = 12;
+//    optional uint64 created_at_block = 13;
+//    optional uint64 synced_at = 14;
+//}
+//
+//Integers wider than 64 bits are stored as big endian bytes with the leading zeros trimmed,
+//with liquidity_net zigzag encoded first so that small negative values stay short. synced_at is in nanoseconds since the unix epoch.
+#[derive(Clone, PartialEq, Message)]
+pub struct UniswapV3PoolProto {
+    #[prost(bytes = "vec", tag = "1")]
+    pub address: Vec<u8>,
+    #[prost(bytes = "vec", tag = "2")]
+    pub token_a: Vec<u8>,
+    #[prost(uint32, tag = "3")]
+    pub token_a_decimals: u32,
+    #[prost(bytes = "vec", tag = "4")]
+    pub token_b: Vec<u8>,
+    #[prost(uint32, tag = "5")]
+    pub token_b_decimals: u32,
+    #[prost(bytes = "vec", tag = "6")]
+    pub liquidity: Vec<u8>,
+    #[prost(bytes = "vec", tag = "7")]
+    pub sqrt_price: Vec<u8>,
+    #[prost(uint32, tag = "8")]
+    pub fee: u32,
+    #[prost(sint32, tag = "9")]
+    pub tick: i32,
+    #[prost(sint32, tag = "10")]
+    pub tick_spacing: i32,
+    #[prost(bytes = "vec", tag = "11")]
+    pub liquidity_net: Vec<u8>,
+    #[prost(uint64, tag = "12")]
+    pub last_synced_block: u64,
+    #[prost(uint64, optional, tag = "13")]
+    pub created_at_block: Option<u64>,
+    #[prost(uint64, optional, tag = "14")]
+    pub synced_at: Option<u64>,
+}
+
+impl UniswapV3Pool {
+    //Serializes the pool to a protobuf message, see UniswapV3PoolProto for the schema
+    pub fn to_proto(&self) -> Vec<u8> {
+        UniswapV3PoolProto::from(self).encode_to_vec()
+    }
+
+    //Deserializes a pool from a protobuf message produced by to_proto
+    pub fn from_proto(bytes: &[u8]) -> Result<Self, ProtoError> {
+        UniswapV3Pool::try_from(UniswapV3PoolProto::decode(bytes)?)
+    }
+}
+
+impl From<&UniswapV3Pool> for UniswapV3PoolProto {
+    fn from(pool: &UniswapV3Pool) -> Self {
+        let mut sqrt_price = [0_u8; 32];
+        pool.sqrt_price.to_big_endian(&mut sqrt_price);
+
+        //Zigzag encoding maps signed values to unsigned ones with the same magnitude, so trimming leading zeros works for negative values too
+        let liquidity_net = ((pool.liquidity_net << 1) ^ (pool.liquidity_net >> 127)) as u128;
+
+        UniswapV3PoolProto {
+            address: pool.address.as_bytes().to_vec(),
+            token_a: pool.token_a.as_bytes().to_vec(),
+            token_a_decimals: pool.token_a_decimals as u32,
+            token_b: pool.token_b.as_bytes().to_vec(),
+            token_b_decimals: pool.token_b_decimals as u32,
+            liquidity: trim_leading_zeros(&pool.liquidity.to_be_bytes()),
+            sqrt_price: trim_leading_zeros(&sqrt_price),
+            fee: pool.fee,
+            tick: pool.tick,
+            tick_spacing: pool.tick_spacing,
+            liquidity_net: trim_leading_zeros(&liquidity_net.to_be_bytes()),
+            last_synced_block: pool.last_synced_block,
+            created_at_block: pool.created_at_block,
+            synced_at: pool.synced_at.map(|synced_at| {
+                synced_at
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_nanos() as u64
+            }),
+        }
+    }
+}
+
+impl TryFrom<UniswapV3PoolProto> for UniswapV3Pool {
+    type Error = ProtoError;
+
+    fn try_from(proto: UniswapV3PoolProto) -> Result<Self, Self::Error> {
+        let liquidity_net = u128_from_bytes("liquidity_net", &proto.liquidity_net)?;
+
+        Ok(UniswapV3Pool {
+            address: h160_from_bytes("address", &proto.address)?,
+            token_a: h160_from_bytes("token_a", &proto.token_a)?,
+            token_a_decimals: u8_from_u32("token_a_decimals", proto.token_a_decimals)?,
+            token_b: h160_from_bytes("token_b", &proto.token_b)?,
+            token_b_decimals: u8_from_u32("token_b_decimals", proto.token_b_decimals)?,
+            liquidity: u128_from_bytes("liquidity", &proto.liquidity)?,
+            sqrt_price: u256_from_bytes("sqrt_price", &proto.sqrt_price)?,
+            fee: proto.fee,
+            tick: proto.tick,
+            tick_spacing: proto.tick_spacing,
+            liquidity_net: (liquidity_net >> 1) as i128 ^ -((liquidity_net & 1) as i128),
+            last_synced_block: proto.last_synced_block,
+            created_at_block: proto.created_at_block,
+            synced_at: proto
+                .synced_at
+                .map(|synced_at| UNIX_EPOCH + Duration::from_nanos(synced_at)),
+        })
+    }
+}
+
+fn trim_leading_zeros(bytes: &[u8]) -> Vec<u8> {
+    let first_non_zero = bytes.iter().position(|b| *b != 0).unwrap_or(bytes.len());
+    bytes[first_non_zero..].to_vec()
+}
+
+fn h160_from_bytes(field: &'static str, bytes: &[u8]) -> Result<H160, ProtoError> {
+    if bytes.len() > 20 {
+        return Err(ProtoError::FieldOutOfRange(field));
+    }
+
+    //Left pad so that an empty field, which protobuf uses for the default value, decodes to the zero address
+    let mut address = [0_u8; 20];
+    address[20 - bytes.len()..].copy_from_slice(bytes);
+    Ok(H160(address))
+}
+
+fn u128_from_bytes(field: &'static str, bytes: &[u8]) -> Result<u128, ProtoError> {
+    if bytes.len() > 16 {
+        return Err(ProtoError::FieldOutOfRange(field));
+    }
+
+    let mut value = [0_u8; 16];
+    value[16 - bytes.len()..].copy_from_slice(bytes);
+    Ok(u128::from_be_bytes(value))
+}
+
+fn u256_from_bytes(field: &'static str, bytes: &[u8]) -> Result<U256, ProtoError> {
+    if bytes.len() > 32 {
+        return Err(ProtoError::FieldOutOfRange(field));
+    }
+
+    Ok(U256::from_big_endian(bytes))
+}
+
+fn u8_from_u32(field: &'static str, value: u32) -> Result<u8, ProtoError> {
+    u8::try_from(value).map_err(|_| ProtoError::FieldOutOfRange(field))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use ethers::types::{H160, U256};
+
+    use crate::{errors::ProtoError, pool::UniswapV3Pool};
+
+    #[test]
+    fn test_proto_round_trip() {
+        let pool = UniswapV3Pool {
+            address: H160::from_low_u64_be(1),
+            token_a: H160::repeat_byte(0xaa),
+            token_a_decimals: 6,
+            token_b: H160::repeat_byte(0xbb),
+            token_b_decimals: 18,
+            liquidity: u128::MAX - 1,
+            sqrt_price: U256::MAX - 1,
+            fee: 500,
+            tick: -887272,
+            tick_spacing: 10,
+            liquidity_net: i128::MIN,
+            last_synced_block: 17_000_000,
+            created_at_block: Some(12376729),
+            synced_at: Some(UNIX_EPOCH + Duration::from_nanos(1_690_000_000_123_456_789)),
+        };
+
+        let bytes = pool.to_proto();
+        assert_eq!(UniswapV3Pool::from_proto(&bytes).unwrap(), pool);
+
+        //The binary message is smaller than the equivalent JSON
+        assert!(bytes.len() < serde_json::to_vec(&pool).unwrap().len());
+
+        //Default and negative values, which encode to empty or zigzagged fields, round trip as well
+        let pool = UniswapV3Pool {
+            liquidity_net: -1,
+            ..Default::default()
+        };
+        assert_eq!(UniswapV3Pool::from_proto(&pool.to_proto()).unwrap(), pool);
+    }
+
+    #[test]
+    fn test_from_proto_rejects_oversized_fields() {
+        let mut proto = super::UniswapV3PoolProto::from(&UniswapV3Pool::default());
+        proto.address = vec![1; 21];
+
+        let result = UniswapV3Pool::try_from(proto);
+        assert!(matches!(
+            result,
+            Err(ProtoError::FieldOutOfRange("address"))
+        ));
+    }
+}
@@ -110,6 +110,10 @@ impl UniswapV2Pool {
         self.fee
     }
 
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(pool = ?self.address))
+    )]
     pub async fn get_pool_data<M: Middleware>(
         &mut self,
         middleware: Arc<M>,
@@ -224,7 +228,7 @@ impl UniswapV2Pool {
         if base_token == self.token_a {
             Ok(fixed_point_math::div_uu(r_1, r_0)?)
         } else {
-            Ok(fixed_point_math::div_uu(r_0, r_1))?
+            Ok(fixed_point_math::div_uu(r_0, r_1)?)
         }
     }
 
@@ -232,6 +236,17 @@ impl UniswapV2Pool {
         self.address
     }
 
+    //Returns the other token in the pool, or None if token is not in the pool
+    pub fn get_other_token(&self, token: H160) -> Option<H160> {
+        if token == self.token_a {
+            Some(self.token_b)
+        } else if token == self.token_b {
+            Some(self.token_a)
+        } else {
+            None
+        }
+    }
+
     pub fn update_pool_from_sync_log(&mut self, sync_log: &Log) {
         (self.reserve_0, self.reserve_1) = self.decode_sync_log(sync_log);
     }
@@ -315,6 +330,44 @@ impl UniswapV2Pool {
         numerator / denominator
     }
 
+    //Returns the amount of token_in needed to receive exactly amount_out, or None if the pool does not hold enough of the other token
+    pub fn simulate_swap_exact_output(&self, token_in: H160, amount_out: U256) -> Option<U256> {
+        if self.token_a == token_in {
+            self.get_amount_in(
+                amount_out,
+                U256::from(self.reserve_0),
+                U256::from(self.reserve_1),
+            )
+        } else {
+            self.get_amount_in(
+                amount_out,
+                U256::from(self.reserve_1),
+                U256::from(self.reserve_0),
+            )
+        }
+    }
+
+    //Mirrors getAmountIn from the UniswapV2Library, rounding the amount in up
+    pub fn get_amount_in(
+        &self,
+        amount_out: U256,
+        reserve_in: U256,
+        reserve_out: U256,
+    ) -> Option<U256> {
+        if amount_out.is_zero() {
+            return Some(U256::zero());
+        }
+
+        if reserve_in.is_zero() || amount_out >= reserve_out {
+            return None;
+        }
+
+        let numerator = reserve_in * amount_out * U256::from(1000);
+        let denominator = (reserve_out - amount_out) * U256::from(997);
+
+        Some(numerator / denominator + 1)
+    }
+
     pub fn swap_calldata(
         &self,
         amount_0_out: U256,
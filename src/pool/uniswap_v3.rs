@@ -1,28 +1,65 @@
-use std::sync::Arc;
+use std::{
+    collections::VecDeque,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
 
 use ethers::{
-    abi::{decode, ethabi::Bytes, ParamType, Token},
+    abi::{decode, ethabi::Bytes, ParamType, Token, Tokenizable},
     providers::Middleware,
-    types::{Log, H160, H256, I256, U256, U64},
+    types::{BlockNumber, Filter, Log, ValueOrArray, H160, H256, I256, U256, U512, U64},
+};
+use futures::{
+    future,
+    stream::{self, Stream, StreamExt},
 };
 use num_bigfloat::BigFloat;
+use uniswap_v3_math::error::UniswapV3MathError;
 
 use crate::{
-    abi, batch_requests,
-    errors::{ArithmeticError, CFMMError},
+    abi,
+    batch_requests::{self, BatchConfig},
+    dex::uniswap_v3::POOL_CREATED_EVENT_SIGNATURE,
+    errors::{ArithmeticError, CFMMError, PoolError},
+    pool::{fixed_point_math, from_wei, Pool},
+    registry::TokenRegistry,
 };
 use serde::{Deserialize, Serialize};
 
+pub use crate::abi::{ExactInputParams, ExactInputSingleParams};
+pub use crate::batch_requests::uniswap_v3::UniswapV3TickData as TickData;
+
 pub const MIN_SQRT_RATIO: U256 = U256([4295128739, 0, 0, 0]);
 pub const MAX_SQRT_RATIO: U256 = U256([6743328256752651558, 17280870778742802505, 4294805859, 0]);
 pub const SWAP_EVENT_SIGNATURE: H256 = H256([
     196, 32, 121, 249, 74, 99, 80, 215, 230, 35, 95, 41, 23, 73, 36, 249, 40, 204, 42, 200, 24,
     235, 100, 254, 216, 0, 78, 17, 95, 188, 202, 103,
 ]);
+pub const INITIALIZE_EVENT_SIGNATURE: H256 = H256([
+    152, 99, 96, 54, 203, 102, 169, 193, 154, 55, 67, 94, 252, 30, 144, 20, 33, 144, 33, 78, 138,
+    190, 184, 33, 189, 186, 63, 41, 144, 221, 76, 149,
+]);
+//Hash of the UniswapV3Pool init code, used by the factory to CREATE2 deploy pools
+pub const POOL_INIT_CODE_HASH: H256 = H256([
+    227, 79, 25, 155, 25, 178, 180, 244, 127, 104, 68, 38, 25, 213, 85, 82, 125, 36, 79, 120, 163,
+    41, 126, 168, 147, 37, 248, 67, 248, 123, 139, 84,
+]);
 
 pub const U256_TWO: U256 = U256([2, 0, 0, 0]);
 pub const Q128: U256 = U256([0, 0, 1, 0]);
 pub const Q224: U256 = U256([0, 0, 0, 4294967296]);
+//Maximum number of requests in flight when scanning the tick bitmap
+const MAX_CONCURRENT_REQUESTS: usize = 32;
+//Max number of pools populated by a single pool data batch request
+const POOL_DATA_BATCH_SIZE: usize = 76;
+//How often price_stream checks for a new block
+const PRICE_STREAM_POLL_INTERVAL: Duration = Duration::from_secs(1);
+//When price_stream falls further behind than this, the pool is resynced instead of replaying the swap logs
+const PRICE_STREAM_MAX_BLOCK_RANGE: u64 = 100;
+//Default thresholds of looks_like_stable_pair. 50 ticks is a price within about 0.5% of 1 after adjusting for decimals,
+//and 0.05% is the highest fee tier stable pairs are usually deployed on.
+pub const STABLE_PAIR_MAX_TICK_DISTANCE: u32 = 50;
+pub const STABLE_PAIR_MAX_FEE: u32 = 500;
 #[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, Hash)]
 pub struct UniswapV3Pool {
     pub address: H160,
@@ -35,7 +72,17 @@ pub struct UniswapV3Pool {
     pub fee: u32,
     pub tick: i32,
     pub tick_spacing: i32,
+    //liquidityNet of the tick at `tick` as stored by the pool contract, zero when that tick is not initialized.
+    //Syncing, updating from a swap log and simulate_swap_mut all keep this invariant.
     pub liquidity_net: i128,
+    #[serde(default)]
+    pub last_synced_block: u64,
+    //Block the pool was created at, when known. See fetch_creation_block
+    #[serde(default)]
+    pub created_at_block: Option<u64>,
+    //When the pool data was last fetched by sync_pool or get_pool_data. See age
+    #[serde(default)]
+    pub synced_at: Option<SystemTime>,
 }
 
 impl UniswapV3Pool {
@@ -65,7 +112,44 @@ impl UniswapV3Pool {
             tick,
             tick_spacing,
             liquidity_net,
+            last_synced_block: 0,
+            created_at_block: None,
+            synced_at: None,
+        }
+    }
+
+    //Same as new, but rejects a pool whose token_a and token_b are identical
+    #[allow(clippy::too_many_arguments)]
+    pub fn try_new(
+        address: H160,
+        token_a: H160,
+        token_a_decimals: u8,
+        token_b: H160,
+        token_b_decimals: u8,
+        fee: u32,
+        liquidity: u128,
+        sqrt_price: U256,
+        tick: i32,
+        tick_spacing: i32,
+        liquidity_net: i128,
+    ) -> Result<UniswapV3Pool, PoolError> {
+        if token_a == token_b {
+            return Err(PoolError::IdenticalTokens(address));
         }
+
+        Ok(UniswapV3Pool::new(
+            address,
+            token_a,
+            token_a_decimals,
+            token_b,
+            token_b_decimals,
+            fee,
+            liquidity,
+            sqrt_price,
+            tick,
+            tick_spacing,
+            liquidity_net,
+        ))
     }
 
     //Creates a new instance of the pool from the pair address
@@ -73,6 +157,8 @@ impl UniswapV3Pool {
         pair_address: H160,
         middleware: Arc<M>,
     ) -> Result<Self, CFMMError<M>> {
+        validate_address(pair_address)?;
+
         let mut pool = UniswapV3Pool {
             address: pair_address,
             token_a: H160::zero(),
@@ -85,6 +171,9 @@ impl UniswapV3Pool {
             tick_spacing: 0,
             fee: 0,
             liquidity_net: 0,
+            last_synced_block: 0,
+            created_at_block: None,
+            synced_at: None,
         };
 
         pool.get_pool_data(middleware.clone()).await?;
@@ -124,6 +213,9 @@ impl UniswapV3Pool {
             tick_spacing: 0,
             tick: 0,
             liquidity_net: 0,
+            last_synced_block: 0,
+            created_at_block: log.block_number.map(|block_number| block_number.as_u64()),
+            synced_at: None,
         })
     }
 
@@ -131,16 +223,154 @@ impl UniswapV3Pool {
         self.fee
     }
 
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(pool = ?self.address))
+    )]
     pub async fn get_pool_data<M: Middleware>(
         &mut self,
         middleware: Arc<M>,
     ) -> Result<(), CFMMError<M>> {
-        batch_requests::uniswap_v3::get_v3_pool_data_batch_request(self, middleware.clone())
-            .await?;
+        self.get_pool_data_with_config(&BatchConfig::default(), middleware)
+            .await
+    }
+
+    //Same as get_pool_data, but fetches the pool data with the batch contract override in config if there is one
+    pub async fn get_pool_data_with_config<M: Middleware>(
+        &mut self,
+        config: &BatchConfig,
+        middleware: Arc<M>,
+    ) -> Result<(), CFMMError<M>> {
+        batch_requests::uniswap_v3::get_v3_pool_data_batch_request_with_config(
+            self,
+            config,
+            middleware.clone(),
+        )
+        .await?;
+
+        if self.data_is_populated() && self.token_a == self.token_b {
+            return Err(CFMMError::IdenticalTokens(self.address));
+        }
+
+        self.synced_at = Some(SystemTime::now());
+
+        Ok(())
+    }
+
+    //Opt-in alternative to get_pool_data for deployments where the pool's fee() reverts.
+    //If the batch request fails, the pool data is fetched one call at a time and the fee is looked up through the factory's getPool
+    //for each fee tier when a factory is provided, falling back to inferring the fee from the tick spacing.
+    pub async fn get_pool_data_with_fee_fallback<M: Middleware>(
+        &mut self,
+        factory: Option<H160>,
+        middleware: Arc<M>,
+    ) -> Result<(), CFMMError<M>> {
+        if self.get_pool_data(middleware.clone()).await.is_ok() && self.data_is_populated() {
+            return Ok(());
+        }
+
+        //get_pool_data falls back to individual calls when the batch request reverts, which leaves
+        //everything but the fee populated when fee() reverts
+        if !self.data_is_populated() {
+            self.get_pool_data_without_fee(middleware.clone()).await?;
+
+            if let Ok(fee) = self.get_fee(middleware.clone()).await {
+                self.fee = fee;
+                return Ok(());
+            }
+        }
+
+        self.fee = self.infer_fee(factory, middleware).await?;
+
+        Ok(())
+    }
+
+    //Populates the pool data with one call per field rather than the batch request contract.
+    //get_pool_data falls back to this when the deployless batch request reverts, e.g. on chains with a different EVM version.
+    pub async fn get_pool_data_individually<M: Middleware>(
+        &mut self,
+        middleware: Arc<M>,
+    ) -> Result<(), CFMMError<M>> {
+        self.get_pool_data_without_fee(middleware.clone()).await?;
+        self.fee = self.get_fee(middleware).await?;
+
+        Ok(())
+    }
+
+    async fn get_pool_data_without_fee<M: Middleware>(
+        &mut self,
+        middleware: Arc<M>,
+    ) -> Result<(), CFMMError<M>> {
+        self.token_a = self.get_token_0(middleware.clone()).await?;
+        self.token_b = self.get_token_1(middleware.clone()).await?;
+
+        if self.token_a == self.token_b {
+            return Err(CFMMError::IdenticalTokens(self.address));
+        }
+
+        (self.token_a_decimals, self.token_b_decimals) =
+            self.get_token_decimals(middleware.clone()).await?;
+        (self.sqrt_price, self.tick, ..) = self.get_slot_0(middleware.clone()).await?;
+        self.liquidity = self.get_liquidity(middleware.clone()).await?;
+        self.tick_spacing = self.get_tick_spacing(middleware.clone()).await?;
+        self.liquidity_net = self.get_liquidity_net(self.tick, middleware).await?;
 
         Ok(())
     }
 
+    async fn infer_fee<M: Middleware>(
+        &self,
+        factory: Option<H160>,
+        middleware: Arc<M>,
+    ) -> Result<u32, CFMMError<M>> {
+        if let Some(factory) = factory {
+            let factory = abi::IUniswapV3Factory::new(factory, middleware);
+
+            for fee_tier in FeeTier::CANONICAL {
+                let pool = factory
+                    .get_pool(self.token_a, self.token_b, fee_tier.to_u32())
+                    .call()
+                    .await?;
+
+                if pool == self.address {
+                    return Ok(fee_tier.to_u32());
+                }
+            }
+        }
+
+        FeeTier::from_tick_spacing(self.tick_spacing)
+            .map(FeeTier::to_u32)
+            .ok_or(CFMMError::PoolDataError)
+    }
+
+    pub fn fee_tier(&self) -> FeeTier {
+        FeeTier::from_u32(self.fee)
+    }
+
+    //Guesses whether the pool pairs two stablecoins, which routers can use to prefer tighter spread pools. See looks_like_stable_pair_within.
+    pub fn looks_like_stable_pair(&self) -> bool {
+        self.looks_like_stable_pair_within(STABLE_PAIR_MAX_TICK_DISTANCE, STABLE_PAIR_MAX_FEE)
+    }
+
+    //Returns true if the decimal adjusted price is within max_tick_distance ticks of 1 and the fee is at most max_fee.
+    //This is only a heuristic, pairs of tokens pegged to the same asset such as WETH/stETH also look stable.
+    pub fn looks_like_stable_pair_within(&self, max_tick_distance: u32, max_fee: u32) -> bool {
+        if self.fee > max_fee {
+            return false;
+        }
+
+        match self.try_calculate_price(self.token_a) {
+            Ok(price) => (price.ln() / 1.0001_f64.ln()).abs() <= max_tick_distance as f64,
+            Err(_) => false,
+        }
+    }
+
+    //Returns true when all of the pool's liquidity is out of range of the current tick, so any swap immediately moves to the next
+    //initialized tick. Routers should deprioritize these pools.
+    pub fn is_active_liquidity_zero(&self) -> bool {
+        self.liquidity == 0
+    }
+
     pub fn data_is_populated(&self) -> bool {
         !(self.token_a.is_zero() || self.token_b.is_zero())
     }
@@ -206,6 +436,122 @@ impl UniswapV3Pool {
         Ok(tick_info.1)
     }
 
+    //Returns the liquidity net of the tick at block_number, or at the latest block if block_number is None
+    pub async fn get_liquidity_net_at_block<M: Middleware>(
+        &self,
+        tick: i32,
+        block_number: Option<U64>,
+        middleware: Arc<M>,
+    ) -> Result<i128, CFMMError<M>> {
+        let v3_pool = abi::IUniswapV3Pool::new(self.address, middleware);
+
+        let tick_info = match block_number {
+            Some(block_number) => v3_pool.ticks(tick).block(block_number).call().await?,
+            None => v3_pool.ticks(tick).call().await?,
+        };
+
+        Ok(tick_info.1)
+    }
+
+    //Returns the fee growth per unit of liquidity of token_0 and token_1 inside tick_lower..tick_upper, as the pool contract computes it
+    //for positions. The values are Q128.128 and are expected to wrap around, so only the difference between two readings is meaningful.
+    pub async fn fee_growth_inside<M: Middleware>(
+        &self,
+        tick_lower: i32,
+        tick_upper: i32,
+        middleware: Arc<M>,
+    ) -> Result<(U256, U256), CFMMError<M>> {
+        let v3_pool = abi::IUniswapV3Pool::new(self.address, middleware.clone());
+        let fee_growth_global_0 = v3_pool.fee_growth_global_0x128().call().await?;
+        let fee_growth_global_1 = v3_pool.fee_growth_global_1x128().call().await?;
+        let lower = self.get_tick_info(tick_lower, middleware.clone()).await?;
+        let upper = self.get_tick_info(tick_upper, middleware).await?;
+
+        let fee_growth_inside =
+            |fee_growth_global: U256, lower_outside: U256, upper_outside: U256| {
+                let fee_growth_below = if self.tick >= tick_lower {
+                    lower_outside
+                } else {
+                    fee_growth_global.overflowing_sub(lower_outside).0
+                };
+                let fee_growth_above = if self.tick < tick_upper {
+                    upper_outside
+                } else {
+                    fee_growth_global.overflowing_sub(upper_outside).0
+                };
+
+                fee_growth_global
+                    .overflowing_sub(fee_growth_below)
+                    .0
+                    .overflowing_sub(fee_growth_above)
+                    .0
+            };
+
+        Ok((
+            fee_growth_inside(fee_growth_global_0, lower.2, upper.2),
+            fee_growth_inside(fee_growth_global_1, lower.3, upper.3),
+        ))
+    }
+
+    //Returns the fees of token_0 and token_1 earned by a position with liquidity in tick_lower..tick_upper since the position last
+    //recorded last_fee_growth_inside, i.e. its feeGrowthInside0LastX128 and feeGrowthInside1LastX128
+    pub async fn calculate_position_fees<M: Middleware>(
+        &self,
+        tick_lower: i32,
+        tick_upper: i32,
+        liquidity: u128,
+        last_fee_growth_inside: (U256, U256),
+        middleware: Arc<M>,
+    ) -> Result<(U256, U256), CFMMError<M>> {
+        let (fee_growth_inside_0, fee_growth_inside_1) = self
+            .fee_growth_inside(tick_lower, tick_upper, middleware)
+            .await?;
+
+        Ok((
+            uniswap_v3_math::full_math::mul_div(
+                fee_growth_inside_0
+                    .overflowing_sub(last_fee_growth_inside.0)
+                    .0,
+                U256::from(liquidity),
+                Q128,
+            )?,
+            uniswap_v3_math::full_math::mul_div(
+                fee_growth_inside_1
+                    .overflowing_sub(last_fee_growth_inside.1)
+                    .0,
+                U256::from(liquidity),
+                Q128,
+            )?,
+        ))
+    }
+
+    //Returns the amounts of token_0 and token_1 a position could collect right now, which is the fees it earned since last_fee_growth_inside
+    //on top of the tokens_owed already accounted to the position, e.g. as returned by the position manager's positions(tokenId).
+    pub async fn estimate_collectible<M: Middleware>(
+        &self,
+        tick_lower: i32,
+        tick_upper: i32,
+        liquidity: u128,
+        last_fee_growth_inside: (U256, U256),
+        tokens_owed: (u128, u128),
+        middleware: Arc<M>,
+    ) -> Result<(U256, U256), CFMMError<M>> {
+        let (fees_0, fees_1) = self
+            .calculate_position_fees(
+                tick_lower,
+                tick_upper,
+                liquidity,
+                last_fee_growth_inside,
+                middleware,
+            )
+            .await?;
+
+        Ok((
+            fees_0 + U256::from(tokens_owed.0),
+            fees_1 + U256::from(tokens_owed.1),
+        ))
+    }
+
     pub async fn get_initialized<M: Middleware>(
         &self,
         tick: i32,
@@ -223,6 +569,22 @@ impl UniswapV3Pool {
         Ok(v3_pool.slot_0().call().await?)
     }
 
+    //Returns slot0's feeProtocol, where the lower 4 bits hold the protocol fee denominator for token_0 and the upper 4 bits for token_1
+    pub async fn get_fee_protocol<M: Middleware>(
+        &self,
+        middleware: Arc<M>,
+    ) -> Result<u8, CFMMError<M>> {
+        let (.., fee_protocol, _) = self.get_slot_0(middleware).await?;
+        Ok(fee_protocol)
+    }
+
+    //Returns true if the pool is locked, which is only the case while a swap, mint or burn is executing, e.g. when read from within
+    //a flash or swap callback. Reads still succeed while locked, but the state is mid-update, so simulations seeded from it may be stale.
+    pub async fn is_locked<M: Middleware>(&self, middleware: Arc<M>) -> Result<bool, CFMMError<M>> {
+        let (.., unlocked) = self.get_slot_0(middleware).await?;
+        Ok(!unlocked)
+    }
+
     pub async fn get_liquidity<M: Middleware>(
         &self,
         middleware: Arc<M>,
@@ -242,47 +604,159 @@ impl UniswapV3Pool {
         &mut self,
         middleware: Arc<M>,
     ) -> Result<(), CFMMError<M>> {
+        let current_block = middleware
+            .get_block_number()
+            .await
+            .map_err(CFMMError::MiddlewareError)?;
+
         batch_requests::uniswap_v3::sync_v3_pool_batch_request(self, middleware.clone()).await?;
+        self.last_synced_block = current_block.as_u64();
+        self.synced_at = Some(SystemTime::now());
+
         Ok(())
     }
 
+    //Returns a copy of the pool with its sqrt price, tick, liquidity and liquidity net read at block_number, leaving the pool untouched.
+    //Requires an archive node for blocks outside of the node's state history.
+    pub async fn at_block<M: Middleware>(
+        &self,
+        block_number: U64,
+        middleware: Arc<M>,
+    ) -> Result<UniswapV3Pool, CFMMError<M>> {
+        let mut pool = *self;
+
+        batch_requests::uniswap_v3::sync_v3_pool_batch_request_at_block(
+            &mut pool,
+            block_number,
+            middleware,
+        )
+        .await?;
+        pool.last_synced_block = block_number.as_u64();
+
+        Ok(pool)
+    }
+
+    //Fetches the same state as sync_pool, but returns only the fields that changed instead of updating the pool.
+    //Applying the delta with apply_delta leaves the pool in the same state as sync_pool would.
+    pub async fn sync_pool_diff<M: Middleware>(
+        &self,
+        middleware: Arc<M>,
+    ) -> Result<PoolDelta, CFMMError<M>> {
+        let mut synced_pool = *self;
+        synced_pool.sync_pool(middleware).await?;
+
+        Ok(PoolDelta {
+            sqrt_price: (synced_pool.sqrt_price != self.sqrt_price)
+                .then_some(synced_pool.sqrt_price),
+            tick: (synced_pool.tick != self.tick).then_some(synced_pool.tick),
+            liquidity: (synced_pool.liquidity != self.liquidity).then_some(synced_pool.liquidity),
+            liquidity_net: (synced_pool.liquidity_net != self.liquidity_net)
+                .then_some(synced_pool.liquidity_net),
+            block_number: synced_pool.last_synced_block,
+            synced_at: synced_pool.synced_at,
+        })
+    }
+
+    //Updates the dynamic fields of the pool that are set in the delta
+    pub fn apply_delta(&mut self, delta: PoolDelta) {
+        if let Some(sqrt_price) = delta.sqrt_price {
+            self.sqrt_price = sqrt_price;
+        }
+        if let Some(tick) = delta.tick {
+            self.tick = tick;
+        }
+        if let Some(liquidity) = delta.liquidity {
+            self.liquidity = liquidity;
+        }
+        if let Some(liquidity_net) = delta.liquidity_net {
+            self.liquidity_net = liquidity_net;
+        }
+        self.last_synced_block = delta.block_number;
+        self.synced_at = delta.synced_at;
+    }
+
+    //Returns how long ago the pool data was fetched, or None if it never was. A clock that moved backwards since gives an age of zero.
+    pub fn age(&self) -> Option<Duration> {
+        self.synced_at
+            .map(|synced_at| synced_at.elapsed().unwrap_or_default())
+    }
+
+    //Compares this snapshot of the pool with a later snapshot other, e.g. to alert on large price moves.
+    //Returns None if the snapshots are of different pools or if the price of either snapshot can not be calculated.
+    pub fn diff(&self, other: &UniswapV3Pool) -> Option<PoolDiff> {
+        if self.address != other.address {
+            return None;
+        }
+
+        let price = self.try_calculate_price(self.token_a).ok()?;
+        let other_price = other.try_calculate_price(self.token_a).ok()?;
+
+        Some(PoolDiff {
+            price_change_pct: (other_price / price - 1.0) * 100.0,
+            tick_delta: other.tick - self.tick,
+            liquidity_delta: other.liquidity.wrapping_sub(self.liquidity) as i128,
+        })
+    }
+
+    //A log with removed set was dropped from the chain in a reorg. Applying it would leave the pool in a state that never happened,
+    //and the swap it undoes is unknown, so the pool is resynced instead.
     pub async fn update_pool_from_swap_log<M: Middleware>(
         &mut self,
         swap_log: &Log,
         middleware: Arc<M>,
     ) -> Result<(), CFMMError<M>> {
-        (_, _, self.sqrt_price, self.liquidity, self.tick) = self.decode_swap_log(swap_log);
+        if swap_log.removed == Some(true) {
+            #[cfg(feature = "tracing")]
+            tracing::debug!(pool = ?self.address, "swap log was removed in a reorg, resyncing");
 
-        self.liquidity_net = self.get_liquidity_net(self.tick, middleware).await?;
+            return self.sync_pool(middleware).await;
+        }
+
+        (_, _, self.sqrt_price, self.liquidity, self.tick) = self.decode_swap_log(swap_log)?;
+
+        //The log may be from an older block, so the liquidity net is read at the same block to keep the pool state consistent
+        self.liquidity_net = self
+            .get_liquidity_net_at_block(self.tick, swap_log.block_number, middleware)
+            .await?;
 
         Ok(())
     }
 
     //Returns reserve0, reserve1
-    pub fn decode_swap_log(&self, swap_log: &Log) -> (I256, I256, U256, u128, i32) {
+    pub fn decode_swap_log<M: Middleware>(
+        &self,
+        swap_log: &Log,
+    ) -> Result<(I256, I256, U256, u128, i32), CFMMError<M>> {
+        decode_swap_log_data(swap_log)
+    }
+
+    //Sets the starting price and tick of a newly created pool from its Initialize log
+    pub fn update_pool_from_initialize_log<M: Middleware>(
+        &mut self,
+        initialize_log: &Log,
+    ) -> Result<(), CFMMError<M>> {
         let log_data = decode(
             &[
-                ParamType::Int(256),  //amount0
-                ParamType::Int(256),  //amount1
                 ParamType::Uint(160), //sqrtPriceX96
-                ParamType::Uint(128), //liquidity
-                ParamType::Int(24),
+                ParamType::Int(24),   //tick
             ],
-            &swap_log.data,
+            &initialize_log.data,
         )
-        .expect("Could not get log data");
+        .map_err(|_| {
+            CFMMError::AbiDecode(
+                initialize_log.address,
+                initialize_log.topics.first().copied().unwrap_or_default(),
+            )
+        })?;
 
-        let amount_0 = I256::from_raw(log_data[1].to_owned().into_int().unwrap());
-        let amount_1 = I256::from_raw(log_data[1].to_owned().into_int().unwrap());
-        let sqrt_price = log_data[2].to_owned().into_uint().unwrap();
-        let liquidity = log_data[3].to_owned().into_uint().unwrap().as_u128();
-        let tick = log_data[4].to_owned().into_uint().unwrap().as_u32() as i32;
+        self.sqrt_price = log_data[0].to_owned().into_uint().unwrap();
+        self.tick = decode_i24(log_data[1].to_owned().into_int().unwrap());
 
-        (amount_0, amount_1, sqrt_price, liquidity, tick)
+        Ok(())
     }
 
     pub async fn get_token_decimals<M: Middleware>(
-        &mut self,
+        &self,
         middleware: Arc<M>,
     ) -> Result<(u8, u8), CFMMError<M>> {
         let token_a_decimals = abi::IErc20::new(self.token_a, middleware.clone())
@@ -298,6 +772,16 @@ impl UniswapV3Pool {
         Ok((token_a_decimals, token_b_decimals))
     }
 
+    //Returns false if the decimals stored for either token differ from the decimals the token contracts report,
+    //e.g. for a pool loaded from a checkpoint where a token was mis-read.
+    pub async fn verify_decimals<M: Middleware>(
+        &self,
+        middleware: Arc<M>,
+    ) -> Result<bool, CFMMError<M>> {
+        Ok(self.get_token_decimals(middleware).await?
+            == (self.token_a_decimals, self.token_b_decimals))
+    }
+
     pub async fn get_fee<M: Middleware>(
         &mut self,
         middleware: Arc<M>,
@@ -310,6 +794,71 @@ impl UniswapV3Pool {
         Ok(fee)
     }
 
+    //Finds the PoolCreated log for the pool emitted by the factory and stores the block it was emitted at in created_at_block.
+    //This scans the factory's logs from genesis, so it is kept out of get_pool_data and only called when the creation block is needed.
+    pub async fn fetch_creation_block<M: Middleware>(
+        &mut self,
+        factory: H160,
+        middleware: Arc<M>,
+    ) -> Result<u64, CFMMError<M>> {
+        //token0, token1 and fee are indexed, which narrows the query down to the pool's own PoolCreated log
+        let logs = middleware
+            .get_logs(
+                &Filter::new()
+                    .topic0(ValueOrArray::Value(POOL_CREATED_EVENT_SIGNATURE))
+                    .topic1(ValueOrArray::Value(H256::from(self.token_a)))
+                    .topic2(ValueOrArray::Value(H256::from(self.token_b)))
+                    .topic3(ValueOrArray::Value(H256::from_low_u64_be(self.fee as u64)))
+                    .address(factory)
+                    .from_block(BlockNumber::Earliest)
+                    .to_block(BlockNumber::Latest),
+            )
+            .await
+            .map_err(CFMMError::MiddlewareError)?;
+
+        for log in logs {
+            let tokens = decode(&[ParamType::Int(24), ParamType::Address], &log.data)?;
+
+            if tokens[1].to_owned().into_address() == Some(self.address) {
+                if let Some(block_number) = log.block_number {
+                    self.created_at_block = Some(block_number.as_u64());
+                    return Ok(block_number.as_u64());
+                }
+            }
+        }
+
+        Err(CFMMError::CreationBlockNotFound(self.address))
+    }
+
+    pub async fn get_factory<M: Middleware>(
+        &self,
+        middleware: Arc<M>,
+    ) -> Result<H160, CFMMError<M>> {
+        let factory = abi::IUniswapV3Pool::new(self.address, middleware)
+            .factory()
+            .call()
+            .await?;
+
+        Ok(factory)
+    }
+
+    //Returns true if the pool reports expected_factory as its factory and the pool address is the CREATE2 address that expected_factory
+    //deploys the pool's tokens and fee to. A contract mimicking the pool interface can report any factory, but can not be deployed there.
+    pub async fn verify_provenance<M: Middleware>(
+        &self,
+        expected_factory: H160,
+        middleware: Arc<M>,
+    ) -> Result<bool, CFMMError<M>> {
+        if self.get_factory(middleware).await? != expected_factory {
+            return Ok(false);
+        }
+
+        Ok(
+            compute_pool_address(expected_factory, self.token_a, self.token_b, self.fee)
+                == self.address,
+        )
+    }
+
     pub async fn get_token_0<M: Middleware>(
         &self,
         middleware: Arc<M>,
@@ -344,7 +893,7 @@ impl UniswapV3Pool {
        ==> y = L^2*price
     */
     pub fn calculate_virtual_reserves(&self) -> Result<(u128, u128), ArithmeticError> {
-        let price: f64 = self.calculate_price(self.token_a);
+        let price: f64 = self.try_calculate_price(self.token_a)?;
 
         let sqrt_price = BigFloat::from_f64(price.sqrt());
         let liquidity = BigFloat::from_u128(self.liquidity);
@@ -372,386 +921,1681 @@ impl UniswapV3Pool {
         ))
     }
 
-    pub fn calculate_price(&self, base_token: H160) -> f64 {
-        let tick = uniswap_v3_math::tick_math::get_tick_at_sqrt_ratio(self.sqrt_price).unwrap();
-        let shift = self.token_a_decimals as i8 - self.token_b_decimals as i8;
-        let price = if shift < 0 {
-            1.0001_f64.powi(tick) / 10_f64.powi(-shift as i32)
-        } else {
-            1.0001_f64.powi(tick) * 10_f64.powi(shift as i32)
-        };
+    //Same as calculate_virtual_reserves, but with the pool's sqrt price and liquidity read at block_number through at_block,
+    //leaving the pool untouched.
+    pub async fn virtual_reserves_at_block<M: Middleware>(
+        &self,
+        block_number: U64,
+        middleware: Arc<M>,
+    ) -> Result<(u128, u128), CFMMError<M>> {
+        Ok(self
+            .at_block(block_number, middleware)
+            .await?
+            .calculate_virtual_reserves()?)
+    }
 
-        if base_token == self.token_a {
-            price
-        } else {
-            1.0 / price
+    //Returns the amounts of token_a and token_b needed to mint liquidity between tick_lower and tick_upper at the current sqrt price,
+    //rounded down like the periphery's LiquidityAmounts.getAmountsForLiquidity. Below the range the position is all token_a,
+    //above it the position is all token_b, and within it the position holds both.
+    pub fn amounts_for_liquidity(
+        &self,
+        tick_lower: i32,
+        tick_upper: i32,
+        liquidity: u128,
+    ) -> Result<(U256, U256), ArithmeticError> {
+        let mut sqrt_price_lower = uniswap_v3_math::tick_math::get_sqrt_ratio_at_tick(tick_lower)?;
+        let mut sqrt_price_upper = uniswap_v3_math::tick_math::get_sqrt_ratio_at_tick(tick_upper)?;
+        if sqrt_price_lower > sqrt_price_upper {
+            (sqrt_price_lower, sqrt_price_upper) = (sqrt_price_upper, sqrt_price_lower);
         }
-    }
 
-    pub fn address(&self) -> H160 {
-        self.address
+        if self.sqrt_price <= sqrt_price_lower {
+            Ok((
+                amount_0_for_liquidity(sqrt_price_lower, sqrt_price_upper, liquidity)?,
+                U256::zero(),
+            ))
+        } else if self.sqrt_price < sqrt_price_upper {
+            Ok((
+                amount_0_for_liquidity(self.sqrt_price, sqrt_price_upper, liquidity)?,
+                amount_1_for_liquidity(sqrt_price_lower, self.sqrt_price, liquidity)?,
+            ))
+        } else {
+            Ok((
+                U256::zero(),
+                amount_1_for_liquidity(sqrt_price_lower, sqrt_price_upper, liquidity)?,
+            ))
+        }
     }
 
-    pub async fn simulate_swap_mut_with_cache<M: Middleware>(
-        &mut self,
-        token_in: H160,
-        amount_in: U256,
-        num_ticks: u16,
-        middleware: Arc<M>,
-    ) -> Result<U256, CFMMError<M>> {
-        if amount_in.is_zero() {
-            return Ok(U256::zero());
+    //Returns the most liquidity between tick_lower and tick_upper that amount_a of token_a and amount_b of token_b can mint at the current
+    //sqrt price, rounded down like the periphery's LiquidityAmounts.getLiquidityForAmounts. Only the token the range needs at the
+    //current price is used when the price is outside of the range. The inverse of amounts_for_liquidity.
+    pub fn liquidity_for_amounts(
+        &self,
+        tick_lower: i32,
+        tick_upper: i32,
+        amount_a: U256,
+        amount_b: U256,
+    ) -> Result<u128, ArithmeticError> {
+        let mut sqrt_price_lower = uniswap_v3_math::tick_math::get_sqrt_ratio_at_tick(tick_lower)?;
+        let mut sqrt_price_upper = uniswap_v3_math::tick_math::get_sqrt_ratio_at_tick(tick_upper)?;
+        if sqrt_price_lower > sqrt_price_upper {
+            (sqrt_price_lower, sqrt_price_upper) = (sqrt_price_upper, sqrt_price_lower);
         }
 
-        let zero_for_one = token_in == self.token_a;
-
-        //TODO: make this a queue instead of vec and then an iterator FIXME::
-        let (mut tick_data, block_number) =
-            batch_requests::uniswap_v3::get_uniswap_v3_tick_data_batch_request(
-                self,
-                self.tick,
-                zero_for_one,
-                num_ticks,
-                None,
-                middleware.clone(),
+        if self.sqrt_price <= sqrt_price_lower {
+            liquidity_for_amount_0(sqrt_price_lower, sqrt_price_upper, amount_a)
+        } else if self.sqrt_price < sqrt_price_upper {
+            Ok(
+                liquidity_for_amount_0(self.sqrt_price, sqrt_price_upper, amount_a)?.min(
+                    liquidity_for_amount_1(sqrt_price_lower, self.sqrt_price, amount_b)?,
+                ),
             )
-            .await?;
+        } else {
+            liquidity_for_amount_1(sqrt_price_lower, sqrt_price_upper, amount_b)
+        }
+    }
 
-        let mut tick_data_iter = tick_data.iter();
+    //Approximates the amount out using the constant product formula on the virtual reserves, without fetching tick data.
+    //This is only accurate while the swap stays within the current tick, since liquidity changes at initialized ticks are ignored.
+    //Returns an error if the fee is invalid or the virtual reserves can not be computed from the sqrt price.
+    pub fn quick_quote(&self, token_in: H160, amount_in: U256) -> Result<U256, ArithmeticError> {
+        validate_fee(self.fee)?;
 
-        //Set sqrt_price_limit_x_96 to the max or min sqrt price in the pool depending on zero_for_one
-        let sqrt_price_limit_x_96 = if zero_for_one {
-            MIN_SQRT_RATIO + 1
-        } else {
-            MAX_SQRT_RATIO - 1
-        };
+        let (reserve_0, reserve_1) = self.calculate_virtual_reserves()?;
 
-        //Initialize a mutable state state struct to hold the dynamic simulated state of the pool
-        let mut current_state = CurrentState {
-            sqrt_price_x_96: self.sqrt_price, //Active price on the pool
-            amount_calculated: I256::zero(),  //Amount of token_out that has been calculated
-            amount_specified_remaining: I256::from_raw(amount_in), //Amount of token_in that has not been swapped
-            tick: self.tick,                                       //Current i24 tick of the pool
-            liquidity: self.liquidity, //Current available liquidity in the tick range
+        let (reserve_in, reserve_out) = if token_in == self.token_a {
+            (U256::from(reserve_0), U256::from(reserve_1))
+        } else {
+            (U256::from(reserve_1), U256::from(reserve_0))
         };
 
-        let mut liquidity_net = self.liquidity_net;
+        if amount_in.is_zero() || reserve_out.is_zero() {
+            return Ok(U256::zero());
+        }
 
-        while current_state.amount_specified_remaining != I256::zero()
-            && current_state.sqrt_price_x_96 != sqrt_price_limit_x_96
-        {
-            //Initialize a new step struct to hold the dynamic state of the pool at each step
-            let mut step = StepComputations {
-                sqrt_price_start_x_96: current_state.sqrt_price_x_96, //Set the sqrt_price_start_x_96 to the current sqrt_price_x_96
-                ..Default::default()
-            };
+        //The fee is denominated in hundredths of a bip. The products are computed in 512 bits so that any amount_in fits,
+        //and the quote is always below reserve_out, so it fits back in 256 bits.
+        let amount_in_with_fee = amount_in.full_mul(U256::from(1_000_000 - self.fee));
+        let numerator = amount_in_with_fee * U512::from(reserve_out);
+        let denominator = reserve_in.full_mul(U256::from(1_000_000)) + amount_in_with_fee;
 
-            let next_tick_data = if let Some(tick_data) = tick_data_iter.next() {
-                tick_data
-            } else {
-                (tick_data, _) =
-                    batch_requests::uniswap_v3::get_uniswap_v3_tick_data_batch_request(
-                        self,
-                        current_state.tick,
-                        zero_for_one,
-                        num_ticks,
-                        Some(block_number),
-                        middleware.clone(),
-                    )
-                    .await?;
+        Ok(U256::try_from(numerator / denominator).expect("quote is below reserve_out"))
+    }
 
-                tick_data_iter = tick_data.iter();
+    #[deprecated(
+        note = "panics if the sqrt price is out of range, use try_calculate_price instead"
+    )]
+    pub fn calculate_price(&self, base_token: H160) -> f64 {
+        self.try_calculate_price(base_token).unwrap()
+    }
 
-                if let Some(tick_data) = tick_data_iter.next() {
-                    tick_data
-                } else {
-                    //This should never happen, but if it does, we should return an error because something is wrong
-                    return Err(CFMMError::NoInitializedTicks);
-                }
-            };
+    //Returns the price of base_token in terms of the other token, or an error if the sqrt price is outside of the valid range,
+    //such as for a pool that has not been initialized or synced
+    pub fn try_calculate_price(&self, base_token: H160) -> Result<f64, ArithmeticError> {
+        let tick = uniswap_v3_math::tick_math::get_tick_at_sqrt_ratio(self.sqrt_price)?;
+        let price = self.price_at_tick(tick);
 
-            step.tick_next = next_tick_data.tick;
+        if base_token == self.token_a {
+            Ok(price)
+        } else {
+            Ok(1.0 / price)
+        }
+    }
 
-            // ensure that we do not overshoot the min/max tick, as the tick bitmap is not aware of these bounds
-            //Note: this could be removed as we are clamping in the batch contract
-            step.tick_next = step.tick_next.clamp(MIN_TICK, MAX_TICK);
+    //Returns the (bid, ask) price of token_a in terms of token_b that the pool quotes for an infinitesimally small swap at the current
+    //sqrt price. The bid is what selling token_a pays after the fee and the ask is what buying token_a costs including the fee,
+    //so the spread between them is roughly twice the fee.
+    pub async fn bid_ask_prices<M: Middleware>(
+        &self,
+        middleware: Arc<M>,
+    ) -> Result<(f64, f64), CFMMError<M>> {
+        let pool = UniswapV3Pool {
+            sqrt_price: self.get_sqrt_price(middleware).await?,
+            ..*self
+        };
 
-            //Get the next sqrt price from the input amount
-            step.sqrt_price_next_x96 =
-                uniswap_v3_math::tick_math::get_sqrt_ratio_at_tick(step.tick_next)?;
+        if pool.sqrt_price.is_zero() {
+            return Err(CFMMError::PoolNotInitialized(self.address));
+        }
 
-            //Target spot price
-            let swap_target_sqrt_ratio = if zero_for_one {
-                if step.sqrt_price_next_x96 < sqrt_price_limit_x_96 {
-                    sqrt_price_limit_x_96
-                } else {
-                    step.sqrt_price_next_x96
-                }
-            } else if step.sqrt_price_next_x96 > sqrt_price_limit_x_96 {
-                sqrt_price_limit_x_96
-            } else {
-                step.sqrt_price_next_x96
-            };
+        validate_fee(self.fee)?;
 
-            //Compute swap step and update the current state
-            (
-                current_state.sqrt_price_x_96,
-                step.amount_in,
-                step.amount_out,
-                step.fee_amount,
-            ) = uniswap_v3_math::swap_math::compute_swap_step(
-                current_state.sqrt_price_x_96,
-                swap_target_sqrt_ratio,
-                current_state.liquidity,
-                current_state.amount_specified_remaining,
-                self.fee,
-            )?;
+        let mid_price = pool.try_calculate_price(self.token_a)?;
+        let fee = self.fee as f64 / 1_000_000.0;
 
-            //Decrement the amount remaining to be swapped and amount received from the step
-            current_state.amount_specified_remaining = current_state
-                .amount_specified_remaining
-                .overflowing_sub(I256::from_raw(
-                    step.amount_in.overflowing_add(step.fee_amount).0,
-                ))
-                .0;
+        Ok((mid_price * (1.0 - fee), mid_price / (1.0 - fee)))
+    }
 
-            current_state.amount_calculated -= I256::from_raw(step.amount_out);
+    //Returns the current spot price of base_token if it is within max_deviation_bps of its time weighted average price over the last
+    //twap_window_secs, and the TWAP otherwise. Moving the spot price within a block is cheap while moving the TWAP is not, so this
+    //is a manipulation resistant price for protocols using the pool as an oracle. The pool's observation cardinality must cover the window.
+    pub async fn robust_price<M: Middleware>(
+        &self,
+        base_token: H160,
+        twap_window_secs: u32,
+        max_deviation_bps: u32,
+        middleware: Arc<M>,
+    ) -> Result<f64, CFMMError<M>> {
+        let pool = UniswapV3Pool {
+            sqrt_price: self.get_sqrt_price(middleware.clone()).await?,
+            ..*self
+        };
 
-            //If the price moved all the way to the next price, recompute the liquidity change for the next iteration
-            if current_state.sqrt_price_x_96 == step.sqrt_price_next_x96 {
-                if next_tick_data.initialized {
-                    liquidity_net = next_tick_data.liquidity_net;
+        if pool.sqrt_price.is_zero() {
+            return Err(CFMMError::PoolNotInitialized(self.address));
+        }
 
-                    // we are on a tick boundary, and the next tick is initialized, so we must charge a protocol fee
-                    if zero_for_one {
-                        liquidity_net = -liquidity_net;
-                    }
+        let spot_price = pool.try_calculate_price(base_token)?;
 
-                    current_state.liquidity = if liquidity_net < 0 {
-                        current_state.liquidity - (-liquidity_net as u128)
-                    } else {
-                        current_state.liquidity + (liquidity_net as u128)
-                    };
-                }
-                //Increment the current tick
-                current_state.tick = if zero_for_one {
-                    step.tick_next.wrapping_sub(1)
-                } else {
-                    step.tick_next
-                }
-                //If the current_state sqrt price is not equal to the step sqrt price, then we are not on the same tick.
-                //Update the current_state.tick to the tick at the current_state.sqrt_price_x_96
-            } else if current_state.sqrt_price_x_96 != step.sqrt_price_start_x_96 {
-                current_state.tick = uniswap_v3_math::tick_math::get_tick_at_sqrt_ratio(
-                    current_state.sqrt_price_x_96,
-                )?;
-            }
+        if twap_window_secs == 0 {
+            return Ok(spot_price);
         }
 
-        //Update the pool state
-        self.liquidity = current_state.liquidity;
-        self.sqrt_price = current_state.sqrt_price_x_96;
-        self.tick = current_state.tick;
-        self.liquidity_net = liquidity_net;
+        let twap_tick = self.twap_tick(twap_window_secs, middleware).await?;
+        let twap_price = if base_token == self.token_a {
+            self.price_at_tick(twap_tick)
+        } else {
+            1.0 / self.price_at_tick(twap_tick)
+        };
 
-        Ok((-current_state.amount_calculated).into_raw())
+        let deviation_bps = (spot_price / twap_price - 1.0).abs() * 10_000.0;
+
+        if deviation_bps <= max_deviation_bps as f64 {
+            Ok(spot_price)
+        } else {
+            Ok(twap_price)
+        }
     }
 
-    pub async fn simulate_swap_with_cache<M: Middleware>(
+    //Returns the arithmetic mean tick over the last window_secs from the pool's oracle observations, rounded towards negative infinity
+    //like the periphery's OracleLibrary.consult
+    async fn twap_tick<M: Middleware>(
         &self,
-        token_in: H160,
-        amount_in: U256,
-        num_ticks: u16,
+        window_secs: u32,
         middleware: Arc<M>,
-    ) -> Result<U256, CFMMError<M>> {
-        if amount_in.is_zero() {
-            return Ok(U256::zero());
+    ) -> Result<i32, CFMMError<M>> {
+        let (tick_cumulatives, _) = abi::IUniswapV3Pool::new(self.address, middleware)
+            .observe(vec![window_secs, 0])
+            .call()
+            .await?;
+
+        if tick_cumulatives.len() != 2 {
+            return Err(CFMMError::PoolDataError);
         }
 
-        let zero_for_one = token_in == self.token_a;
+        let tick_cumulatives_delta = tick_cumulatives[1] - tick_cumulatives[0];
+        let mut mean_tick = tick_cumulatives_delta / window_secs as i64;
+        if tick_cumulatives_delta < 0 && tick_cumulatives_delta % window_secs as i64 != 0 {
+            mean_tick -= 1;
+        }
 
-        //TODO: make this a queue instead of vec and then an iterator FIXME::
-        let (mut tick_data, block_number) =
-            batch_requests::uniswap_v3::get_uniswap_v3_tick_data_batch_request(
-                self,
-                self.tick,
-                zero_for_one,
-                num_ticks,
-                None,
-                middleware.clone(),
-            )
-            .await?;
+        Ok(mean_tick as i32)
+    }
 
-        let mut tick_data_iter = tick_data.iter();
+    //Price of token_a in terms of token_b at the tick, adjusted for the token decimals. The shift is computed in i32 because the
+    //difference between two u8 decimals can fall outside of the i8 range, and a negative shift divides by the exact power of ten.
+    fn price_at_tick(&self, tick: i32) -> f64 {
+        let shift = self.token_a_decimals as i32 - self.token_b_decimals as i32;
+        if shift < 0 {
+            1.0001_f64.powi(tick) / 10_f64.powi(-shift)
+        } else {
+            1.0001_f64.powi(tick) * 10_f64.powi(shift)
+        }
+    }
 
-        //Set sqrt_price_limit_x_96 to the max or min sqrt price in the pool depending on zero_for_one
-        let sqrt_price_limit_x_96 = if zero_for_one {
-            MIN_SQRT_RATIO + 1
+    //Converts a human readable price of base_token, in the same terms as try_calculate_price(base_token), to the nearest tick
+    //that is a multiple of tick_spacing, clamped to the usable tick bounds. The inverse of try_calculate_price up to tick spacing.
+    pub fn nearest_tick_for_price(&self, price: f64, base_token: H160) -> i32 {
+        //Ticks track the price of token_a in terms of token_b
+        let price = if base_token == self.token_a {
+            price
         } else {
-            MAX_SQRT_RATIO - 1
+            1.0 / price
         };
 
-        //Initialize a mutable state state struct to hold the dynamic simulated state of the pool
-        let mut current_state = CurrentState {
-            sqrt_price_x_96: self.sqrt_price, //Active price on the pool
-            amount_calculated: I256::zero(),  //Amount of token_out that has been calculated
-            amount_specified_remaining: I256::from_raw(amount_in), //Amount of token_in that has not been swapped
-            tick: self.tick,                                       //Current i24 tick of the pool
-            liquidity: self.liquidity, //Current available liquidity in the tick range
-        };
+        //Undo the decimal adjustment of price_at_tick and solve 1.0001^tick = price without rounding to a whole tick
+        let shift = self.token_a_decimals as i32 - self.token_b_decimals as i32;
+        let tick = (price / 10_f64.powi(shift)).ln() / 1.0001_f64.ln();
 
-        while current_state.amount_specified_remaining != I256::zero()
-            && current_state.sqrt_price_x_96 != sqrt_price_limit_x_96
-        {
-            //Initialize a new step struct to hold the dynamic state of the pool at each step
-            let mut step = StepComputations {
-                sqrt_price_start_x_96: current_state.sqrt_price_x_96, //Set the sqrt_price_start_x_96 to the current sqrt_price_x_96
-                ..Default::default()
-            };
+        let tick_spacing = self.tick_spacing.max(1);
+        let (min_tick, max_tick) = self.usable_tick_bounds();
 
-            let next_tick_data = if let Some(tick_data) = tick_data_iter.next() {
-                tick_data
-            } else {
-                (tick_data, _) =
-                    batch_requests::uniswap_v3::get_uniswap_v3_tick_data_batch_request(
-                        self,
-                        current_state.tick,
-                        zero_for_one,
-                        num_ticks,
-                        Some(block_number),
-                        middleware.clone(),
-                    )
-                    .await?;
+        //Clamp before converting back to an integer, as prices far outside of the tick range would overflow
+        let tick_index = (tick / tick_spacing as f64).round().clamp(
+            (min_tick / tick_spacing) as f64,
+            (max_tick / tick_spacing) as f64,
+        );
 
-                tick_data_iter = tick_data.iter();
+        tick_index as i32 * tick_spacing
+    }
 
-                if let Some(tick_data) = tick_data_iter.next() {
-                    tick_data
-                } else {
-                    //This should never happen, but if it does, we should return an error because something is wrong
-                    return Err(CFMMError::NoInitializedTicks);
-                }
-            };
+    //Returns the impermanent loss of a position between tick_lower and tick_upper that was entered at entry_price, as a fraction of the value
+    //the deposited tokens would have if they had been held instead. Prices are of token_a in terms of token_b as returned by try_calculate_price.
+    //tick_lower must be below tick_upper.
+    pub fn impermanent_loss(
+        &self,
+        entry_price: f64,
+        tick_lower: i32,
+        tick_upper: i32,
+    ) -> Result<f64, ArithmeticError> {
+        if tick_lower >= tick_upper {
+            return Err(ArithmeticError::InvalidTickRange(tick_lower, tick_upper));
+        }
 
-            step.tick_next = next_tick_data.tick;
+        let sqrt_price_lower = self.price_at_tick(tick_lower).sqrt();
+        let sqrt_price_upper = self.price_at_tick(tick_upper).sqrt();
 
-            // ensure that we do not overshoot the min/max tick, as the tick bitmap is not aware of these bounds
-            //Note: this could be removed as we are clamping in the batch contract
-            step.tick_next = step.tick_next.clamp(MIN_TICK, MAX_TICK);
+        //Token amounts held by a position with one unit of liquidity, where only one of the tokens is held outside of the range
+        let amounts_at = |price: f64| {
+            let sqrt_price = price.sqrt().clamp(sqrt_price_lower, sqrt_price_upper);
+            (
+                1.0 / sqrt_price - 1.0 / sqrt_price_upper,
+                sqrt_price - sqrt_price_lower,
+            )
+        };
 
-            //Get the next sqrt price from the input amount
-            step.sqrt_price_next_x96 =
-                uniswap_v3_math::tick_math::get_sqrt_ratio_at_tick(step.tick_next)?;
+        let price = self.try_calculate_price(self.token_a)?;
+        let (entry_amount_a, entry_amount_b) = amounts_at(entry_price);
+        let (amount_a, amount_b) = amounts_at(price);
 
-            //Target spot price
-            let swap_target_sqrt_ratio = if zero_for_one {
-                if step.sqrt_price_next_x96 < sqrt_price_limit_x_96 {
-                    sqrt_price_limit_x_96
-                } else {
-                    step.sqrt_price_next_x96
-                }
-            } else if step.sqrt_price_next_x96 > sqrt_price_limit_x_96 {
-                sqrt_price_limit_x_96
-            } else {
-                step.sqrt_price_next_x96
-            };
+        let held_value = entry_amount_a * price + entry_amount_b;
+        if held_value == 0.0 {
+            return Ok(0.0);
+        }
 
-            //Compute swap step and update the current state
-            (
-                current_state.sqrt_price_x_96,
-                step.amount_in,
-                step.amount_out,
-                step.fee_amount,
-            ) = uniswap_v3_math::swap_math::compute_swap_step(
-                current_state.sqrt_price_x_96,
-                swap_target_sqrt_ratio,
-                current_state.liquidity,
-                current_state.amount_specified_remaining,
-                self.fee,
-            )?;
+        Ok(1.0 - (amount_a * price + amount_b) / held_value)
+    }
 
-            //Decrement the amount remaining to be swapped and amount received from the step
-            current_state.amount_specified_remaining = current_state
-                .amount_specified_remaining
-                .overflowing_sub(I256::from_raw(
-                    step.amount_in.overflowing_add(step.fee_amount).0,
-                ))
-                .0;
+    //Returns the approximate (lower, upper) price of token_a in terms of token_b that a simulation fetching num_ticks of tick data can reach.
+    //Initialized ticks are at least one tick spacing apart, so this is the narrowest range that num_ticks covers. Trades expected to move
+    //the price outside of it may need a larger num_ticks to avoid refetching tick data mid simulation.
+    pub fn price_range_for_ticks(&self, num_ticks: u16) -> (f64, f64) {
+        let (min_tick, max_tick) = self.usable_tick_bounds();
+        let tick_range = num_ticks as i32 * self.tick_spacing.max(1);
 
-            current_state.amount_calculated -= I256::from_raw(step.amount_out);
+        (
+            self.price_at_tick(self.tick.saturating_sub(tick_range).max(min_tick)),
+            self.price_at_tick(self.tick.saturating_add(tick_range).min(max_tick)),
+        )
+    }
 
-            //If the price moved all the way to the next price, recompute the liquidity change for the next iteration
-            if current_state.sqrt_price_x_96 == step.sqrt_price_next_x96 {
-                if next_tick_data.initialized {
-                    let mut liquidity_net = next_tick_data.liquidity_net;
+    //Returns the min and max ticks that are multiples of tick_spacing, the bounds within which positions can provide liquidity
+    pub fn usable_tick_bounds(&self) -> (i32, i32) {
+        usable_tick_bounds(self.tick_spacing)
+    }
 
-                    // we are on a tick boundary, and the next tick is initialized, so we must charge a protocol fee
-                    if zero_for_one {
-                        liquidity_net = -liquidity_net;
-                    }
+    //Verifies that the tick is within one tick of the tick implied by sqrt_price, guarding against partially updated pool state
+    pub fn assert_tick_consistency(&self) -> Result<(), ArithmeticError> {
+        let expected_tick = uniswap_v3_math::tick_math::get_tick_at_sqrt_ratio(self.sqrt_price)
+            .map_err(|_| ArithmeticError::SqrtPriceOverflow)?;
 
-                    current_state.liquidity = if liquidity_net < 0 {
-                        current_state.liquidity - (-liquidity_net as u128)
-                    } else {
-                        current_state.liquidity + (liquidity_net as u128)
-                    };
-                }
-                //Increment the current tick
-                current_state.tick = if zero_for_one {
-                    step.tick_next.wrapping_sub(1)
-                } else {
-                    step.tick_next
-                }
-                //If the current_state sqrt price is not equal to the step sqrt price, then we are not on the same tick.
-                //Update the current_state.tick to the tick at the current_state.sqrt_price_x_96
-            } else if current_state.sqrt_price_x_96 != step.sqrt_price_start_x_96 {
-                current_state.tick = uniswap_v3_math::tick_math::get_tick_at_sqrt_ratio(
-                    current_state.sqrt_price_x_96,
-                )?;
-            }
+        if (self.tick - expected_tick).abs() > 1 {
+            return Err(ArithmeticError::TickInconsistency(self.tick, expected_tick));
         }
 
-        Ok((-current_state.amount_calculated).into_raw())
+        Ok(())
     }
 
-    pub async fn simulate_swap<M: Middleware>(
-        &self,
+    pub fn address(&self) -> H160 {
+        self.address
+    }
+
+    pub fn sqrt_price(&self) -> U256 {
+        self.sqrt_price
+    }
+
+    pub fn tick(&self) -> i32 {
+        self.tick
+    }
+
+    pub fn liquidity(&self) -> u128 {
+        self.liquidity
+    }
+
+    //Returns (token_a, token_b)
+    pub fn tokens(&self) -> (H160, H160) {
+        (self.token_a, self.token_b)
+    }
+
+    //Returns (token_a_decimals, token_b_decimals)
+    pub fn decimals(&self) -> (u8, u8) {
+        (self.token_a_decimals, self.token_b_decimals)
+    }
+
+    //Returns the other token in the pool, or None if token is not in the pool
+    pub fn get_other_token(&self, token: H160) -> Option<H160> {
+        if token == self.token_a {
+            Some(self.token_b)
+        } else if token == self.token_b {
+            Some(self.token_a)
+        } else {
+            None
+        }
+    }
+
+    //Simulates the swap and updates the pool to the resulting state.
+    //This is cancellation safe: the swap is simulated on local state and the pool is only written once every await has completed,
+    //so if the future is dropped mid-simulation (e.g. by a timeout) the pool is left unchanged.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(pool = ?self.address, ?token_in, %amount_in))
+    )]
+    pub async fn simulate_swap_mut_with_cache<M: Middleware>(
+        &mut self,
         token_in: H160,
         amount_in: U256,
+        num_ticks: u16,
         middleware: Arc<M>,
     ) -> Result<U256, CFMMError<M>> {
-        self.simulate_swap_with_cache(token_in, amount_in, 150, middleware)
-            .await
+        let (amount_out, current_state, liquidity_net) = self
+            .simulate_exact_input(token_in, amount_in, num_ticks, None, middleware)
+            .await?;
+
+        //Update the pool state in a single write after the last await, so that dropping the future mid-simulation leaves the pool untouched
+        *self = UniswapV3Pool {
+            liquidity: current_state.liquidity,
+            sqrt_price: current_state.sqrt_price_x_96,
+            tick: current_state.tick,
+            liquidity_net,
+            ..*self
+        };
+
+        Ok(amount_out)
     }
 
-    pub async fn get_word<M: Middleware>(
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(pool = ?self.address, ?token_in, %amount_in))
+    )]
+    pub async fn simulate_swap_with_cache<M: Middleware>(
         &self,
-        word_pos: i16,
-        block_number: Option<U64>,
+        token_in: H160,
+        amount_in: U256,
+        num_ticks: u16,
         middleware: Arc<M>,
     ) -> Result<U256, CFMMError<M>> {
-        if block_number.is_some() {
-            //TODO: in the future, create a batch call to get this and liquidity net within the same call
+        let (amount_out, ..) = self
+            .simulate_exact_input(token_in, amount_in, num_ticks, None, middleware)
+            .await?;
 
-            Ok(abi::IUniswapV3Pool::new(self.address, middleware.clone())
-                .tick_bitmap(word_pos)
-                .block(block_number.unwrap())
-                .call()
-                .await?)
-        } else {
-            //TODO: in the future, create a batch call to get this and liquidity net within the same call
-            Ok(abi::IUniswapV3Pool::new(self.address, middleware.clone())
-                .tick_bitmap(word_pos)
-                .call()
-                .await?)
-        }
+        Ok(amount_out)
     }
 
-    pub fn calculate_compressed(&self, tick: i32) -> i32 {
-        if tick < 0 && tick % self.tick_spacing != 0 {
-            (tick / self.tick_spacing) - 1
-        } else {
-            tick / self.tick_spacing
+    //Shared core of simulate_swap_with_cache and simulate_swap_mut_with_cache, returning the amount out along with the state and
+    //liquidity_net the pool is left in, so that the two only differ in whether they write the state back to the pool
+    async fn simulate_exact_input<M: Middleware>(
+        &self,
+        token_in: H160,
+        amount_in: U256,
+        num_ticks: u16,
+        block_number: Option<U64>,
+        middleware: Arc<M>,
+    ) -> Result<(U256, CurrentState, i128), CFMMError<M>> {
+        if amount_in.is_zero() {
+            return Ok((
+                U256::zero(),
+                CurrentState {
+                    amount_specified_remaining: I256::zero(),
+                    amount_calculated: I256::zero(),
+                    sqrt_price_x_96: self.sqrt_price,
+                    tick: self.tick,
+                    liquidity: self.liquidity,
+                    initialized_ticks_crossed: 0,
+                },
+                self.liquidity_net,
+            ));
+        }
+
+        let (current_state, liquidity_net) = self
+            .run_swap(
+                token_in,
+                amount_specified_from_amount_in::<M>(amount_in)?,
+                default_sqrt_price_limit(token_in == self.token_a),
+                num_ticks,
+                None,
+                None,
+                block_number,
+                middleware,
+            )
+            .await?;
+
+        Ok((
+            (-current_state.amount_calculated).into_raw(),
+            current_state,
+            liquidity_net,
+        ))
+    }
+
+    //Returns the amount of token_in needed to receive exactly amount_out, like the pool contract's exact output swaps.
+    //Returns an error if the pool does not have enough liquidity in the direction of the swap to fill amount_out.
+    pub async fn simulate_swap_exact_output<M: Middleware>(
+        &self,
+        token_in: H160,
+        amount_out: U256,
+        middleware: Arc<M>,
+    ) -> Result<U256, CFMMError<M>> {
+        if amount_out.is_zero() {
+            return Ok(U256::zero());
+        }
+
+        let current_state = self
+            .simulate_swap_state(
+                token_in,
+                -amount_specified_from_amount_in::<M>(amount_out)?,
+                default_sqrt_price_limit(token_in == self.token_a),
+                150,
+                None,
+                middleware,
+            )
+            .await?;
+
+        if !current_state.amount_specified_remaining.is_zero() {
+            return Err(CFMMError::InsufficientLiquidity(self.address));
+        }
+
+        Ok(current_state.amount_calculated.into_raw())
+    }
+
+    //Simulates a swap that stops once the pool reaches sqrt_price_limit_x_96, leaving any remaining amount_in unfilled.
+    //Like the pool contract, the limit must be strictly between the current sqrt price and the min/max sqrt price in the direction of the swap.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(pool = ?self.address, ?token_in, %amount_in, %sqrt_price_limit_x_96))
+    )]
+    pub async fn simulate_swap_with_limit<M: Middleware>(
+        &self,
+        token_in: H160,
+        amount_in: U256,
+        sqrt_price_limit_x_96: U256,
+        middleware: Arc<M>,
+    ) -> Result<U256, CFMMError<M>> {
+        let zero_for_one = token_in == self.token_a;
+
+        let limit_is_valid = if zero_for_one {
+            sqrt_price_limit_x_96 < self.sqrt_price && sqrt_price_limit_x_96 > MIN_SQRT_RATIO
+        } else {
+            sqrt_price_limit_x_96 > self.sqrt_price && sqrt_price_limit_x_96 < MAX_SQRT_RATIO
+        };
+
+        if !limit_is_valid {
+            return Err(CFMMError::InvalidSqrtPriceLimit(sqrt_price_limit_x_96));
+        }
+
+        if amount_in.is_zero() {
+            return Ok(U256::zero());
+        }
+
+        let current_state = self
+            .simulate_swap_state(
+                token_in,
+                amount_specified_from_amount_in::<M>(amount_in)?,
+                sqrt_price_limit_x_96,
+                150,
+                None,
+                middleware,
+            )
+            .await?;
+
+        Ok((-current_state.amount_calculated).into_raw())
+    }
+
+    //Simulates a swap that stops once the price of token_in in terms of token_out falls to price_limit.
+    //The price is human readable, in the same terms as try_calculate_price(token_in).
+    pub async fn simulate_swap_with_price_limit<M: Middleware>(
+        &self,
+        token_in: H160,
+        amount_in: U256,
+        price_limit: f64,
+        middleware: Arc<M>,
+    ) -> Result<U256, CFMMError<M>> {
+        //sqrt_price always tracks the price of token_a in terms of token_b
+        let price_limit = if token_in == self.token_a {
+            price_limit
+        } else {
+            1.0 / price_limit
+        };
+
+        let sqrt_price_limit_x_96 =
+            sqrt_price_from_price(price_limit, self.token_a_decimals, self.token_b_decimals);
+
+        self.simulate_swap_with_limit(token_in, amount_in, sqrt_price_limit_x_96, middleware)
+            .await
+    }
+
+    //Returns the sqrt price limit for a swap that accepts at most slippage_bps of price movement away from the current price.
+    //The result is clamped to the open range between MIN_SQRT_RATIO and MAX_SQRT_RATIO, so it can be passed to simulate_swap_with_limit or swap_calldata as is.
+    pub fn sqrt_price_limit_from_slippage(&self, zero_for_one: bool, slippage_bps: u32) -> U256 {
+        //A zero_for_one swap pushes the price down, so the worst acceptable price is below the current price
+        let price_factor_bps = if zero_for_one {
+            10000_u32.saturating_sub(slippage_bps)
+        } else {
+            10000_u32.saturating_add(slippage_bps)
+        };
+
+        //sqrt(price_factor_bps / 10000) as a Q64.96
+        let sqrt_factor_x_96 =
+            ((U256::from(price_factor_bps) << 192) / U256::from(10000)).integer_sqrt();
+
+        let sqrt_price_limit_x_96 =
+            U256::try_from(self.sqrt_price.full_mul(sqrt_factor_x_96) >> 96)
+                .unwrap_or(MAX_SQRT_RATIO);
+
+        sqrt_price_limit_x_96.clamp(MIN_SQRT_RATIO + 1, MAX_SQRT_RATIO - 1)
+    }
+
+    //Returns the amount of token_in (including fees) that can be swapped before the pool reaches its min/max sqrt price.
+    //Any amount above this value is only partially filled.
+    pub async fn max_swappable_in<M: Middleware>(
+        &self,
+        token_in: H160,
+        middleware: Arc<M>,
+    ) -> Result<U256, CFMMError<M>> {
+        let current_state = self
+            .simulate_swap_state(
+                token_in,
+                I256::MAX,
+                default_sqrt_price_limit(token_in == self.token_a),
+                150,
+                None,
+                middleware,
+            )
+            .await?;
+
+        Ok((I256::MAX - current_state.amount_specified_remaining).into_raw())
+    }
+
+    //Returns the amount in and amount out of the smallest swap of token_in that moves the price of base_token to target_price,
+    //e.g. for grid and rebalancing strategies. The price is human readable, in the same terms as try_calculate_price(base_token),
+    //and must be on the side of the current price that swapping token_in moves the pool towards.
+    pub async fn swap_until_price<M: Middleware>(
+        &self,
+        token_in: H160,
+        target_price: f64,
+        base_token: H160,
+        middleware: Arc<M>,
+    ) -> Result<(U256, U256), CFMMError<M>> {
+        //sqrt_price always tracks the price of token_a in terms of token_b
+        let target_price = if base_token == self.token_a {
+            target_price
+        } else {
+            1.0 / target_price
+        };
+
+        let sqrt_price_target_x_96 =
+            sqrt_price_from_price(target_price, self.token_a_decimals, self.token_b_decimals);
+
+        let target_is_valid = if token_in == self.token_a {
+            sqrt_price_target_x_96 < self.sqrt_price && sqrt_price_target_x_96 > MIN_SQRT_RATIO
+        } else {
+            sqrt_price_target_x_96 > self.sqrt_price && sqrt_price_target_x_96 < MAX_SQRT_RATIO
+        };
+
+        if !target_is_valid {
+            return Err(CFMMError::InvalidSqrtPriceLimit(sqrt_price_target_x_96));
+        }
+
+        let current_state = self
+            .simulate_swap_state(
+                token_in,
+                I256::MAX,
+                sqrt_price_target_x_96,
+                150,
+                None,
+                middleware,
+            )
+            .await?;
+
+        Ok((
+            (I256::MAX - current_state.amount_specified_remaining).into_raw(),
+            (-current_state.amount_calculated).into_raw(),
+        ))
+    }
+
+    //Returns how much of base_token can be (bought, sold) before its price moves bps basis points away from the current price,
+    //a standard market depth metric (e.g. bps = 200 for the ±2% depth). Selling 100% or more of the price away is capped at the
+    //pool's minimum price, so the sold depth is then everything the pool can absorb.
+    pub async fn depth_at_bps<M: Middleware>(
+        &self,
+        bps: u32,
+        base_token: H160,
+        middleware: Arc<M>,
+    ) -> Result<(U256, U256), CFMMError<M>> {
+        let change = bps as f64 / 10_000.0;
+        let (up, down) = ((1.0 + change).sqrt(), (1.0 - change).max(0.0).sqrt());
+
+        //sqrt_price tracks the price of token_a, so the price of token_b moves the opposite way
+        let (quote_token, buy_factor, sell_factor) = if base_token == self.token_a {
+            (self.token_b, up, down)
+        } else {
+            (self.token_a, 1.0 / up, 1.0 / down)
+        };
+
+        let buy_state = self
+            .simulate_swap_state(
+                quote_token,
+                I256::MAX,
+                self.scaled_sqrt_price(buy_factor),
+                150,
+                None,
+                middleware.clone(),
+            )
+            .await?;
+
+        let sell_state = self
+            .simulate_swap_state(
+                base_token,
+                I256::MAX,
+                self.scaled_sqrt_price(sell_factor),
+                150,
+                None,
+                middleware,
+            )
+            .await?;
+
+        Ok((
+            (-buy_state.amount_calculated).into_raw(),
+            (I256::MAX - sell_state.amount_specified_remaining).into_raw(),
+        ))
+    }
+
+    //Returns the current sqrt price multiplied by factor, clamped to the range a swap can move the price within
+    fn scaled_sqrt_price(&self, factor: f64) -> U256 {
+        let scaled = factor * 1e18;
+        let sqrt_price = if scaled.is_finite() && scaled < u128::MAX as f64 {
+            uniswap_v3_math::full_math::mul_div(
+                self.sqrt_price,
+                U256::from(scaled as u128),
+                U256::exp10(18),
+            )
+            .unwrap_or(MAX_SQRT_RATIO)
+        } else {
+            MAX_SQRT_RATIO
+        };
+
+        sqrt_price.clamp(MIN_SQRT_RATIO + 1, MAX_SQRT_RATIO - 1)
+    }
+
+    //Runs the swap loop without mutating the pool, returning the simulated state once the amount is consumed or the price limit is reached
+    async fn simulate_swap_state<M: Middleware>(
+        &self,
+        token_in: H160,
+        amount_specified: I256,
+        sqrt_price_limit_x_96: U256,
+        num_ticks: u16,
+        trace: Option<&mut Vec<StepComputations>>,
+        middleware: Arc<M>,
+    ) -> Result<CurrentState, CFMMError<M>> {
+        let (current_state, _) = self
+            .run_swap(
+                token_in,
+                amount_specified,
+                sqrt_price_limit_x_96,
+                num_ticks,
+                None,
+                trace,
+                None,
+                middleware,
+            )
+            .await?;
+
+        Ok(current_state)
+    }
+
+    //Swap loop shared by every simulation. amount_specified is positive for an exact input swap and negative for an exact output swap.
+    //Runs without mutating the pool until the amount is consumed, the price limit is reached or max_ticks_crossed initialized ticks
+    //have been crossed, returning the simulated state along with the liquidity_net of the tick the swap ends on.
+    #[allow(clippy::too_many_arguments)]
+    async fn run_swap<M: Middleware>(
+        &self,
+        token_in: H160,
+        amount_specified: I256,
+        sqrt_price_limit_x_96: U256,
+        num_ticks: u16,
+        max_ticks_crossed: Option<u32>,
+        mut trace: Option<&mut Vec<StepComputations>>,
+        block_number: Option<U64>,
+        middleware: Arc<M>,
+    ) -> Result<(CurrentState, i128), CFMMError<M>> {
+        if self.sqrt_price.is_zero() {
+            return Err(CFMMError::PoolNotInitialized(self.address));
+        }
+
+        validate_fee(self.fee)?;
+
+        let zero_for_one = token_in == self.token_a;
+
+        //TODO: make this a queue instead of vec and then an iterator FIXME::
+        let (mut tick_data, block_number) =
+            batch_requests::uniswap_v3::get_uniswap_v3_tick_data_batch_request(
+                self,
+                self.tick,
+                zero_for_one,
+                num_ticks,
+                block_number,
+                middleware.clone(),
+            )
+            .await?;
+
+        let mut tick_data_iter = tick_data.iter();
+
+        let (min_tick, max_tick) = self.usable_tick_bounds();
+
+        //Initialize a mutable state state struct to hold the dynamic simulated state of the pool
+        let mut current_state = CurrentState {
+            sqrt_price_x_96: self.sqrt_price, //Active price on the pool
+            amount_calculated: I256::zero(),  //Amount of token_out that has been calculated
+            amount_specified_remaining: amount_specified, //Amount of token_in that has not been swapped
+            tick: self.tick,                              //Current i24 tick of the pool
+            liquidity: self.liquidity, //Current available liquidity in the tick range
+            initialized_ticks_crossed: 0,
+        };
+
+        let mut liquidity_net = self.liquidity_net;
+
+        #[cfg(feature = "tracing")]
+        let mut ticks_crossed: u32 = 0;
+
+        let max_iterations = max_swap_iterations(self.tick_spacing);
+        let mut iterations = 0;
+
+        while current_state.amount_specified_remaining != I256::zero()
+            && current_state.sqrt_price_x_96 != sqrt_price_limit_x_96
+        {
+            //Malformed tick data can keep the swap from making progress, which would otherwise loop forever
+            iterations += 1;
+            if iterations > max_iterations {
+                return Err(CFMMError::SimulationDidNotConverge(self.address));
+            }
+
+            let next_tick_data = if let Some(tick_data) = tick_data_iter.next() {
+                tick_data
+            } else {
+                #[cfg(feature = "tracing")]
+                tracing::debug!(tick = current_state.tick, "tick data exhausted, refetching");
+
+                (tick_data, _) =
+                    batch_requests::uniswap_v3::get_uniswap_v3_tick_data_batch_request(
+                        self,
+                        current_state.tick,
+                        zero_for_one,
+                        num_ticks,
+                        Some(block_number),
+                        middleware.clone(),
+                    )
+                    .await?;
+
+                tick_data_iter = tick_data.iter();
+
+                if let Some(tick_data) = tick_data_iter.next() {
+                    tick_data
+                } else {
+                    //This should never happen, but if it does, we should return an error because something is wrong
+                    return Err(CFMMError::NoInitializedTicks);
+                }
+            };
+
+            let tick_before_step = current_state.tick;
+
+            let (step, reached_tick_bound) = swap_step(
+                &mut current_state,
+                next_tick_data,
+                zero_for_one,
+                sqrt_price_limit_x_96,
+                self.fee,
+                (min_tick, max_tick),
+                || CFMMError::LiquidityNetOverflow(self.address),
+            )?;
+
+            if current_state.sqrt_price_x_96 == step.sqrt_price_next_x96 {
+                #[cfg(feature = "tracing")]
+                {
+                    ticks_crossed += 1;
+                }
+
+                //Crossing up leaves the pool on the crossed tick. Crossing down leaves it on the tick below, which is only initialized
+                //if it is the next tick in the tick data
+                liquidity_net = if zero_for_one {
+                    tick_data_iter
+                        .as_slice()
+                        .first()
+                        .filter(|tick_data| {
+                            tick_data.initialized && tick_data.tick == current_state.tick
+                        })
+                        .map_or(0, |tick_data| tick_data.liquidity_net)
+                } else if next_tick_data.initialized {
+                    next_tick_data.liquidity_net
+                } else {
+                    0
+                };
+            } else if current_state.tick != tick_before_step {
+                //There are no initialized ticks between the current tick and the next tick in the tick data,
+                //so a new tick is only initialized if it is the next tick
+                liquidity_net =
+                    if next_tick_data.initialized && next_tick_data.tick == current_state.tick {
+                        next_tick_data.liquidity_net
+                    } else {
+                        0
+                    };
+            }
+
+            if let Some(trace) = trace.as_mut() {
+                trace.push(step);
+            }
+
+            if reached_tick_bound
+                || max_ticks_crossed
+                    .is_some_and(|max| current_state.initialized_ticks_crossed >= max)
+            {
+                break;
+            }
+        }
+
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            ticks_crossed,
+            tick = current_state.tick,
+            "swap simulation complete"
+        );
+
+        Ok((current_state, liquidity_net))
+    }
+
+    //Returns true if swapping amount_in of token_in would cross at least n initialized ticks, stopping the simulation as soon as it does.
+    //Each initialized tick crossed adds to the gas cost of the swap, so routers can use this to filter out expensive trades.
+    pub async fn would_cross_n_ticks<M: Middleware>(
+        &self,
+        token_in: H160,
+        amount_in: U256,
+        n: u16,
+        middleware: Arc<M>,
+    ) -> Result<bool, CFMMError<M>> {
+        if n == 0 {
+            return Ok(true);
+        }
+
+        if amount_in.is_zero() {
+            return Ok(false);
+        }
+
+        let (current_state, _) = self
+            .run_swap(
+                token_in,
+                amount_specified_from_amount_in::<M>(amount_in)?,
+                default_sqrt_price_limit(token_in == self.token_a),
+                n,
+                Some(n as u32),
+                None,
+                None,
+                middleware,
+            )
+            .await?;
+
+        Ok(current_state.initialized_ticks_crossed >= n as u32)
+    }
+
+    //Simulates a swap, returning the amount out along with every step computed while crossing ticks
+    pub async fn simulate_swap_trace<M: Middleware>(
+        &self,
+        token_in: H160,
+        amount_in: U256,
+        middleware: Arc<M>,
+    ) -> Result<(U256, Vec<StepComputations>), CFMMError<M>> {
+        let mut trace = vec![];
+
+        if amount_in.is_zero() {
+            return Ok((U256::zero(), trace));
+        }
+
+        let current_state = self
+            .simulate_swap_state(
+                token_in,
+                amount_specified_from_amount_in::<M>(amount_in)?,
+                default_sqrt_price_limit(token_in == self.token_a),
+                150,
+                Some(&mut trace),
+                middleware,
+            )
+            .await?;
+
+        Ok(((-current_state.amount_calculated).into_raw(), trace))
+    }
+
+    //Simulates a swap, rounding the amount out according to rounding_mode. RoundingMode::Protocol is identical to simulate_swap,
+    //while RoundingMode::Ideal is at most 1 wei higher per step and is useful to compare against off-chain models that do not round.
+    pub async fn simulate_swap_with_rounding<M: Middleware>(
+        &self,
+        token_in: H160,
+        amount_in: U256,
+        rounding_mode: RoundingMode,
+        middleware: Arc<M>,
+    ) -> Result<U256, CFMMError<M>> {
+        if rounding_mode == RoundingMode::Protocol {
+            return self.simulate_swap(token_in, amount_in, middleware).await;
+        }
+
+        if amount_in.is_zero() {
+            return Ok(U256::zero());
+        }
+
+        let zero_for_one = token_in == self.token_a;
+        let mut trace = vec![];
+        let current_state = self
+            .simulate_swap_state(
+                token_in,
+                amount_specified_from_amount_in::<M>(amount_in)?,
+                default_sqrt_price_limit(zero_for_one),
+                150,
+                Some(&mut trace),
+                middleware,
+            )
+            .await?;
+
+        //Each step ends at the price the following step starts from, and the last step ends at the final price of the swap
+        let sqrt_prices_after = trace
+            .iter()
+            .skip(1)
+            .map(|step| step.sqrt_price_start_x_96)
+            .chain(std::iter::once(current_state.sqrt_price_x_96));
+
+        let mut amount_out = U256::zero();
+        for (step, sqrt_price_after) in trace.iter().zip(sqrt_prices_after) {
+            amount_out += if zero_for_one {
+                uniswap_v3_math::sqrt_price_math::_get_amount_1_delta(
+                    sqrt_price_after,
+                    step.sqrt_price_start_x_96,
+                    step.liquidity,
+                    true,
+                )?
+            } else {
+                uniswap_v3_math::sqrt_price_math::_get_amount_0_delta(
+                    step.sqrt_price_start_x_96,
+                    sqrt_price_after,
+                    step.liquidity,
+                    true,
+                )?
+            };
+        }
+
+        Ok(amount_out)
+    }
+
+    //Simulates a swap, returning the amount out and the portion of the LP fee taken by the protocol when feeProtocol is enabled.
+    //The protocol fee does not change the price, liquidity or amount out of the swap. It only leaves the pool's token balances,
+    //so it only matters when accounting for the pool's balances across multiple swaps within a block.
+    //feeProtocol is read from the pool's slot0 along with the swap, as it is not part of the synced pool state.
+    pub async fn simulate_swap_with_protocol_fee<M: Middleware>(
+        &self,
+        token_in: H160,
+        amount_in: U256,
+        middleware: Arc<M>,
+    ) -> Result<(U256, U256), CFMMError<M>> {
+        let fee_protocol = self.get_fee_protocol(middleware.clone()).await?;
+        let (amount_out, trace) = self
+            .simulate_swap_trace(token_in, amount_in, middleware)
+            .await?;
+
+        let fee_protocol = if token_in == self.token_a {
+            fee_protocol % 16
+        } else {
+            fee_protocol >> 4
+        };
+
+        if fee_protocol == 0 {
+            return Ok((amount_out, U256::zero()));
+        }
+
+        //The protocol fee is taken from each step's fee amount, rounding down as the pool contract does
+        let protocol_fee = trace.iter().fold(U256::zero(), |protocol_fee, step| {
+            protocol_fee + step.fee_amount / fee_protocol
+        });
+
+        Ok((amount_out, protocol_fee))
+    }
+
+    //Returns the ratio of this pool's output to the other pool's output for the same trade, where a value above 1 means this pool is deeper.
+    //Outputs are net of each pool's fee, so pools in different fee tiers are compared on what the trader actually receives.
+    pub async fn relative_depth<M: Middleware>(
+        &self,
+        other: &UniswapV3Pool,
+        token_in: H160,
+        amount_in: U256,
+        middleware: Arc<M>,
+    ) -> Result<f64, CFMMError<M>> {
+        let amount_out = self
+            .simulate_swap(token_in, amount_in, middleware.clone())
+            .await?;
+        let other_amount_out = other.simulate_swap(token_in, amount_in, middleware).await?;
+
+        Ok(fixed_point_math::q64_to_f64(fixed_point_math::div_uu(
+            amount_out,
+            other_amount_out,
+        )?))
+    }
+
+    //Simulates a swap, returning the amount of token_in consumed, the amount out, the pool state after the swap and the number of initialized ticks crossed
+    pub async fn simulate_swap_detailed<M: Middleware>(
+        &self,
+        token_in: H160,
+        amount_in: U256,
+        middleware: Arc<M>,
+    ) -> Result<SwapResult, CFMMError<M>> {
+        if amount_in.is_zero() {
+            return Ok(SwapResult {
+                sqrt_price_after: self.sqrt_price,
+                tick_after: self.tick,
+                liquidity_after: self.liquidity,
+                ..Default::default()
+            });
+        }
+
+        let amount_specified = amount_specified_from_amount_in::<M>(amount_in)?;
+        let current_state = self
+            .simulate_swap_state(
+                token_in,
+                amount_specified,
+                default_sqrt_price_limit(token_in == self.token_a),
+                150,
+                None,
+                middleware,
+            )
+            .await?;
+
+        Ok(SwapResult {
+            amount_in: (amount_specified - current_state.amount_specified_remaining).into_raw(),
+            amount_out: (-current_state.amount_calculated).into_raw(),
+            sqrt_price_after: current_state.sqrt_price_x_96,
+            tick_after: current_state.tick,
+            liquidity_after: current_state.liquidity,
+            ticks_crossed: current_state.initialized_ticks_crossed,
+        })
+    }
+
+    //Returns the spot price of base_token at the sqrt price the pool is left at after swapping amount_in of token_in, which is the price
+    //the next infinitesimal trade would get. This differs from the average execution price of the swap itself.
+    pub async fn marginal_price_after<M: Middleware>(
+        &self,
+        token_in: H160,
+        amount_in: U256,
+        base_token: H160,
+        middleware: Arc<M>,
+    ) -> Result<f64, CFMMError<M>> {
+        let swap = self
+            .simulate_swap_detailed(token_in, amount_in, middleware)
+            .await?;
+
+        Ok(UniswapV3Pool {
+            sqrt_price: swap.sqrt_price_after,
+            tick: swap.tick_after,
+            liquidity: swap.liquidity_after,
+            ..*self
+        }
+        .try_calculate_price(base_token)?)
+    }
+
+    //Returns the price of base_token quoted by swapping 1 whole unit of token_in (10^decimals), including the fee and the price impact
+    //of the unit swap, as a convenience for price feeds. If token_in is the base token this is the human readable amount out,
+    //otherwise the price is the inverse of the amount out since base_token is bought.
+    pub async fn quote_unit<M: Middleware>(
+        &self,
+        token_in: H160,
+        base_token: H160,
+        middleware: Arc<M>,
+    ) -> Result<f64, CFMMError<M>> {
+        let (token_in_decimals, token_out_decimals) = if token_in == self.token_a {
+            (self.token_a_decimals, self.token_b_decimals)
+        } else {
+            (self.token_b_decimals, self.token_a_decimals)
+        };
+
+        let amount_out = self
+            .simulate_swap(
+                token_in,
+                U256::from(10).pow(U256::from(token_in_decimals)),
+                middleware,
+            )
+            .await?;
+        let amount_out_human = from_wei(amount_out, token_out_decimals);
+
+        if token_in == base_token {
+            Ok(amount_out_human)
+        } else {
+            Ok(1.0 / amount_out_human)
+        }
+    }
+
+    //Simulates a swap and returns the human readable amounts, price and fee a frontend displays for it in a single call
+    pub async fn simulate_swap_display<M: Middleware>(
+        &self,
+        token_in: H160,
+        amount_in: U256,
+        middleware: Arc<M>,
+    ) -> Result<SwapDisplay, CFMMError<M>> {
+        let swap = self
+            .simulate_swap_detailed(token_in, amount_in, middleware)
+            .await?;
+
+        let (token_in_decimals, token_out_decimals) = if token_in == self.token_a {
+            (self.token_a_decimals, self.token_b_decimals)
+        } else {
+            (self.token_b_decimals, self.token_a_decimals)
+        };
+
+        let amount_in_human = from_wei(swap.amount_in, token_in_decimals);
+        let amount_out_human = from_wei(swap.amount_out, token_out_decimals);
+
+        //The effective price of an empty swap is the mid price, with no impact
+        let mid_price = self.try_calculate_price(token_in)?;
+        let price = if amount_in_human == 0.0 {
+            mid_price
+        } else {
+            amount_out_human / amount_in_human
+        };
+
+        Ok(SwapDisplay {
+            amount_in_human,
+            amount_out_human,
+            price,
+            price_impact: 1.0 - price / mid_price,
+            fee_human: amount_in_human * self.fee as f64 / 1_000_000.0,
+        })
+    }
+
+    pub async fn simulate_swap<M: Middleware>(
+        &self,
+        token_in: H160,
+        amount_in: U256,
+        middleware: Arc<M>,
+    ) -> Result<U256, CFMMError<M>> {
+        self.simulate_swap_with_cache(token_in, amount_in, 150, middleware)
+            .await
+    }
+
+    //Same as simulate_swap, but reads the tick data at block_number rather than the latest block. The pool state itself is not
+    //refetched, so use a pool returned by at_block to simulate against the state of that block.
+    pub async fn simulate_swap_at_block<M: Middleware>(
+        &self,
+        token_in: H160,
+        amount_in: U256,
+        block_number: U64,
+        middleware: Arc<M>,
+    ) -> Result<U256, CFMMError<M>> {
+        let (amount_out, ..) = self
+            .simulate_exact_input(token_in, amount_in, 150, Some(block_number), middleware)
+            .await?;
+
+        Ok(amount_out)
+    }
+
+    //Applies the pending swap logs, e.g. swaps seen in the mempool, to a copy of the pool in order and simulates the swap against the
+    //resulting state, to predict execution after the pending transactions land. Logs emitted by other pools are ignored.
+    pub async fn simulate_swap_pending<M: Middleware>(
+        &self,
+        token_in: H160,
+        amount_in: U256,
+        pending_swaps: &[Log],
+        middleware: Arc<M>,
+    ) -> Result<U256, CFMMError<M>> {
+        let mut pool = *self;
+
+        for swap_log in pending_swaps {
+            if swap_log.address == self.address {
+                pool.update_pool_from_swap_log(swap_log, middleware.clone())
+                    .await?;
+            }
+        }
+
+        pool.simulate_swap(token_in, amount_in, middleware).await
+    }
+
+    //Returns the amount out of swapping each of sizes into the pool on its own, e.g. for rendering a price impact curve.
+    //Tick data is fetched once, as far as the largest size reaches, and every size is simulated against it instead of
+    //fetching tick data for each size. The amounts match simulate_swap for each size at the same block.
+    pub async fn quote_curve<M: Middleware>(
+        &self,
+        token_in: H160,
+        sizes: &[U256],
+        middleware: Arc<M>,
+    ) -> Result<Vec<U256>, CFMMError<M>> {
+        if self.sqrt_price.is_zero() {
+            return Err(CFMMError::PoolNotInitialized(self.address));
+        }
+
+        for size in sizes {
+            amount_specified_from_amount_in::<M>(*size)?;
+        }
+
+        let Some(max_size) = sizes.iter().max().copied() else {
+            return Ok(vec![]);
+        };
+
+        let zero_for_one = token_in == self.token_a;
+        let (min_tick, max_tick) = self.usable_tick_bounds();
+
+        let (mut ticks, block_number) =
+            batch_requests::uniswap_v3::get_uniswap_v3_tick_data_batch_request(
+                self,
+                self.tick,
+                zero_for_one,
+                150,
+                None,
+                middleware.clone(),
+            )
+            .await?;
+
+        //Extend the tick data, pinned to the same block, until the largest size no longer reaches the end of it
+        loop {
+            let last_tick = match ticks.last() {
+                Some(tick_data) => tick_data.tick,
+                None => return Err(CFMMError::NoInitializedTicks),
+            };
+
+            if last_tick <= min_tick || last_tick >= max_tick {
+                break;
+            }
+
+            let swap_result = simulate_swap_pure(
+                self.sqrt_price,
+                self.liquidity,
+                self.tick,
+                self.tick_spacing,
+                self.fee,
+                &ticks,
+                zero_for_one,
+                max_size,
+            )?;
+
+            //Crossing a tick moves the current tick below it in a zero_for_one swap and onto it otherwise
+            let (reached_last_tick, next_tick_start) = if zero_for_one {
+                (swap_result.tick_after < last_tick, last_tick - 1)
+            } else {
+                (swap_result.tick_after >= last_tick, last_tick)
+            };
+
+            if !reached_last_tick {
+                break;
+            }
+
+            let (next_ticks, _) =
+                batch_requests::uniswap_v3::get_uniswap_v3_tick_data_batch_request(
+                    self,
+                    next_tick_start,
+                    zero_for_one,
+                    150,
+                    Some(block_number),
+                    middleware.clone(),
+                )
+                .await?;
+
+            if next_ticks.is_empty() {
+                return Err(CFMMError::NoInitializedTicks);
+            }
+
+            ticks.extend(next_ticks);
+        }
+
+        sizes
+            .iter()
+            .map(|size| {
+                Ok(simulate_swap_pure(
+                    self.sqrt_price,
+                    self.liquidity,
+                    self.tick,
+                    self.tick_spacing,
+                    self.fee,
+                    &ticks,
+                    zero_for_one,
+                    *size,
+                )?
+                .amount_out)
+            })
+            .collect()
+    }
+
+    //Simulates a swap with the token_in address looked up by symbol in the registry
+    pub async fn simulate_swap_by_symbol<M: Middleware>(
+        &self,
+        symbol: &str,
+        amount_in: U256,
+        registry: &TokenRegistry,
+        middleware: Arc<M>,
+    ) -> Result<U256, CFMMError<M>> {
+        let token_in = registry
+            .get(symbol)
+            .ok_or_else(|| CFMMError::UnknownTokenSymbol(symbol.to_owned()))?;
+
+        if self.get_other_token(token_in).is_none() {
+            return Err(CFMMError::TokenNotInPool(self.address, token_in));
+        }
+
+        self.simulate_swap(token_in, amount_in, middleware).await
+    }
+
+    //Returns a stream that emits the price of base_token each time a swap on the pool is observed.
+    //New blocks are polled for swap logs, which are applied to a copy of the pool starting from the block after last_synced_block,
+    //or from the current block if the pool has never been synced. If fetching the logs fails or the stream falls more than
+    //PRICE_STREAM_MAX_BLOCK_RANGE blocks behind, the pool is fully resynced and the resynced price is emitted instead.
+    //If the block number can not be fetched the error is emitted, and the next poll waits PRICE_STREAM_POLL_INTERVAL before retrying.
+    pub fn price_stream<M: Middleware>(
+        &self,
+        base_token: H160,
+        middleware: Arc<M>,
+    ) -> impl Stream<Item = Result<f64, CFMMError<M>>> {
+        let from_block = if self.last_synced_block == 0 {
+            None
+        } else {
+            Some(self.last_synced_block + 1)
+        };
+
+        stream::unfold(
+            (*self, from_block, VecDeque::new(), false, middleware),
+            move |(mut pool, mut from_block, mut prices, mut failed, middleware)| async move {
+                loop {
+                    if let Some(price) = prices.pop_front() {
+                        return Some((price, (pool, from_block, prices, failed, middleware)));
+                    }
+
+                    //Waits before retrying a failed request, so that a consumer polling the stream does not spin while the node is down
+                    if failed {
+                        tokio::time::sleep(PRICE_STREAM_POLL_INTERVAL).await;
+                        failed = false;
+                    }
+
+                    let current_block = match middleware.get_block_number().await {
+                        Ok(block_number) => block_number.as_u64(),
+                        Err(error) => {
+                            return Some((
+                                Err(CFMMError::MiddlewareError(error)),
+                                (pool, from_block, prices, true, middleware),
+                            ))
+                        }
+                    };
+
+                    let start_block = from_block.unwrap_or(current_block);
+                    if current_block < start_block {
+                        tokio::time::sleep(PRICE_STREAM_POLL_INTERVAL).await;
+                        continue;
+                    }
+
+                    let logs = if current_block - start_block < PRICE_STREAM_MAX_BLOCK_RANGE {
+                        middleware
+                            .get_logs(
+                                &Filter::new()
+                                    .topic0(ValueOrArray::Value(SWAP_EVENT_SIGNATURE))
+                                    .address(pool.address)
+                                    .from_block(BlockNumber::Number(U64([start_block])))
+                                    .to_block(BlockNumber::Number(U64([current_block]))),
+                            )
+                            .await
+                            .ok()
+                    } else {
+                        None
+                    };
+
+                    match logs {
+                        //Logs removed in a reorg can not be applied, so the pool is resynced below
+                        Some(logs) if !logs.iter().any(|log| log.removed == Some(true)) => {
+                            for log in logs {
+                                prices.push_back(pool.decode_swap_log(&log).and_then(
+                                    |(_, _, sqrt_price, liquidity, tick)| {
+                                        pool.sqrt_price = sqrt_price;
+                                        pool.liquidity = liquidity;
+                                        pool.tick = tick;
+                                        Ok(pool.try_calculate_price(base_token)?)
+                                    },
+                                ));
+                            }
+                        }
+
+                        //Logs may have been missed or reorged, so the pool is resynced from its current state
+                        _ => prices.push_back(
+                            pool.sync_pool(middleware.clone())
+                                .await
+                                .and_then(|_| Ok(pool.try_calculate_price(base_token)?)),
+                        ),
+                    }
+
+                    from_block = Some(current_block + 1);
+                }
+            },
+        )
+    }
+
+    //Captures the pool state along with num_ticks of tick data in each direction from the current tick, so that swaps can be simulated offline
+    pub async fn freeze<M: Middleware>(
+        &self,
+        num_ticks: u16,
+        middleware: Arc<M>,
+    ) -> Result<FrozenPool, CFMMError<M>> {
+        let (ticks, _) =
+            batch_requests::uniswap_v3::get_tick_data_concurrently(&[*self], num_ticks, middleware)
+                .await?
+                .remove(0);
+
+        Ok(FrozenPool {
+            token_a: self.token_a,
+            token_b: self.token_b,
+            fee: self.fee,
+            tick_spacing: self.tick_spacing,
+            sqrt_price: self.sqrt_price,
+            tick: self.tick,
+            liquidity: self.liquidity,
+            ticks,
+        })
+    }
+
+    //Simulates the swap fully offline from a hypothetical starting state rather than the pool's stored sqrt price, tick and liquidity,
+    //e.g. for what-if analysis after hypothetical mints and burns. tick_data is the pool's tick data sorted by tick, like FrozenPool::ticks.
+    pub fn simulate_swap_from_state(
+        &self,
+        start_sqrt_price: U256,
+        start_tick: i32,
+        start_liquidity: u128,
+        token_in: H160,
+        amount_in: U256,
+        tick_data: &[TickData],
+    ) -> Result<U256, ArithmeticError> {
+        let zero_for_one = token_in == self.token_a;
+        let ticks = ticks_in_swap_direction(tick_data, start_tick, zero_for_one);
+
+        Ok(simulate_swap_pure(
+            start_sqrt_price,
+            start_liquidity,
+            start_tick,
+            self.tick_spacing,
+            self.fee,
+            &ticks,
+            zero_for_one,
+            amount_in,
+        )?
+        .amount_out)
+    }
+
+    //Re-syncs the pool if its state is more than max_block_age blocks old before simulating the swap
+    pub async fn simulate_swap_fresh<M: Middleware>(
+        &mut self,
+        token_in: H160,
+        amount_in: U256,
+        max_block_age: u64,
+        middleware: Arc<M>,
+    ) -> Result<U256, CFMMError<M>> {
+        let current_block = middleware
+            .get_block_number()
+            .await
+            .map_err(CFMMError::MiddlewareError)?
+            .as_u64();
+
+        if current_block.saturating_sub(self.last_synced_block) > max_block_age {
+            self.sync_pool(middleware.clone()).await?;
+        }
+
+        self.simulate_swap(token_in, amount_in, middleware).await
+    }
+
+    pub async fn get_word<M: Middleware>(
+        &self,
+        word_pos: i16,
+        block_number: Option<U64>,
+        middleware: Arc<M>,
+    ) -> Result<U256, CFMMError<M>> {
+        if block_number.is_some() {
+            //TODO: in the future, create a batch call to get this and liquidity net within the same call
+
+            Ok(abi::IUniswapV3Pool::new(self.address, middleware.clone())
+                .tick_bitmap(word_pos)
+                .block(block_number.unwrap())
+                .call()
+                .await?)
+        } else {
+            //TODO: in the future, create a batch call to get this and liquidity net within the same call
+            Ok(abi::IUniswapV3Pool::new(self.address, middleware.clone())
+                .tick_bitmap(word_pos)
+                .call()
+                .await?)
+        }
+    }
+
+    //Returns every initialized tick in the pool along with its liquidity_net, sorted by tick, as of the latest block.
+    //This scans every word of the tick bitmap, which is one request per word plus one per initialized tick. Pools with a
+    //tick spacing of 1 have close to 7000 words, so prefer simulating with the tick data batch request where possible.
+    pub async fn get_all_initialized_ticks<M: Middleware>(
+        &self,
+        middleware: Arc<M>,
+    ) -> Result<Vec<(i32, i128)>, CFMMError<M>> {
+        let block_number = middleware
+            .get_block_number()
+            .await
+            .map_err(CFMMError::MiddlewareError)?;
+
+        let (min_tick, max_tick) = self.usable_tick_bounds();
+        let (min_word, _) = self.calculate_word_pos_bit_pos(self.calculate_compressed(min_tick)?);
+        let (max_word, _) = self.calculate_word_pos_bit_pos(self.calculate_compressed(max_tick)?);
+
+        let words = stream::iter(min_word..=max_word)
+            .map(|word_pos| {
+                let middleware = middleware.clone();
+                async move {
+                    self.get_word(word_pos, Some(block_number), middleware)
+                        .await
+                        .map(|word| (word_pos, word))
+                }
+            })
+            .buffered(MAX_CONCURRENT_REQUESTS)
+            .collect::<Vec<_>>()
+            .await;
+
+        let mut ticks = vec![];
+        for word in words {
+            let (word_pos, word) = word?;
+
+            for bit_pos in 0..256 {
+                if word.bit(bit_pos) {
+                    let tick = (word_pos as i32 * 256 + bit_pos as i32) * self.tick_spacing;
+                    if (min_tick..=max_tick).contains(&tick) {
+                        ticks.push(tick);
+                    }
+                }
+            }
+        }
+
+        let v3_pool = abi::IUniswapV3Pool::new(self.address, middleware);
+        let initialized_ticks = stream::iter(ticks)
+            .map(|tick| {
+                let v3_pool = &v3_pool;
+                async move {
+                    let tick_info = v3_pool.ticks(tick).block(block_number).call().await?;
+                    Ok((tick, tick_info.1))
+                }
+            })
+            .buffered(MAX_CONCURRENT_REQUESTS)
+            .collect::<Vec<Result<(i32, i128), CFMMError<M>>>>()
+            .await;
+
+        initialized_ticks.into_iter().collect()
+    }
+
+    //Returns the pool's active liquidity at every step blocks from from_block up to and including to_block, sorted by block number.
+    //Useful for backtesting how the depth of the pool evolved. Historical reads have the same node requirements as at_block.
+    pub async fn liquidity_series<M: Middleware>(
+        &self,
+        from_block: U64,
+        to_block: U64,
+        step: u64,
+        middleware: Arc<M>,
+    ) -> Result<Vec<(U64, u128)>, CFMMError<M>> {
+        let blocks = (from_block.as_u64()..=to_block.as_u64())
+            .step_by(step.max(1) as usize)
+            .map(U64::from);
+
+        let v3_pool = abi::IUniswapV3Pool::new(self.address, middleware);
+        let liquidity_series = stream::iter(blocks)
+            .map(|block_number| {
+                let v3_pool = &v3_pool;
+                async move {
+                    let liquidity = v3_pool.liquidity().block(block_number).call().await?;
+                    Ok((block_number, liquidity))
+                }
+            })
+            .buffered(MAX_CONCURRENT_REQUESTS)
+            .collect::<Vec<Result<(U64, u128), CFMMError<M>>>>()
+            .await;
+
+        liquidity_series.into_iter().collect()
+    }
+
+    //Returns the average price of base_token at every step blocks from from_block up to and including to_block, weighting each sample
+    //by the pool's active liquidity at that block, so that prices the pool could barely trade at count for less. Each sample is read
+    //with at_block.
+    pub async fn lwap<M: Middleware>(
+        &self,
+        from_block: U64,
+        to_block: U64,
+        step: u64,
+        base_token: H160,
+        middleware: Arc<M>,
+    ) -> Result<f64, CFMMError<M>> {
+        let blocks = (from_block.as_u64()..=to_block.as_u64())
+            .step_by(step.max(1) as usize)
+            .map(U64::from);
+
+        let samples = stream::iter(blocks)
+            .map(|block_number| {
+                let middleware = middleware.clone();
+                async move {
+                    let pool = self.at_block(block_number, middleware).await?;
+                    Ok((pool.try_calculate_price(base_token)?, pool.liquidity))
+                }
+            })
+            .buffered(MAX_CONCURRENT_REQUESTS)
+            .collect::<Vec<Result<(f64, u128), CFMMError<M>>>>()
+            .await;
+
+        let mut weighted_price_sum = 0.0;
+        let mut liquidity_sum = 0.0;
+        for sample in samples {
+            let (price, liquidity) = sample?;
+            weighted_price_sum += price * liquidity as f64;
+            liquidity_sum += liquidity as f64;
+        }
+
+        if liquidity_sum == 0.0 {
+            return Err(CFMMError::NoSampledLiquidity(self.address));
+        }
+
+        Ok(weighted_price_sum / liquidity_sum)
+    }
+
+    //Returns the tick divided by the tick spacing, rounded towards negative infinity.
+    //Pools created from an event log have no tick spacing until their data is populated, which is an error.
+    pub fn calculate_compressed(&self, tick: i32) -> Result<i32, ArithmeticError> {
+        if self.tick_spacing == 0 {
+            return Err(ArithmeticError::UninitializedTickSpacing);
+        }
+
+        if tick < 0 && tick % self.tick_spacing != 0 {
+            Ok((tick / self.tick_spacing) - 1)
+        } else {
+            Ok(tick / self.tick_spacing)
         }
     }
 
@@ -759,98 +2603,4601 @@ impl UniswapV3Pool {
         uniswap_v3_math::tick_bit_map::position(compressed)
     }
 
-    pub async fn simulate_swap_mut<M: Middleware>(
-        &mut self,
-        token_in: H160,
-        amount_in: U256,
-        middleware: Arc<M>,
-    ) -> Result<U256, CFMMError<M>> {
-        self.simulate_swap_mut_with_cache(token_in, amount_in, 150, middleware)
-            .await
-    }
+    //Cancellation safe, see simulate_swap_mut_with_cache
+    pub async fn simulate_swap_mut<M: Middleware>(
+        &mut self,
+        token_in: H160,
+        amount_in: U256,
+        middleware: Arc<M>,
+    ) -> Result<U256, CFMMError<M>> {
+        self.simulate_swap_mut_with_cache(token_in, amount_in, 150, middleware)
+            .await
+    }
+
+    //Applies each (token_in, amount_in) swap in order, with every swap seeing the pool state left by the previous one,
+    //and returns the amount out of each swap. Useful for modeling several swaps landing in the same block, e.g. a sandwich.
+    //The swaps are simulated on a copy of the pool, so the pool is only updated once every swap has succeeded.
+    pub async fn simulate_swaps_sequential<M: Middleware>(
+        &mut self,
+        swaps: &[(H160, U256)],
+        middleware: Arc<M>,
+    ) -> Result<Vec<U256>, CFMMError<M>> {
+        let mut pool = *self;
+        let mut amounts_out = Vec::with_capacity(swaps.len());
+
+        for (token_in, amount_in) in swaps {
+            amounts_out.push(
+                pool.simulate_swap_mut(*token_in, *amount_in, middleware.clone())
+                    .await?,
+            );
+        }
+
+        *self = pool;
+
+        Ok(amounts_out)
+    }
+
+    //Swaps a small amount of token_a for token_b and back, returning false if the round trip loses more than the two fees it pays.
+    //Honest pools return close to the amount in less the fees, so a larger loss points to inconsistent tick data, as served by
+    //honeypot pools, or a pool that should not be routed through.
+    pub async fn simulate_roundtrip_sanity<M: Middleware>(
+        &self,
+        middleware: Arc<M>,
+    ) -> Result<bool, CFMMError<M>> {
+        if self.sqrt_price.is_zero() {
+            return Err(CFMMError::PoolNotInitialized(self.address));
+        }
+
+        //0.01% of the virtual token_a reserve, small enough that the price impact of the round trip is negligible
+        let (reserve_0, _) = self.calculate_virtual_reserves()?;
+        let amount_in = U256::from(reserve_0 / 10_000);
+
+        if amount_in.is_zero() {
+            return Ok(false);
+        }
+
+        let mut pool = *self;
+        let amount_out = pool
+            .simulate_swap_mut(self.token_a, amount_in, middleware.clone())
+            .await?;
+        let amount_recovered = pool
+            .simulate_swap_mut(self.token_b, amount_out, middleware)
+            .await?;
+
+        //Allow for the fee on both swaps plus 1 bip for rounding
+        let max_loss = amount_in * U256::from(2 * self.fee as u64 + 100) / U256::from(1_000_000);
+
+        Ok(amount_recovered + max_loss >= amount_in)
+    }
+
+    pub fn swap_calldata(
+        &self,
+        recipient: H160,
+        zero_for_one: bool,
+        amount_specified: I256,
+        sqrt_price_limit_x_96: U256,
+        calldata: Vec<u8>,
+    ) -> Bytes {
+        let input_tokens = vec![
+            Token::Address(recipient),
+            Token::Bool(zero_for_one),
+            Token::Int(amount_specified.into_raw()),
+            Token::Uint(sqrt_price_limit_x_96),
+            Token::Bytes(calldata),
+        ];
+
+        abi::IUNISWAPV3POOL_ABI
+            .function("swap")
+            .unwrap()
+            .encode_input(&input_tokens)
+            .expect("Could not encode swap calldata")
+    }
+}
+
+//Returns the address the Uniswap V3 factory deploys the pool for the token pair and fee to
+pub fn compute_pool_address(factory: H160, token_a: H160, token_b: H160, fee: u32) -> H160 {
+    let (token_0, token_1) = if token_a < token_b {
+        (token_a, token_b)
+    } else {
+        (token_b, token_a)
+    };
+
+    let salt = ethers::utils::keccak256(ethers::abi::encode(&[
+        Token::Address(token_0),
+        Token::Address(token_1),
+        Token::Uint(U256::from(fee)),
+    ]));
+
+    ethers::utils::get_create2_address_from_hash(factory, salt, POOL_INIT_CODE_HASH)
+}
+
+//Encodes the calldata for SwapRouter02's exactInputSingle, swapping exactly params.amount_in through a single pool.
+//SwapRouter02 does not take a deadline here, wrap the calldata in a multicall with a deadline if one is needed.
+pub fn swap_router_exact_input_single_calldata(params: ExactInputSingleParams) -> Bytes {
+    abi::ISWAPROUTER02_ABI
+        .function("exactInputSingle")
+        .unwrap()
+        .encode_input(&[params.into_token()])
+        .expect("Could not encode exactInputSingle calldata")
+}
+
+//Encodes a multi-hop swap path as expected by the SwapRouter, token_0 | fee_0 | token_1 | fee_1 | ... | token_n,
+//with each fee packed into 3 bytes. Panics unless there is exactly one more token than fees.
+pub fn encode_path(tokens: &[H160], fees: &[u32]) -> Bytes {
+    assert_eq!(
+        tokens.len(),
+        fees.len() + 1,
+        "A path must have exactly one more token than fees"
+    );
+
+    let mut path = Vec::with_capacity(tokens.len() * 20 + fees.len() * 3);
+    for (i, token) in tokens.iter().enumerate() {
+        path.extend_from_slice(token.as_bytes());
+
+        if let Some(fee) = fees.get(i) {
+            path.extend_from_slice(&fee.to_be_bytes()[1..]);
+        }
+    }
+
+    path
+}
+
+//Encodes the calldata for a multi-hop SwapRouter02 exactInput along path (see encode_path), wrapped in
+//multicall(deadline, data) so that the swap reverts if it is not included by deadline.
+pub fn exact_input_calldata(
+    path: Bytes,
+    recipient: H160,
+    deadline: U256,
+    amount_in: U256,
+    amount_out_min: U256,
+) -> Bytes {
+    let params = ExactInputParams {
+        path: path.into(),
+        recipient,
+        amount_in,
+        amount_out_minimum: amount_out_min,
+    };
+
+    let exact_input_calldata = abi::ISWAPROUTER02_ABI
+        .function("exactInput")
+        .unwrap()
+        .encode_input(&[params.into_token()])
+        .expect("Could not encode exactInput calldata");
+
+    abi::ISWAPROUTER02_ABI
+        .function("multicall")
+        .unwrap()
+        .encode_input(&[
+            Token::Uint(deadline),
+            Token::Array(vec![Token::Bytes(exact_input_calldata)]),
+        ])
+        .expect("Could not encode multicall calldata")
+}
+
+//Looks up the pool for each (token_a, token_b, fee) through the factory's getPool and populates the pools that exist with pool data
+//batch requests. Results are returned in the same order as pairs, with None for pairs that do not have a pool.
+pub async fn get_pools_from_token_pairs<M: Middleware>(
+    pairs: &[(H160, H160, u32)],
+    factory: H160,
+    middleware: Arc<M>,
+) -> Result<Vec<Option<UniswapV3Pool>>, CFMMError<M>> {
+    let factory = abi::IUniswapV3Factory::new(factory, middleware.clone());
+
+    let addresses = stream::iter(pairs)
+        .map(|&(token_a, token_b, fee)| {
+            let factory = &factory;
+            async move { factory.get_pool(token_a, token_b, fee).call().await }
+        })
+        .buffered(MAX_CONCURRENT_REQUESTS)
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut pools = addresses
+        .iter()
+        .filter(|address| !address.is_zero())
+        .map(|&address| {
+            Pool::UniswapV3(UniswapV3Pool {
+                address,
+                ..Default::default()
+            })
+        })
+        .collect::<Vec<_>>();
+
+    for pools in pools.chunks_mut(POOL_DATA_BATCH_SIZE) {
+        batch_requests::uniswap_v3::get_pool_data_batch_request(pools, middleware.clone()).await?;
+    }
+
+    let mut pools = pools.into_iter();
+    Ok(addresses
+        .iter()
+        .map(|address| {
+            if address.is_zero() {
+                return None;
+            }
+
+            match pools.next() {
+                Some(Pool::UniswapV3(pool)) if pool.data_is_populated() => Some(pool),
+                _ => None,
+            }
+        })
+        .collect())
+}
+
+//Returns the populated pool of token_a and token_b for every canonical fee tier that has one, in fee tier order,
+//so that routers can consider every tier of the pair.
+pub async fn get_all_fee_tier_pools<M: Middleware>(
+    token_a: H160,
+    token_b: H160,
+    factory: H160,
+    middleware: Arc<M>,
+) -> Result<Vec<UniswapV3Pool>, CFMMError<M>> {
+    let pairs = FeeTier::CANONICAL
+        .iter()
+        .map(|fee_tier| (token_a, token_b, fee_tier.to_u32()))
+        .collect::<Vec<_>>();
+
+    Ok(get_pools_from_token_pairs(&pairs, factory, middleware)
+        .await?
+        .into_iter()
+        .flatten()
+        .collect())
+}
+
+//Syncs each pool and calculates its price in terms of base_token, with at most max_concurrency requests in flight.
+//Results are returned in the same order as pools.
+pub async fn fetch_prices<M: Middleware>(
+    pools: &[UniswapV3Pool],
+    base_token: H160,
+    max_concurrency: usize,
+    middleware: Arc<M>,
+) -> Vec<Result<f64, CFMMError<M>>> {
+    let mut indexed_prices = stream::iter(pools.iter().copied().enumerate())
+        .map(|(i, mut pool)| {
+            let middleware = middleware.clone();
+            async move {
+                let price =
+                    batch_requests::uniswap_v3::sync_v3_pool_batch_request(&mut pool, middleware)
+                        .await
+                        .and_then(|_| Ok(pool.try_calculate_price(base_token)?));
+
+                (i, price)
+            }
+        })
+        .buffer_unordered(max_concurrency.max(1))
+        .collect::<Vec<_>>()
+        .await;
+
+    indexed_prices.sort_unstable_by_key(|(i, _)| *i);
+    indexed_prices.into_iter().map(|(_, price)| price).collect()
+}
+
+//Calculates the price of each pool in terms of base_token at block_number, for building historical price series.
+//Pool data is fetched in a single batch request, falling back to a slot0 call per pool if the batch request fails.
+pub async fn snapshot_prices_at_block<M: Middleware>(
+    pools: &[UniswapV3Pool],
+    base_token: H160,
+    block_number: U64,
+    middleware: Arc<M>,
+) -> Vec<Result<f64, CFMMError<M>>> {
+    let mut snapshot = pools
+        .iter()
+        .map(|pool| {
+            Pool::UniswapV3(UniswapV3Pool {
+                sqrt_price: U256::zero(),
+                ..*pool
+            })
+        })
+        .collect::<Vec<Pool>>();
+
+    if batch_requests::uniswap_v3::get_pool_data_batch_request_at_block(
+        &mut snapshot,
+        block_number,
+        middleware.clone(),
+    )
+    .await
+    .is_ok()
+    {
+        return snapshot
+            .iter()
+            .map(|pool| match pool {
+                //A zero sqrt price signals that the pool could not be read at the block
+                Pool::UniswapV3(pool) if !pool.sqrt_price.is_zero() => {
+                    Ok(pool.try_calculate_price(base_token)?)
+                }
+                _ => Err(CFMMError::PoolDataError),
+            })
+            .collect();
+    }
+
+    future::join_all(pools.iter().map(|pool| {
+        let middleware = middleware.clone();
+        async move {
+            let (sqrt_price, ..) = abi::IUniswapV3Pool::new(pool.address, middleware)
+                .slot_0()
+                .block(block_number)
+                .call()
+                .await?;
+
+            Ok(UniswapV3Pool {
+                sqrt_price,
+                ..*pool
+            }
+            .try_calculate_price(base_token)?)
+        }
+    }))
+    .await
+}
+
+//Returns the average price of base_token across pools for the same pair, weighted by each pool's active liquidity so that
+//thin pools have little influence on the result. Pools whose price can not be calculated are skipped.
+//Returns zero if none of the pools have active liquidity.
+pub fn aggregate_mid_price(pools: &[UniswapV3Pool], base_token: H160) -> f64 {
+    let (weighted_price, total_liquidity) = pools
+        .iter()
+        .filter_map(|pool| Some((pool.try_calculate_price(base_token).ok()?, pool.liquidity)))
+        .fold(
+            (0.0, 0.0),
+            |(weighted_price, total_liquidity), (price, liquidity)| {
+                let liquidity = liquidity as f64;
+                (
+                    weighted_price + price * liquidity,
+                    total_liquidity + liquidity,
+                )
+            },
+        );
+
+    if total_liquidity == 0.0 {
+        return 0.0;
+    }
+
+    weighted_price / total_liquidity
+}
+
+//Returns the min and max ticks that are a multiple of tick_spacing
+fn usable_tick_bounds(tick_spacing: i32) -> (i32, i32) {
+    if tick_spacing <= 0 {
+        return (MIN_TICK, MAX_TICK);
+    }
+
+    (
+        MIN_TICK / tick_spacing * tick_spacing,
+        MAX_TICK / tick_spacing * tick_spacing,
+    )
+}
+
+//Returns the most steps a swap can take. Each step moves the price to at least the next tick spacing in the direction of the swap,
+//so a swap that takes more steps than there are tick spacings between the usable tick bounds is not making progress.
+fn max_swap_iterations(tick_spacing: i32) -> usize {
+    let (min_tick, max_tick) = usable_tick_bounds(tick_spacing);
+    ((max_tick - min_tick) / tick_spacing.max(1)) as usize + 1
+}
+
+//Simulates an exact input swap against the provided tick data without any middleware, so that the swap math can be driven by
+//property tests and fuzzers. ticks must be ordered in the direction of the swap starting from the current tick, as returned
+//by the tick data batch request. Once ticks is exhausted, the swap continues with the remaining liquidity up to the usable tick bounds.
+#[allow(clippy::too_many_arguments)]
+pub fn simulate_swap_pure(
+    sqrt_price: U256,
+    liquidity: u128,
+    tick: i32,
+    tick_spacing: i32,
+    fee: u32,
+    ticks: &[TickData],
+    zero_for_one: bool,
+    amount_in: U256,
+) -> Result<SwapResult, ArithmeticError> {
+    //Amounts that do not fit in an I256 would be interpreted as an exact output swap
+    if amount_in > I256::MAX.into_raw() {
+        return Err(ArithmeticError::AmountTooLarge(amount_in));
+    }
+
+    validate_fee(fee)?;
+
+    let (min_tick, max_tick) = usable_tick_bounds(tick_spacing);
+    let sqrt_price_limit_x_96 = default_sqrt_price_limit(zero_for_one);
+    let last_tick = TickData {
+        initialized: false,
+        tick: if zero_for_one { min_tick } else { max_tick },
+        liquidity_net: 0,
+    };
+
+    let amount_specified = I256::from_raw(amount_in);
+    let mut current_state = CurrentState {
+        sqrt_price_x_96: sqrt_price,
+        amount_calculated: I256::zero(),
+        amount_specified_remaining: amount_specified,
+        tick,
+        liquidity,
+        initialized_ticks_crossed: 0,
+    };
+
+    let mut tick_data_iter = ticks.iter();
+    while current_state.amount_specified_remaining != I256::zero()
+        && current_state.sqrt_price_x_96 != sqrt_price_limit_x_96
+    {
+        let next_tick_data = tick_data_iter.next().unwrap_or(&last_tick);
+
+        let (_, reached_tick_bound) = swap_step(
+            &mut current_state,
+            next_tick_data,
+            zero_for_one,
+            sqrt_price_limit_x_96,
+            fee,
+            (min_tick, max_tick),
+            || ArithmeticError::LiquidityNetOverflow,
+        )?;
+
+        if reached_tick_bound {
+            break;
+        }
+    }
+
+    Ok(SwapResult {
+        amount_in: (amount_specified - current_state.amount_specified_remaining).into_raw(),
+        amount_out: (-current_state.amount_calculated).into_raw(),
+        sqrt_price_after: current_state.sqrt_price_x_96,
+        tick_after: current_state.tick,
+        liquidity_after: current_state.liquidity,
+        ticks_crossed: current_state.initialized_ticks_crossed,
+    })
+}
+
+//Computes a single step of the swap loop towards next_tick_data, updating current_state in place.
+//Returns the step along with whether the swap reached the usable tick bounds, past which it cannot progress.
+fn swap_step<E: From<UniswapV3MathError> + From<ArithmeticError>>(
+    current_state: &mut CurrentState,
+    next_tick_data: &TickData,
+    zero_for_one: bool,
+    sqrt_price_limit_x_96: U256,
+    fee: u32,
+    (min_tick, max_tick): (i32, i32),
+    liquidity_net_overflow: impl Fn() -> E,
+) -> Result<(StepComputations, bool), E> {
+    //Initialize a new step struct to hold the dynamic state of the pool at each step
+    let mut step = StepComputations {
+        sqrt_price_start_x_96: current_state.sqrt_price_x_96, //Set the sqrt_price_start_x_96 to the current sqrt_price_x_96
+        liquidity: current_state.liquidity, //Liquidity that is active for the step
+        ..Default::default()
+    };
+
+    //Swapping down from a tick boundary crosses the boundary tick first without swapping anything, like the pool contract
+    step.tick_next = next_tick_data.tick;
+    step.initialized = next_tick_data.initialized;
+
+    // ensure that we do not overshoot the usable min/max tick, as the tick bitmap is not aware of these bounds
+    step.tick_next = step.tick_next.clamp(min_tick, max_tick);
+
+    //Get the next sqrt price from the input amount
+    step.sqrt_price_next_x96 = uniswap_v3_math::tick_math::get_sqrt_ratio_at_tick(step.tick_next)?;
+
+    //Target spot price
+    let swap_target_sqrt_ratio = if zero_for_one {
+        if step.sqrt_price_next_x96 < sqrt_price_limit_x_96 {
+            sqrt_price_limit_x_96
+        } else {
+            step.sqrt_price_next_x96
+        }
+    } else if step.sqrt_price_next_x96 > sqrt_price_limit_x_96 {
+        sqrt_price_limit_x_96
+    } else {
+        step.sqrt_price_next_x96
+    };
+
+    //Compute swap step and update the current state
+    (
+        current_state.sqrt_price_x_96,
+        step.amount_in,
+        step.amount_out,
+        step.fee_amount,
+    ) = uniswap_v3_math::swap_math::compute_swap_step(
+        current_state.sqrt_price_x_96,
+        swap_target_sqrt_ratio,
+        current_state.liquidity,
+        current_state.amount_specified_remaining,
+        fee,
+    )?;
+
+    //Decrement the amount remaining to be swapped and amount received from the step
+    apply_step_amounts(current_state, &step)?;
+
+    //No position can provide liquidity past the usable tick bounds, so the swap cannot progress once it reaches them
+    let reached_tick_bound = current_state.sqrt_price_x_96 == step.sqrt_price_next_x96
+        && (step.tick_next == min_tick || step.tick_next == max_tick);
+
+    //If the price moved all the way to the next price, recompute the liquidity change for the next iteration
+    if current_state.sqrt_price_x_96 == step.sqrt_price_next_x96 {
+        if next_tick_data.initialized {
+            current_state.initialized_ticks_crossed += 1;
+
+            let mut liquidity_net = next_tick_data.liquidity_net;
+
+            // we are on a tick boundary, and the next tick is initialized, so we must charge a protocol fee
+            if zero_for_one {
+                liquidity_net = liquidity_net
+                    .checked_neg()
+                    .ok_or_else(&liquidity_net_overflow)?;
+            }
+
+            current_state.liquidity = if liquidity_net < 0 {
+                current_state
+                    .liquidity
+                    .checked_sub(liquidity_net.unsigned_abs())
+            } else {
+                current_state.liquidity.checked_add(liquidity_net as u128)
+            }
+            .ok_or_else(&liquidity_net_overflow)?;
+        }
+        //Increment the current tick. tick_next is clamped to the usable tick bounds, so stepping below it can not overflow
+        current_state.tick = if zero_for_one {
+            step.tick_next - 1
+        } else {
+            step.tick_next
+        }
+        //If the current_state sqrt price is not equal to the step sqrt price, then we are not on the same tick.
+        //Update the current_state.tick to the tick at the current_state.sqrt_price_x_96
+    } else if current_state.sqrt_price_x_96 != step.sqrt_price_start_x_96 {
+        current_state.tick =
+            uniswap_v3_math::tick_math::get_tick_at_sqrt_ratio(current_state.sqrt_price_x_96)?;
+    }
+
+    Ok((step, reached_tick_bound))
+}
+
+//Applies the amounts of a step to the amount remaining and the amount calculated. For an exact input swap the amount remaining is
+//positive and decremented by the amount in, while an exact output swap has a negative amount remaining that is incremented by the amount out.
+//The pool contract does this with unchecked arithmetic since a step never consumes more than the amount remaining,
+//so an overflow here means the inputs are corrupt and is surfaced as an error instead of wrapping. A step that consumes more
+//than the amount remaining leaves zero remaining rather than flipping its sign, which would turn the rest of the swap into the
+//other kind of swap instead of ending the loop.
+fn apply_step_amounts(
+    current_state: &mut CurrentState,
+    step: &StepComputations,
+) -> Result<(), ArithmeticError> {
+    let step_amount_in = step
+        .amount_in
+        .checked_add(step.fee_amount)
+        .and_then(|amount| I256::try_from(amount).ok())
+        .ok_or(ArithmeticError::AmountOverflow(step.amount_in))?;
+    let step_amount_out = I256::try_from(step.amount_out)
+        .map_err(|_| ArithmeticError::AmountOverflow(step.amount_out))?;
+
+    if current_state.amount_specified_remaining.is_negative() {
+        current_state.amount_specified_remaining = current_state
+            .amount_specified_remaining
+            .checked_add(step_amount_out)
+            .ok_or(ArithmeticError::AmountOverflow(step.amount_out))?
+            .min(I256::zero());
+        current_state.amount_calculated = current_state
+            .amount_calculated
+            .checked_add(step_amount_in)
+            .ok_or(ArithmeticError::AmountOverflow(step.amount_in))?;
+    } else {
+        current_state.amount_specified_remaining = current_state
+            .amount_specified_remaining
+            .checked_sub(step_amount_in)
+            .ok_or(ArithmeticError::AmountOverflow(step.amount_in))?
+            .max(I256::zero());
+        current_state.amount_calculated = current_state
+            .amount_calculated
+            .checked_sub(step_amount_out)
+            .ok_or(ArithmeticError::AmountOverflow(step.amount_out))?;
+    }
+
+    Ok(())
+}
+
+//Decodes amount0, amount1, sqrtPriceX96, liquidity and tick from the data of a Swap log
+fn decode_swap_log_data<M: Middleware>(
+    swap_log: &Log,
+) -> Result<(I256, I256, U256, u128, i32), CFMMError<M>> {
+    let log_data = decode(
+        &[
+            ParamType::Int(256),  //amount0
+            ParamType::Int(256),  //amount1
+            ParamType::Uint(160), //sqrtPriceX96
+            ParamType::Uint(128), //liquidity
+            ParamType::Int(24),
+        ],
+        &swap_log.data,
+    )
+    .map_err(|_| {
+        CFMMError::AbiDecode(
+            swap_log.address,
+            swap_log.topics.first().copied().unwrap_or_default(),
+        )
+    })?;
+
+    let amount_0 = I256::from_raw(log_data[0].to_owned().into_int().unwrap());
+    let amount_1 = I256::from_raw(log_data[1].to_owned().into_int().unwrap());
+    let sqrt_price = log_data[2].to_owned().into_uint().unwrap();
+    let liquidity = log_data[3].to_owned().into_uint().unwrap().as_u128();
+    let tick = decode_i24(log_data[4].to_owned().into_int().unwrap());
+
+    Ok((amount_0, amount_1, sqrt_price, liquidity, tick))
+}
+
+//Parses a Swap log into a SwapEvent, including the sender and recipient indexed in its topics
+pub fn parse_swap_event<M: Middleware>(swap_log: &Log) -> Result<SwapEvent, CFMMError<M>> {
+    let abi_decode_error = || {
+        CFMMError::AbiDecode(
+            swap_log.address,
+            swap_log.topics.first().copied().unwrap_or_default(),
+        )
+    };
+
+    let (sender, recipient) = match swap_log.topics.as_slice() {
+        [signature, sender, recipient] if *signature == SWAP_EVENT_SIGNATURE => (
+            H160::from_slice(&sender[12..]),
+            H160::from_slice(&recipient[12..]),
+        ),
+        _ => return Err(abi_decode_error()),
+    };
+
+    let (amount0, amount1, sqrt_price, liquidity, tick) = decode_swap_log_data(swap_log)?;
+
+    Ok(SwapEvent {
+        amount0,
+        amount1,
+        sqrt_price,
+        liquidity,
+        tick,
+        sender,
+        recipient,
+    })
+}
+
+//Amount of token_0 held by liquidity between two sqrt prices, where sqrt_price_a is the lower one, rounded down
+fn amount_0_for_liquidity(
+    sqrt_price_a: U256,
+    sqrt_price_b: U256,
+    liquidity: u128,
+) -> Result<U256, ArithmeticError> {
+    Ok(uniswap_v3_math::full_math::mul_div(
+        U256::from(liquidity) << 96,
+        sqrt_price_b - sqrt_price_a,
+        sqrt_price_b,
+    )? / sqrt_price_a)
+}
+
+//Amount of token_1 held by liquidity between two sqrt prices, where sqrt_price_a is the lower one, rounded down
+fn amount_1_for_liquidity(
+    sqrt_price_a: U256,
+    sqrt_price_b: U256,
+    liquidity: u128,
+) -> Result<U256, ArithmeticError> {
+    Ok(uniswap_v3_math::full_math::mul_div(
+        U256::from(liquidity),
+        sqrt_price_b - sqrt_price_a,
+        U256::one() << 96,
+    )?)
+}
+
+//Liquidity that amount_0 of token_0 provides between two sqrt prices, where sqrt_price_a is the lower one, rounded down
+fn liquidity_for_amount_0(
+    sqrt_price_a: U256,
+    sqrt_price_b: U256,
+    amount_0: U256,
+) -> Result<u128, ArithmeticError> {
+    let intermediate =
+        uniswap_v3_math::full_math::mul_div(sqrt_price_a, sqrt_price_b, U256::one() << 96)?;
+    liquidity_to_u128(uniswap_v3_math::full_math::mul_div(
+        amount_0,
+        intermediate,
+        sqrt_price_b - sqrt_price_a,
+    )?)
+}
+
+//Liquidity that amount_1 of token_1 provides between two sqrt prices, where sqrt_price_a is the lower one, rounded down
+fn liquidity_for_amount_1(
+    sqrt_price_a: U256,
+    sqrt_price_b: U256,
+    amount_1: U256,
+) -> Result<u128, ArithmeticError> {
+    liquidity_to_u128(uniswap_v3_math::full_math::mul_div(
+        amount_1,
+        U256::one() << 96,
+        sqrt_price_b - sqrt_price_a,
+    )?)
+}
+
+fn liquidity_to_u128(liquidity: U256) -> Result<u128, ArithmeticError> {
+    if liquidity > U256::from(u128::MAX) {
+        return Err(ArithmeticError::LiquidityOverflow(liquidity));
+    }
+
+    Ok(liquidity.as_u128())
+}
+
+//Encodes a tick as an ABI int24, sign extended to 256 bits as it appears in calldata and logs
+pub fn encode_i24(tick: i32) -> U256 {
+    I256::from(tick).into_raw()
+}
+
+//Decodes an ABI int24 tick from the low 24 bits of value, sign extending from bit 23. The upper bits are ignored,
+//so this also decodes ticks that were not sign extended to 256 bits.
+pub fn decode_i24(value: U256) -> i32 {
+    ((value.low_u32() << 8) as i32) >> 8
+}
+
+//Decodes the initialized ticks of a downloaded tick bitmap, given as (word position, word) pairs, into a sorted list of ticks.
+//The words are decoded in parallel, which speeds up building the full tick list of a pool from a bulk download of its bitmap.
+#[cfg(feature = "rayon")]
+pub fn decode_initialized_ticks(words: &[(i16, U256)], tick_spacing: i32) -> Vec<i32> {
+    use rayon::prelude::*;
+
+    let mut ticks = words
+        .par_iter()
+        .flat_map_iter(|&(word_pos, word)| {
+            (0..256)
+                .filter(move |&bit_pos| word.bit(bit_pos))
+                .map(move |bit_pos| (word_pos as i32 * 256 + bit_pos as i32) * tick_spacing)
+        })
+        .collect::<Vec<i32>>();
+
+    ticks.par_sort_unstable();
+    ticks
+}
+
+//Rejects addresses that can not be a deployed pool before any calls are made against them
+fn validate_address<M: Middleware>(address: H160) -> Result<(), CFMMError<M>> {
+    if address.is_zero() {
+        return Err(CFMMError::ZeroAddress);
+    }
+
+    Ok(())
+}
+
+//Converts an exact input amount to the amount specified of the swap loop. Amounts above I256::MAX would wrap around to a negative
+//amount specified, which the loop treats as an exact output swap, so they are rejected.
+fn amount_specified_from_amount_in<M: Middleware>(amount_in: U256) -> Result<I256, CFMMError<M>> {
+    I256::try_from(amount_in).map_err(|_| CFMMError::AmountTooLarge(amount_in))
+}
+
+//The fee is denominated in hundredths of a bip, so any value below 1_000_000 is a valid fee, including the dynamic fees of V3 forks
+//that are not one of the canonical tiers. A fee of 1_000_000 or more would take the entire amount in.
+fn validate_fee(fee: u32) -> Result<(), ArithmeticError> {
+    if fee >= 1_000_000 {
+        return Err(ArithmeticError::InvalidFee(fee));
+    }
+
+    Ok(())
+}
+
+//Returns the min or max sqrt price in the pool depending on zero_for_one
+fn default_sqrt_price_limit(zero_for_one: bool) -> U256 {
+    if zero_for_one {
+        MIN_SQRT_RATIO + 1
+    } else {
+        MAX_SQRT_RATIO - 1
+    }
+}
+
+//Converts a human readable price of token_a in terms of token_b into a Q64.96 sqrt price, the inverse of try_calculate_price.
+//Returns zero if the price is not a finite positive number.
+pub fn sqrt_price_from_price(price: f64, token_a_decimals: u8, token_b_decimals: u8) -> U256 {
+    if !(price.is_finite() && price > 0.0) {
+        return U256::zero();
+    }
+
+    let shift = token_a_decimals as i32 - token_b_decimals as i32;
+    let sqrt_price = (price / 10_f64.powi(shift)).sqrt();
+
+    //Decompose the f64 into mantissa * 2^exponent so that the Q64.96 value can be built without losing precision
+    let bits = sqrt_price.to_bits();
+    let biased_exponent = ((bits >> 52) & 0x7ff) as i32;
+    let mantissa = if biased_exponent == 0 {
+        (bits & 0xfffffffffffff) << 1
+    } else {
+        (bits & 0xfffffffffffff) | (1 << 52)
+    };
+
+    let shift = biased_exponent - 1075 + 96;
+    if shift >= 0 {
+        U256::from(mantissa) << shift as usize
+    } else {
+        U256::from(mantissa) >> (-shift) as usize
+    }
+}
+
+pub struct CurrentState {
+    amount_specified_remaining: I256,
+    amount_calculated: I256,
+    sqrt_price_x_96: U256,
+    tick: i32,
+    liquidity: u128,
+    initialized_ticks_crossed: u32,
+}
+
+//Snapshot of a pool's state and a window of its tick data, used to simulate swaps fully offline.
+//Swaps that move the price past the last tick in the window are simulated as if the active liquidity extends to the usable tick bounds.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FrozenPool {
+    pub token_a: H160,
+    pub token_b: H160,
+    pub fee: u32,
+    pub tick_spacing: i32,
+    pub sqrt_price: U256,
+    pub tick: i32,
+    pub liquidity: u128,
+    //Tick data sorted by tick
+    pub ticks: Vec<TickData>,
+}
+
+impl FrozenPool {
+    pub fn simulate_swap(&self, token_in: H160, amount_in: U256) -> Result<U256, ArithmeticError> {
+        Ok(self.simulate_swap_detailed(token_in, amount_in)?.amount_out)
+    }
+
+    //Past the last tick in ticks, the liquidity is assumed to stay the same up to the tick bounds, so the result is only exact when
+    //is_within_tick_data holds for it
+    pub fn simulate_swap_detailed(
+        &self,
+        token_in: H160,
+        amount_in: U256,
+    ) -> Result<SwapResult, ArithmeticError> {
+        let zero_for_one = token_in == self.token_a;
+        let ticks = ticks_in_swap_direction(&self.ticks, self.tick, zero_for_one);
+
+        simulate_swap_pure(
+            self.sqrt_price,
+            self.liquidity,
+            self.tick,
+            self.tick_spacing,
+            self.fee,
+            &ticks,
+            zero_for_one,
+            amount_in,
+        )
+    }
+
+    //Returns true if a swap of token_in that ended at swap_result stayed within the fetched tick data, or if the tick data runs
+    //all the way to the tick bound in the direction of the swap
+    pub fn is_within_tick_data(&self, token_in: H160, swap_result: &SwapResult) -> bool {
+        let (min_tick, max_tick) = usable_tick_bounds(self.tick_spacing);
+
+        if token_in == self.token_a {
+            self.ticks.first().is_some_and(|tick_data| {
+                tick_data.tick <= min_tick || swap_result.tick_after >= tick_data.tick
+            })
+        } else {
+            self.ticks.last().is_some_and(|tick_data| {
+                tick_data.tick >= max_tick || swap_result.tick_after < tick_data.tick
+            })
+        }
+    }
+}
+
+//Returns the ticks a swap starting at tick passes through, in the order it reaches them, from tick data sorted by tick.
+//Swapping token_a moves the price down through the ticks at or below the current tick, otherwise up through the ticks above it.
+fn ticks_in_swap_direction(ticks: &[TickData], tick: i32, zero_for_one: bool) -> Vec<TickData> {
+    let split = ticks.partition_point(|tick_data| tick_data.tick <= tick);
+
+    if zero_for_one {
+        ticks[..split].iter().rev().copied().collect()
+    } else {
+        ticks[split..].to_vec()
+    }
+}
+
+//Detailed result of a simulated swap, mirroring the values returned by the QuoterV2 contract
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SwapResult {
+    pub amount_in: U256,
+    pub amount_out: U256,
+    pub sqrt_price_after: U256,
+    pub tick_after: i32,
+    pub liquidity_after: u128,
+    pub ticks_crossed: u32,
+}
+
+//Fee tiers enabled by the Uniswap V3 factory. Custom holds any other fee, such as the fees of V3 forks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum FeeTier {
+    //0.01%
+    Lowest,
+    //0.05%
+    Low,
+    //0.3%
+    Medium,
+    //1%
+    High,
+    Custom(u32),
+}
+
+impl FeeTier {
+    pub const CANONICAL: [FeeTier; 4] = [
+        FeeTier::Lowest,
+        FeeTier::Low,
+        FeeTier::Medium,
+        FeeTier::High,
+    ];
+
+    pub fn from_u32(fee: u32) -> FeeTier {
+        match fee {
+            100 => FeeTier::Lowest,
+            500 => FeeTier::Low,
+            3000 => FeeTier::Medium,
+            10000 => FeeTier::High,
+            fee => FeeTier::Custom(fee),
+        }
+    }
+
+    //Fee in hundredths of a bip, as stored in the pool's fee
+    pub fn to_u32(self) -> u32 {
+        match self {
+            FeeTier::Lowest => 100,
+            FeeTier::Low => 500,
+            FeeTier::Medium => 3000,
+            FeeTier::High => 10000,
+            FeeTier::Custom(fee) => fee,
+        }
+    }
+
+    //Tick spacing the factory enables the fee tier with, or None for a custom fee
+    pub fn tick_spacing(self) -> Option<i32> {
+        match self {
+            FeeTier::Lowest => Some(1),
+            FeeTier::Low => Some(10),
+            FeeTier::Medium => Some(60),
+            FeeTier::High => Some(200),
+            FeeTier::Custom(_) => None,
+        }
+    }
+
+    //Returns the canonical fee tier with the tick spacing, if any
+    pub fn from_tick_spacing(tick_spacing: i32) -> Option<FeeTier> {
+        FeeTier::CANONICAL
+            .into_iter()
+            .find(|fee_tier| fee_tier.tick_spacing() == Some(tick_spacing))
+    }
+}
+
+impl From<u32> for FeeTier {
+    fn from(fee: u32) -> FeeTier {
+        FeeTier::from_u32(fee)
+    }
+}
+
+impl From<FeeTier> for u32 {
+    fn from(fee_tier: FeeTier) -> u32 {
+        fee_tier.to_u32()
+    }
+}
+
+//Human readable summary of a simulated swap, as returned by simulate_swap_display. Amounts are in whole tokens and the price is the
+//effective price of token_in in terms of token_out. The price impact is the fraction of the mid price lost to the fee and to moving
+//the price, and the fee is the amount of token_in kept by the pool.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SwapDisplay {
+    pub amount_in_human: f64,
+    pub amount_out_human: f64,
+    pub price: f64,
+    pub price_impact: f64,
+    pub fee_human: f64,
+}
+
+//Swap event emitted by a pool. Positive amounts were paid into the pool and negative amounts were paid out of it,
+//and the sqrt price, liquidity and tick are the state of the pool after the swap.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SwapEvent {
+    pub amount0: I256,
+    pub amount1: I256,
+    pub sqrt_price: U256,
+    pub liquidity: u128,
+    pub tick: i32,
+    pub sender: H160,
+    pub recipient: H160,
+}
+
+//Change in the dynamic state of a pool between syncs, as returned by sync_pool_diff. Fields that did not change are None.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PoolDelta {
+    pub sqrt_price: Option<U256>,
+    pub tick: Option<i32>,
+    pub liquidity: Option<u128>,
+    pub liquidity_net: Option<i128>,
+    //Block the delta was synced at
+    pub block_number: u64,
+    #[serde(default)]
+    pub synced_at: Option<SystemTime>,
+}
+
+//Change between two snapshots of a pool, as returned by diff. The price change is the percentage change in the price of token_a
+//in terms of token_b, and the deltas are the later snapshot's value minus the earlier one.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PoolDiff {
+    pub price_change_pct: f64,
+    pub tick_delta: i32,
+    pub liquidity_delta: i128,
+}
+
+//How amounts are rounded when computing the output of a swap
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RoundingMode {
+    //Rounds in the pool's favor, matching the amounts the contract pays out
+    #[default]
+    Protocol,
+    //Rounds each step's output up, giving the output the trade would receive without the protocol rounding against the trader
+    Ideal,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct StepComputations {
+    pub sqrt_price_start_x_96: U256,
+    pub tick_next: i32,
+    pub initialized: bool,
+    pub sqrt_price_next_x96: U256,
+    pub amount_in: U256,
+    pub amount_out: U256,
+    pub fee_amount: U256,
+    pub liquidity: u128,
+}
+
+const MIN_TICK: i32 = -887272;
+const MAX_TICK: i32 = 887272;
+
+//Full state of a tick as stored by the pool contract, e.g. for fee and oracle accounting. Swap simulation only needs whether
+//a tick is initialized and its liquidity_net, which is what TickData holds, so convert a Tick with TickData::from((tick, info))
+//to simulate offline against ticks fetched with get_tick_info.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Tick {
+    pub liquidity_gross: u128,
+    pub liquidity_net: i128,
+    pub fee_growth_outside_0_x_128: U256,
+    pub fee_growth_outside_1_x_128: U256,
+    pub tick_cumulative_outside: U256,
+    pub seconds_per_liquidity_outside_x_128: U256,
+    pub seconds_outside: u32,
+    pub initialized: bool,
+}
+
+//Converts the tuple returned by get_tick_info, sign extending the int56 tickCumulativeOutside to 256 bits
+impl From<(u128, i128, U256, U256, i64, U256, u32, bool)> for Tick {
+    fn from(tick_info: (u128, i128, U256, U256, i64, U256, u32, bool)) -> Self {
+        Tick {
+            liquidity_gross: tick_info.0,
+            liquidity_net: tick_info.1,
+            fee_growth_outside_0_x_128: tick_info.2,
+            fee_growth_outside_1_x_128: tick_info.3,
+            tick_cumulative_outside: I256::from(tick_info.4).into_raw(),
+            seconds_per_liquidity_outside_x_128: tick_info.5,
+            seconds_outside: tick_info.6,
+            initialized: tick_info.7,
+        }
+    }
+}
+
+//Tick does not store its own index, so it is paired with the tick it was fetched for
+impl From<(i32, Tick)> for TickData {
+    fn from((tick, info): (i32, Tick)) -> Self {
+        TickData {
+            initialized: info.initialized,
+            tick,
+            liquidity_net: info.liquidity_net,
+        }
+    }
+}
+
+mod test {
+    #[allow(unused)]
+    use crate::abi::{ExactInputCall, ExactInputSingleCall, IUniswapV3Pool, MulticallCall};
+
+    #[allow(unused)]
+    use super::{
+        aggregate_mid_price, apply_step_amounts, compute_pool_address, decode_i24, encode_i24,
+        encode_path, exact_input_calldata, fetch_prices, get_all_fee_tier_pools,
+        get_pools_from_token_pairs, parse_swap_event, simulate_swap_pure, snapshot_prices_at_block,
+        sqrt_price_from_price, swap_router_exact_input_single_calldata, CurrentState,
+        ExactInputSingleParams, FeeTier, FrozenPool, PoolDelta, PoolDiff, RoundingMode,
+        StepComputations, Tick, TickData, UniswapV3Pool, INITIALIZE_EVENT_SIGNATURE,
+        MIN_SQRT_RATIO, SWAP_EVENT_SIGNATURE,
+    };
+    #[allow(unused)]
+    use crate::errors::{ArithmeticError, CFMMError, PoolError};
+    #[allow(unused)]
+    use crate::reference::{QuoterV2, QUOTER_V2_ADDRESS};
+    #[allow(unused)]
+    use crate::registry::TokenRegistry;
+    #[cfg(test)]
+    use crate::test_utils::{mock_pool, mock_tick_data_response};
+    #[allow(unused)]
+    use ethers::providers::Middleware;
+
+    #[allow(unused)]
+    use ethers::{
+        abi::{AbiDecode, Token},
+        providers::{
+            Http, JsonRpcClient, JsonRpcError, MockError, MockProvider, MockResponse, Provider,
+        },
+        types::{Bytes, Log, H160, H256, I256, U256, U64},
+    };
+    #[allow(unused)]
+    use futures::StreamExt;
+    #[allow(unused)]
+    use serde::{de::DeserializeOwned, Serialize};
+    #[allow(unused)]
+    use std::error::Error;
+    #[allow(unused)]
+    use std::{str::FromStr, sync::Arc};
+
+    //Serves responses from a MockProvider for the first max_requests requests, then never resolves
+    #[allow(unused)]
+    #[derive(Debug)]
+    struct StallingClient {
+        mock: MockProvider,
+        max_requests: usize,
+        requests: std::sync::atomic::AtomicUsize,
+    }
+
+    #[async_trait::async_trait]
+    impl JsonRpcClient for StallingClient {
+        type Error = MockError;
+
+        async fn request<T, R>(&self, method: &str, params: T) -> Result<R, MockError>
+        where
+            T: Serialize + Send + Sync + std::fmt::Debug,
+            R: DeserializeOwned + Send,
+        {
+            let requests = self
+                .requests
+                .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+
+            if requests >= self.max_requests {
+                futures::future::pending::<()>().await;
+            }
+
+            self.mock.request(method, params).await
+        }
+    }
+
+    //Answers every eth_call with historical_response when it is pinned to a block and with latest_response otherwise
+    #[allow(unused)]
+    #[derive(Debug)]
+    struct BlockPinnedClient {
+        historical_response: Bytes,
+        latest_response: Bytes,
+    }
+
+    #[async_trait::async_trait]
+    impl JsonRpcClient for BlockPinnedClient {
+        type Error = MockError;
+
+        async fn request<T, R>(&self, _method: &str, params: T) -> Result<R, MockError>
+        where
+            T: Serialize + Send + Sync + std::fmt::Debug,
+            R: DeserializeOwned + Send,
+        {
+            let params = serde_json::to_value(params)?;
+            let response = if params[1] == "latest" {
+                &self.latest_response
+            } else {
+                &self.historical_response
+            };
+
+            Ok(serde_json::from_value(serde_json::to_value(response)?)?)
+        }
+    }
+
+    //Answers eth_blockNumber with block_number, and every tick data batch request with the response for its pool and direction,
+    //recording the pool, direction and block of each request
+    #[allow(unused)]
+    #[derive(Debug)]
+    struct TickDataClient {
+        block_number: U64,
+        responses: std::collections::HashMap<(H160, bool), Bytes>,
+        requests: std::sync::Mutex<Vec<(H160, bool, serde_json::Value)>>,
+    }
+
+    #[async_trait::async_trait]
+    impl JsonRpcClient for TickDataClient {
+        type Error = MockError;
+
+        async fn request<T, R>(&self, method: &str, params: T) -> Result<R, MockError>
+        where
+            T: Serialize + Send + Sync + std::fmt::Debug,
+            R: DeserializeOwned + Send,
+        {
+            if method == "eth_blockNumber" {
+                return Ok(serde_json::from_value(serde_json::to_value(
+                    self.block_number,
+                )?)?);
+            }
+
+            //The constructor arguments are appended to the bytecode, with the pool and direction in the first two words
+            let params = serde_json::to_value(params)?;
+            let data: Bytes = serde_json::from_value(params[0]["data"].clone())?;
+            let args = &data[data.len() - 5 * 32..];
+            let pool = H160::from_slice(&args[12..32]);
+            let zero_for_one = args[63] == 1;
+
+            self.requests
+                .lock()
+                .unwrap()
+                .push((pool, zero_for_one, params[1].clone()));
+
+            Ok(serde_json::from_value(serde_json::to_value(
+                &self.responses[&(pool, zero_for_one)],
+            )?)?)
+        }
+    }
+
+    //Collects the fields of every event, and of every new span prefixed with the span's name
+    #[cfg(all(test, feature = "tracing"))]
+    #[derive(Clone, Default)]
+    struct EventCollector(Arc<std::sync::Mutex<Vec<String>>>);
+
+    #[cfg(all(test, feature = "tracing"))]
+    struct FieldVisitor(String);
+
+    #[cfg(all(test, feature = "tracing"))]
+    impl tracing::field::Visit for FieldVisitor {
+        fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+            self.0.push_str(&format!("{}={:?} ", field.name(), value));
+        }
+    }
+
+    #[cfg(all(test, feature = "tracing"))]
+    impl<S: tracing::Subscriber> tracing_subscriber::Layer<S> for EventCollector {
+        fn on_new_span(
+            &self,
+            attrs: &tracing::span::Attributes<'_>,
+            _id: &tracing::span::Id,
+            _ctx: tracing_subscriber::layer::Context<'_, S>,
+        ) {
+            let mut visitor = FieldVisitor(format!("{}: ", attrs.metadata().name()));
+            attrs.record(&mut visitor);
+            self.0.lock().unwrap().push(visitor.0);
+        }
+
+        fn on_event(
+            &self,
+            event: &tracing::Event<'_>,
+            _ctx: tracing_subscriber::layer::Context<'_, S>,
+        ) {
+            let mut visitor = FieldVisitor(String::new());
+            event.record(&mut visitor);
+            self.0.lock().unwrap().push(visitor.0);
+        }
+    }
+
+    #[cfg(all(test, feature = "tracing"))]
+    #[tokio::test]
+    async fn test_simulate_swap_emits_tracing_events() {
+        use tracing_subscriber::prelude::*;
+
+        let (provider, mock) = Provider::mocked();
+        mock.push::<Bytes, _>(mock_tick_data_response(
+            &[(true, -10, 1_000_000), (true, -20, 1_000_000)],
+            1,
+        ))
+        .unwrap();
+
+        let collector = EventCollector::default();
+        let _guard = tracing::subscriber::set_default(
+            tracing_subscriber::registry().with(collector.clone()),
+        );
+
+        let pool = mock_pool();
+        pool.simulate_swap(pool.token_a, U256::from(1000), Arc::new(provider))
+            .await
+            .unwrap();
+
+        let events = collector.0.lock().unwrap();
+        assert_eq!(
+            events
+                .iter()
+                .filter(|e| e.starts_with("get_uniswap_v3_tick_data_batch_request"))
+                .collect::<Vec<_>>(),
+            vec![&format!(
+                "get_uniswap_v3_tick_data_batch_request_with_config: pool={:?} tick_start=0 zero_for_one=true num_ticks=150 block_number=None ",
+                pool.address
+            )]
+        );
+        assert!(events.iter().any(|e| e.contains("fetched tick data")));
+        assert!(events
+            .iter()
+            .any(|e| e.contains("swap simulation complete") && e.contains("ticks_crossed=0")));
+    }
+
+    #[tokio::test]
+    async fn test_simulate_swap_liquidity_net_overflow() {
+        let (provider, mock) = Provider::mocked();
+        mock.push::<Bytes, _>(mock_tick_data_response(
+            &[(true, -10, i128::MIN), (true, -20, 1_000_000)],
+            1,
+        ))
+        .unwrap();
+
+        let pool = mock_pool();
+
+        //Swap enough token_a to cross the initialized tick at -10
+        let result = pool
+            .simulate_swap(
+                pool.token_a,
+                U256::from(10_000_000_000_000_000_u128),
+                Arc::new(provider),
+            )
+            .await;
+
+        assert!(matches!(
+            result,
+            Err(CFMMError::LiquidityNetOverflow(address)) if address == pool.address
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_simulate_swap_fresh_resyncs_stale_pool() {
+        let (provider, mock) = Provider::mocked();
+
+        //Responses are popped in reverse order
+        mock.push::<Bytes, _>(mock_tick_data_response(&[(true, -10, 1_000_000)], 100))
+            .unwrap();
+        mock.push::<Bytes, _>(Bytes::from(ethers::abi::encode(&[Token::Tuple(vec![
+            Token::Uint(U256::from(2_000_000_000_000_000_000_u128)),
+            Token::Uint(U256::one() << 96),
+            Token::Int(U256::zero()),
+            Token::Int(U256::zero()),
+        ])])))
+        .unwrap();
+        mock.push(U64::from(100)).unwrap();
+        mock.push(U64::from(100)).unwrap();
+
+        let mut pool = mock_pool();
+        pool.last_synced_block = 50;
+
+        pool.simulate_swap_fresh(pool.token_a, U256::from(1000), 10, Arc::new(provider))
+            .await
+            .unwrap();
+
+        assert_eq!(pool.last_synced_block, 100);
+        assert_eq!(pool.liquidity, 2_000_000_000_000_000_000);
+    }
+
+    #[test]
+    fn test_decode_truncated_swap_log() {
+        let pool = mock_pool();
+
+        let swap_log = Log {
+            address: pool.address,
+            topics: vec![SWAP_EVENT_SIGNATURE],
+            data: vec![0; 32].into(),
+            ..Default::default()
+        };
+
+        let result = pool.decode_swap_log::<Provider<MockProvider>>(&swap_log);
+
+        assert!(matches!(
+            result,
+            Err(CFMMError::AbiDecode(address, topic)) if address == pool.address && topic == SWAP_EVENT_SIGNATURE
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_fetch_prices() {
+        let (provider, mock) = Provider::mocked();
+
+        let pools = (0..10)
+            .map(|i| UniswapV3Pool {
+                address: H160::from_low_u64_be(100 + i),
+                ..mock_pool()
+            })
+            .collect::<Vec<UniswapV3Pool>>();
+
+        for _ in &pools {
+            mock.push::<Bytes, _>(Bytes::from(ethers::abi::encode(&[Token::Tuple(vec![
+                Token::Uint(U256::from(1_000_000_000_000_000_000_u128)),
+                Token::Uint(U256::one() << 96),
+                Token::Int(U256::zero()),
+                Token::Int(U256::zero()),
+            ])])))
+            .unwrap();
+        }
+
+        let prices = fetch_prices(&pools, pools[0].token_a, 3, Arc::new(provider)).await;
+
+        assert_eq!(prices.len(), 10);
+        for price in prices {
+            assert_eq!(price.unwrap(), 1.0);
+        }
+    }
+
+    #[test]
+    fn test_sqrt_price_from_price() {
+        assert_eq!(sqrt_price_from_price(1.0, 18, 18), U256::one() << 96);
+        assert_eq!(sqrt_price_from_price(4.0, 18, 18), U256::from(2) << 96);
+
+        //USDC/WETH
+        let pool = UniswapV3Pool {
+            token_a_decimals: 6,
+            token_b_decimals: 18,
+            ..mock_pool()
+        };
+
+        for price in [1.0 / 1850.25, 0.000001, 1000.0] {
+            let pool = UniswapV3Pool {
+                sqrt_price: sqrt_price_from_price(price, 6, 18),
+                ..pool
+            };
+
+            let round_trip_price = pool.try_calculate_price(pool.token_a).unwrap();
+            assert!(((round_trip_price - price) / price).abs() < 0.0001);
+        }
+    }
+
+    #[test]
+    fn test_assert_tick_consistency() {
+        let pool = mock_pool();
+        assert!(pool.assert_tick_consistency().is_ok());
+
+        let pool = UniswapV3Pool {
+            tick: 1,
+            ..mock_pool()
+        };
+        assert!(pool.assert_tick_consistency().is_ok());
+
+        //sqrt_price corresponds to tick 0
+        let pool = UniswapV3Pool {
+            tick: -100,
+            ..mock_pool()
+        };
+        assert!(matches!(
+            pool.assert_tick_consistency(),
+            Err(ArithmeticError::TickInconsistency(-100, 0))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_simulate_swap_with_price_limit() {
+        let (provider, mock) = Provider::mocked();
+        let middleware = Arc::new(provider);
+
+        let ticks = (1..=20)
+            .map(|i| (false, -10 * i, 0))
+            .collect::<Vec<(bool, i32, i128)>>();
+        mock.push::<Bytes, _>(mock_tick_data_response(&ticks, 1))
+            .unwrap();
+        mock.push::<Bytes, _>(mock_tick_data_response(&ticks, 1))
+            .unwrap();
+
+        let pool = mock_pool();
+
+        //Allow the price to move at most 1%
+        let price_limit = pool.try_calculate_price(pool.token_a).unwrap() * 0.99;
+
+        let amount_out = pool
+            .simulate_swap_with_price_limit(
+                pool.token_a,
+                U256::from(1_000_000_000_000_000_000_u128),
+                price_limit,
+                middleware.clone(),
+            )
+            .await
+            .unwrap();
+
+        //Swapping more does not fill any further once the limit is reached
+        let amount_out_larger_swap = pool
+            .simulate_swap_with_price_limit(
+                pool.token_a,
+                U256::from(2_000_000_000_000_000_000_u128),
+                price_limit,
+                middleware.clone(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(amount_out, amount_out_larger_swap);
+
+        //Moving the price by 1% with 1e18 liquidity yields liquidity * (1 - sqrt(0.99)) of token_b
+        let expected_amount_out = 1e18 * (1.0 - 0.99_f64.sqrt());
+        assert!(
+            (amount_out.as_u128() as f64 - expected_amount_out).abs() / expected_amount_out < 0.001
+        );
+
+        //A limit on the wrong side of the current price is rejected
+        let result = pool
+            .simulate_swap_with_price_limit(pool.token_a, U256::from(1000), 1.01, middleware)
+            .await;
+        assert!(matches!(result, Err(CFMMError::InvalidSqrtPriceLimit(_))));
+    }
+
+    #[tokio::test]
+    async fn test_simulate_swap_with_non_canonical_fee() {
+        let (provider, mock) = Provider::mocked();
+        let middleware = Arc::new(provider);
+
+        let ticks = (1..=20)
+            .map(|i| (false, -10 * i, 0))
+            .collect::<Vec<(bool, i32, i128)>>();
+        mock.push::<Bytes, _>(mock_tick_data_response(&ticks, 1))
+            .unwrap();
+
+        //A 2% fee, above the highest canonical fee tier
+        let mut pool = mock_pool();
+        pool.fee = 20000;
+
+        let amount_in = U256::from(1_000_000_000_000_u128);
+        let amount_out = pool
+            .simulate_swap(pool.token_a, amount_in, middleware.clone())
+            .await
+            .unwrap();
+
+        //The swap is small relative to the liquidity at a price of 1, so the output is the amount in less the 2% fee
+        let expected_amount_out = 1e12 * 0.98;
+        assert!(
+            (amount_out.as_u128() as f64 - expected_amount_out).abs() / expected_amount_out < 1e-5
+        );
+
+        //A fee of 100% or more is rejected before any tick data is fetched
+        pool.fee = 1_000_000;
+        let result = pool
+            .simulate_swap(pool.token_a, amount_in, middleware)
+            .await;
+        assert!(matches!(
+            result,
+            Err(CFMMError::ArithmeticError(ArithmeticError::InvalidFee(
+                1_000_000
+            )))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_new_from_zero_address() {
+        //The mock provider has no responses queued, so any RPC call would fail with a different error
+        let (provider, _mock) = Provider::mocked();
+        let middleware = Arc::new(provider);
+
+        let result = UniswapV3Pool::new_from_address(H160::zero(), middleware).await;
+        assert!(matches!(result, Err(CFMMError::ZeroAddress)));
+    }
+
+    #[test]
+    fn test_encode_decode_i24() {
+        for tick in [0, 1, -1, 10, -10, 887272, -887272, 8388607, -8388608] {
+            assert_eq!(encode_i24(tick), I256::from(tick).into_raw());
+            assert_eq!(decode_i24(encode_i24(tick)), tick);
+        }
+
+        //Ticks that are not sign extended past 24 bits decode the same
+        assert_eq!(decode_i24(U256::from(0xffffff)), -1);
+        assert_eq!(decode_i24(U256::from(0x800000)), -8388608);
+        assert_eq!(decode_i24(U256::from(0x7fffff)), 8388607);
+        assert_eq!(
+            decode_i24(U256::from(-887272_i32 as u32 & 0xffffff)),
+            -887272
+        );
+    }
+
+    #[test]
+    fn test_sqrt_price_limit_from_slippage() {
+        let pool = mock_pool();
+        let sqrt_price = pool.sqrt_price.as_u128() as f64;
+
+        //The price is the square of the sqrt price, so 100 bps of slippage is a ~1% move in the squared ratio
+        let limit_zero_for_one = pool.sqrt_price_limit_from_slippage(true, 100);
+        assert!(limit_zero_for_one < pool.sqrt_price);
+        let price_ratio = (limit_zero_for_one.as_u128() as f64 / sqrt_price).powi(2);
+        assert!((price_ratio - 0.99).abs() < 1e-9);
+
+        let limit_one_for_zero = pool.sqrt_price_limit_from_slippage(false, 100);
+        assert!(limit_one_for_zero > pool.sqrt_price);
+        let price_ratio = (limit_one_for_zero.as_u128() as f64 / sqrt_price).powi(2);
+        assert!((price_ratio - 1.01).abs() < 1e-9);
+
+        //Slippage beyond the price bounds is clamped to a limit the pool accepts
+        assert_eq!(
+            pool.sqrt_price_limit_from_slippage(true, 10000),
+            MIN_SQRT_RATIO + 1
+        );
+    }
+
+    #[tokio::test]
+    async fn test_quick_quote() {
+        let (provider, mock) = Provider::mocked();
+        let middleware = Arc::new(provider);
+
+        //Most of the liquidity ends at tick -10
+        let ticks = [(true, -10, 900_000_000_000_000_000), (true, -887270, 0)];
+        mock.push::<Bytes, _>(mock_tick_data_response(&ticks, 1))
+            .unwrap();
+        mock.push::<Bytes, _>(mock_tick_data_response(&ticks, 1))
+            .unwrap();
+
+        let pool = mock_pool();
+
+        //A tiny trade stays within the current tick
+        let amount_in = U256::from(1_000_000_000_000_u128);
+        let quick_amount_out = pool.quick_quote(pool.token_a, amount_in).unwrap();
+        let amount_out = pool
+            .simulate_swap(pool.token_a, amount_in, middleware.clone())
+            .await
+            .unwrap();
+
+        let difference = quick_amount_out.as_u128() as f64 - amount_out.as_u128() as f64;
+        assert!(difference.abs() / (amount_out.as_u128() as f64) < 0.0001);
+
+        //A large trade crosses tick -10, where the quote no longer accounts for the drop in liquidity
+        let amount_in = U256::from(100_000_000_000_000_000_u128);
+        let quick_amount_out = pool.quick_quote(pool.token_a, amount_in).unwrap();
+        let amount_out = pool
+            .simulate_swap(pool.token_a, amount_in, middleware)
+            .await
+            .unwrap();
+
+        assert!(quick_amount_out.as_u128() as f64 > amount_out.as_u128() as f64 * 1.1);
+
+        //Any amount_in is quoted without overflowing, approaching but never reaching the virtual reserve out
+        let (_, reserve_1) = pool.calculate_virtual_reserves().unwrap();
+        let quick_amount_out = pool.quick_quote(pool.token_a, U256::MAX).unwrap();
+        assert!(quick_amount_out < U256::from(reserve_1));
+        assert!(quick_amount_out > U256::from(reserve_1) * 99 / 100);
+
+        let invalid_fee_pool = UniswapV3Pool {
+            fee: 1_000_000,
+            ..pool
+        };
+        assert!(matches!(
+            invalid_fee_pool.quick_quote(pool.token_a, amount_in),
+            Err(ArithmeticError::InvalidFee(1_000_000))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_would_cross_n_ticks() {
+        let (provider, mock) = Provider::mocked();
+        let middleware = Arc::new(provider);
+
+        let ticks = (1..=20)
+            .map(|i| (true, -10 * i, 0))
+            .collect::<Vec<(bool, i32, i128)>>();
+        mock.push::<Bytes, _>(mock_tick_data_response(&ticks, 1))
+            .unwrap();
+        mock.push::<Bytes, _>(mock_tick_data_response(&ticks, 1))
+            .unwrap();
+
+        let pool = mock_pool();
+
+        //Stays within the current tick
+        assert!(!pool
+            .would_cross_n_ticks(
+                pool.token_a,
+                U256::from(1_000_000_000_u128),
+                3,
+                middleware.clone()
+            )
+            .await
+            .unwrap());
+
+        //Enough to cross every tick in the mocked tick data, but the simulation stops after the third
+        assert!(pool
+            .would_cross_n_ticks(
+                pool.token_a,
+                U256::from(100_000_000_000_000_000_u128),
+                3,
+                middleware
+            )
+            .await
+            .unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_simulate_swap_trace() {
+        let (provider, mock) = Provider::mocked();
+
+        let ticks = (1..=20)
+            .map(|i| (false, -10 * i, 0))
+            .collect::<Vec<(bool, i32, i128)>>();
+        mock.push::<Bytes, _>(mock_tick_data_response(&ticks, 1))
+            .unwrap();
+
+        let pool = mock_pool();
+
+        //Enough to cross ticks -10 and -20 but not -30
+        let (amount_out, trace) = pool
+            .simulate_swap_trace(
+                pool.token_a,
+                U256::from(1_200_000_000_000_000_u128),
+                Arc::new(provider),
+            )
+            .await
+            .unwrap();
+
+        let ticks_crossed = 2;
+        assert_eq!(trace.len(), ticks_crossed + 1);
+        assert_eq!(
+            trace
+                .iter()
+                .map(|step| step.tick_next)
+                .collect::<Vec<i32>>(),
+            vec![-10, -20, -30]
+        );
+        assert_eq!(
+            trace
+                .iter()
+                .fold(U256::zero(), |total, step| total + step.amount_out),
+            amount_out
+        );
+    }
+
+    #[tokio::test]
+    async fn test_simulate_swap_with_rounding() {
+        let (provider, mock) = Provider::mocked();
+        let middleware = Arc::new(provider);
+
+        let ticks = [(true, -10, 1_000_000)];
+        mock.push::<Bytes, _>(mock_tick_data_response(&ticks, 1))
+            .unwrap();
+        mock.push::<Bytes, _>(mock_tick_data_response(&ticks, 1))
+            .unwrap();
+
+        let pool = mock_pool();
+
+        //Small enough to stay within the current tick
+        let amount_in = U256::from(123_456_789_u128);
+        let amount_out = pool
+            .simulate_swap_with_rounding(
+                pool.token_a,
+                amount_in,
+                RoundingMode::Protocol,
+                middleware.clone(),
+            )
+            .await
+            .unwrap();
+        let ideal_amount_out = pool
+            .simulate_swap_with_rounding(pool.token_a, amount_in, RoundingMode::Ideal, middleware)
+            .await
+            .unwrap();
+
+        assert!(ideal_amount_out >= amount_out);
+        assert!(ideal_amount_out - amount_out <= U256::one());
+    }
+
+    #[test]
+    fn test_aggregate_mid_price() {
+        let deep_pool = UniswapV3Pool {
+            liquidity: 1_000_000_000_000_000_000_000,
+            sqrt_price: sqrt_price_from_price(1.0, 18, 18),
+            ..mock_pool()
+        };
+        let shallow_pool = UniswapV3Pool {
+            liquidity: 1_000_000_000_000_000_000,
+            sqrt_price: sqrt_price_from_price(2.0, 18, 18),
+            fee: 3000,
+            ..mock_pool()
+        };
+        let token = deep_pool.token_a;
+
+        let deep_price = deep_pool.try_calculate_price(token).unwrap();
+        let shallow_price = shallow_pool.try_calculate_price(token).unwrap();
+        let price = aggregate_mid_price(&[deep_pool, shallow_pool], token);
+
+        assert!(price > deep_price.min(shallow_price) && price < deep_price.max(shallow_price));
+        assert!((price - deep_price).abs() < (price - shallow_price).abs());
+        assert_eq!(aggregate_mid_price(&[], token), 0.0);
+    }
+
+    #[tokio::test]
+    async fn test_simulate_swap_by_symbol() {
+        let (provider, mock) = Provider::mocked();
+        let middleware = Arc::new(provider);
+
+        let ticks = [(true, -10, 1_000_000)];
+        mock.push::<Bytes, _>(mock_tick_data_response(&ticks, 1))
+            .unwrap();
+        mock.push::<Bytes, _>(mock_tick_data_response(&ticks, 1))
+            .unwrap();
+
+        let pool = mock_pool();
+        let registry = TokenRegistry::from_iter([
+            ("WETH", pool.token_a),
+            ("USDC", pool.token_b),
+            ("DAI", H160::from_low_u64_be(4)),
+        ]);
+
+        let amount_in = U256::from(1_000_000_000_u128);
+        assert_eq!(
+            pool.simulate_swap_by_symbol("WETH", amount_in, &registry, middleware.clone())
+                .await
+                .unwrap(),
+            pool.simulate_swap(pool.token_a, amount_in, middleware.clone())
+                .await
+                .unwrap()
+        );
+
+        assert!(matches!(
+            pool.simulate_swap_by_symbol("WBTC", amount_in, &registry, middleware.clone())
+                .await,
+            Err(CFMMError::UnknownTokenSymbol(symbol)) if symbol == "WBTC"
+        ));
+        assert!(matches!(
+            pool.simulate_swap_by_symbol("DAI", amount_in, &registry, middleware)
+                .await,
+            Err(CFMMError::TokenNotInPool(_, _))
+        ));
+    }
+
+    #[test]
+    fn test_calculate_compressed() {
+        let pool = UniswapV3Pool {
+            tick_spacing: 60,
+            ..mock_pool()
+        };
+        assert_eq!(pool.calculate_compressed(120).unwrap(), 2);
+        assert_eq!(pool.calculate_compressed(-120).unwrap(), -2);
+        assert_eq!(pool.calculate_compressed(-121).unwrap(), -3);
+
+        //Pools created from an event log have no tick spacing yet
+        let pool = UniswapV3Pool {
+            tick_spacing: 0,
+            ..mock_pool()
+        };
+        assert!(matches!(
+            pool.calculate_compressed(-121),
+            Err(ArithmeticError::UninitializedTickSpacing)
+        ));
+    }
+
+    #[test]
+    fn test_price_range_for_ticks() {
+        let pool = mock_pool();
+        let price = pool.try_calculate_price(pool.token_a).unwrap();
+
+        let (lower, upper) = pool.price_range_for_ticks(10);
+        assert!(lower < price && price < upper);
+
+        let (wider_lower, wider_upper) = pool.price_range_for_ticks(100);
+        assert!(wider_lower < lower && upper < wider_upper);
+
+        //The range never extends past the usable tick bounds
+        let pool = UniswapV3Pool {
+            tick_spacing: 200,
+            ..pool
+        };
+        assert_eq!(
+            pool.price_range_for_ticks(u16::MAX),
+            pool.price_range_for_ticks(u16::MAX / 2)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_bid_ask_prices() {
+        let (provider, mock) = Provider::mocked();
+        let middleware = Arc::new(provider);
+
+        let pool = UniswapV3Pool {
+            fee: 3000,
+            ..mock_pool()
+        };
+
+        mock.push::<Bytes, _>(Bytes::from(ethers::abi::encode(&[
+            Token::Uint(pool.sqrt_price),
+            Token::Int(U256::zero()),
+            Token::Uint(U256::zero()),
+            Token::Uint(U256::one()),
+            Token::Uint(U256::one()),
+            Token::Uint(U256::zero()),
+            Token::Bool(true),
+        ])))
+        .unwrap();
+
+        let (bid, ask) = pool.bid_ask_prices(middleware).await.unwrap();
+        let mid = pool.try_calculate_price(pool.token_a).unwrap();
+
+        assert!(ask > mid && mid > bid);
+
+        //The spread is twice the 0.3% fee, up to the fee compounding on the ask
+        let spread = (ask - bid) / mid;
+        assert!((spread - 0.006).abs() < 1e-4);
+    }
+
+    #[tokio::test]
+    async fn test_liquidity_series() {
+        let (provider, mock) = Provider::mocked();
+        let middleware = Arc::new(provider);
+
+        for liquidity in [300_u128, 200, 100] {
+            mock.push::<Bytes, _>(Bytes::from(ethers::abi::encode(&[Token::Uint(
+                U256::from(liquidity),
+            )])))
+            .unwrap();
+        }
+
+        let pool = mock_pool();
+        let liquidity_series = pool
+            .liquidity_series(U64::from(10), U64::from(20), 5, middleware)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            liquidity_series,
+            vec![
+                (U64::from(10), 100),
+                (U64::from(15), 200),
+                (U64::from(20), 300)
+            ]
+        );
+        assert!(liquidity_series
+            .windows(2)
+            .all(|pair| pair[0].0 < pair[1].0));
+    }
+
+    #[tokio::test]
+    async fn test_virtual_reserves_at_block_mocked() {
+        let (provider, mock) = Provider::mocked();
+        let middleware = Arc::new(provider);
+
+        mock.push::<Bytes, _>(Bytes::from(ethers::abi::encode(&[Token::Tuple(vec![
+            Token::Uint(U256::from(4_000_000_000_000_000_000_u128)),
+            Token::Uint(uniswap_v3_math::tick_math::get_sqrt_ratio_at_tick(1000).unwrap()),
+            Token::Int(encode_i24(1000)),
+            Token::Int(U256::zero()),
+        ])])))
+        .unwrap();
+
+        let pool = mock_pool();
+        let reserves = pool
+            .virtual_reserves_at_block(U64::from(10), middleware)
+            .await
+            .unwrap();
+
+        //The reserves are those of the pool's state at the block, while the pool itself is left untouched
+        let pool_at_block = UniswapV3Pool {
+            liquidity: 4_000_000_000_000_000_000,
+            sqrt_price: uniswap_v3_math::tick_math::get_sqrt_ratio_at_tick(1000).unwrap(),
+            tick: 1000,
+            ..pool
+        };
+        assert_eq!(
+            reserves,
+            pool_at_block.calculate_virtual_reserves().unwrap()
+        );
+        assert_ne!(reserves, pool.calculate_virtual_reserves().unwrap());
+        assert_eq!(pool, mock_pool());
+    }
+
+    #[tokio::test]
+    async fn test_lwap() {
+        let (provider, mock) = Provider::mocked();
+        let middleware = Arc::new(provider);
+
+        //(tick, liquidity) of the pool at blocks 10, 15 and 20
+        let samples = [
+            (0, 1_000_000_000_000_000_000_u128),
+            (100, 3_000_000_000_000_000_000),
+            (200, 1_000_000_000_000_000_000),
+        ];
+        for (tick, liquidity) in samples.iter().rev() {
+            mock.push::<Bytes, _>(Bytes::from(ethers::abi::encode(&[Token::Tuple(vec![
+                Token::Uint(U256::from(*liquidity)),
+                Token::Uint(uniswap_v3_math::tick_math::get_sqrt_ratio_at_tick(*tick).unwrap()),
+                Token::Int(encode_i24(*tick)),
+                Token::Int(U256::zero()),
+            ])])))
+            .unwrap();
+        }
+
+        let pool = mock_pool();
+        let lwap = pool
+            .lwap(U64::from(10), U64::from(20), 5, pool.token_a, middleware)
+            .await
+            .unwrap();
+
+        let prices = samples.map(|(tick, _)| pool.price_at_tick(tick));
+        assert!(lwap > prices[0] && lwap < prices[2]);
+
+        //The sample at tick 100 has three times the liquidity of the others
+        let expected = (prices[0] + 3.0 * prices[1] + prices[2]) / 5.0;
+        assert!((lwap / expected - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_nearest_tick_for_price() {
+        //USDC/WETH like decimals and tick spacing
+        let mut pool = UniswapV3Pool {
+            token_a_decimals: 6,
+            token_b_decimals: 18,
+            tick_spacing: 60,
+            ..mock_pool()
+        };
+
+        for tick in [-887220, -60, 0, 60, 201960, 887220] {
+            pool.tick = tick;
+            pool.sqrt_price = uniswap_v3_math::tick_math::get_sqrt_ratio_at_tick(tick).unwrap();
+
+            let price = pool.try_calculate_price(pool.token_a).unwrap();
+            assert_eq!(pool.nearest_tick_for_price(price, pool.token_a), tick);
+
+            let price = pool.try_calculate_price(pool.token_b).unwrap();
+            assert_eq!(pool.nearest_tick_for_price(price, pool.token_b), tick);
+        }
+
+        //Prices between usable ticks round to the nearest one
+        pool.tick = 201960;
+        pool.sqrt_price = uniswap_v3_math::tick_math::get_sqrt_ratio_at_tick(201960).unwrap();
+        let price = pool.try_calculate_price(pool.token_a).unwrap();
+        assert_eq!(
+            pool.nearest_tick_for_price(price * 1.0001_f64.powi(29), pool.token_a),
+            201960
+        );
+        assert_eq!(
+            pool.nearest_tick_for_price(price * 1.0001_f64.powi(31), pool.token_a),
+            202020
+        );
+
+        //Prices past the usable tick bounds are clamped
+        assert_eq!(pool.nearest_tick_for_price(f64::MAX, pool.token_a), 887220);
+    }
+
+    #[test]
+    fn test_zero_decimal_token_price() {
+        //A token without decimals priced at 2500 of an 18 decimal token
+        let sqrt_price = sqrt_price_from_price(2500.0, 0, 18);
+        let tick = uniswap_v3_math::tick_math::get_tick_at_sqrt_ratio(sqrt_price).unwrap();
+        let pool = UniswapV3Pool {
+            token_a_decimals: 0,
+            token_b_decimals: 18,
+            sqrt_price,
+            tick,
+            tick_spacing: 1,
+            ..mock_pool()
+        };
+
+        //Prices are rounded down to the tick, so they are within one tick of the exact price
+        let price_a = pool.try_calculate_price(pool.token_a).unwrap();
+        let price_b = pool.try_calculate_price(pool.token_b).unwrap();
+        assert!((price_a / 2500.0 - 1.0).abs() < 1e-4);
+        assert!((price_b * 2500.0 - 1.0).abs() < 1e-4);
+        assert_eq!(pool.nearest_tick_for_price(price_a, pool.token_a), tick);
+
+        let (reserve_0, reserve_1) = pool.calculate_virtual_reserves().unwrap();
+        let liquidity = pool.liquidity as f64;
+        assert!((reserve_0 as f64 / (liquidity / 50.0) - 1.0).abs() < 1e-4);
+        assert!((reserve_1 as f64 / (liquidity * 50.0) - 1.0).abs() < 1e-4);
+    }
+
+    #[tokio::test]
+    async fn test_price_stream() {
+        let (provider, mock) = Provider::mocked();
+        let pool = UniswapV3Pool {
+            last_synced_block: 9,
+            ..mock_pool()
+        };
+
+        let swap_log = |sqrt_price: U256, tick: i32| Log {
+            address: pool.address,
+            topics: vec![SWAP_EVENT_SIGNATURE],
+            data: ethers::abi::encode(&[
+                Token::Int(U256::zero()),
+                Token::Int(U256::zero()),
+                Token::Uint(sqrt_price),
+                Token::Uint(U256::from(pool.liquidity)),
+                Token::Int(encode_i24(tick)),
+            ])
+            .into(),
+            ..Default::default()
+        };
+
+        //Responses are popped in reverse order, the block number is requested before the logs
+        mock.push::<Vec<Log>, _>(vec![
+            swap_log(U256::one() << 97, 13863),
+            swap_log(U256::from(3) << 96, 21972),
+        ])
+        .unwrap();
+        mock.push(U64::from(10)).unwrap();
+
+        let prices = pool
+            .price_stream(pool.token_a, Arc::new(provider))
+            .take(2)
+            .collect::<Vec<_>>()
+            .await;
+
+        assert_eq!(prices.len(), 2);
+        assert!((prices[0].as_ref().unwrap() - 4.0).abs() < 1e-3);
+        assert!((prices[1].as_ref().unwrap() - 9.0).abs() < 1e-3);
+    }
+
+    #[tokio::test]
+    async fn test_price_stream_waits_after_error() {
+        //The mock has no responses, so every block number request fails
+        let (provider, _mock) = Provider::mocked();
+        let pool = mock_pool();
+
+        let start = std::time::Instant::now();
+        let prices = pool
+            .price_stream(pool.token_a, Arc::new(provider))
+            .take(2)
+            .collect::<Vec<_>>()
+            .await;
+
+        assert!(prices
+            .iter()
+            .all(|price| matches!(price, Err(CFMMError::MiddlewareError(_)))));
+        assert!(start.elapsed() >= super::PRICE_STREAM_POLL_INTERVAL);
+    }
+
+    #[tokio::test]
+    async fn test_update_pool_from_removed_swap_log() {
+        let (provider, mock) = Provider::mocked();
+
+        //The pool is at tick -25 after the reorg
+        let sqrt_price = uniswap_v3_math::tick_math::get_sqrt_ratio_at_tick(-25).unwrap();
+        mock.push::<Bytes, _>(Bytes::from(ethers::abi::encode(&[Token::Tuple(vec![
+            Token::Uint(U256::from(mock_pool().liquidity)),
+            Token::Uint(sqrt_price),
+            Token::Int(encode_i24(-25)),
+            Token::Int(I256::from(-1000).into_raw()),
+        ])])))
+        .unwrap();
+        mock.push(U64::from(101)).unwrap();
+
+        //Swap to tick 100 that was removed in the reorg
+        let mut pool = mock_pool();
+        let swap_log = Log {
+            address: pool.address,
+            topics: vec![SWAP_EVENT_SIGNATURE],
+            data: ethers::abi::encode(&[
+                Token::Int(U256::zero()),
+                Token::Int(U256::zero()),
+                Token::Uint(uniswap_v3_math::tick_math::get_sqrt_ratio_at_tick(100).unwrap()),
+                Token::Uint(U256::from(pool.liquidity)),
+                Token::Int(encode_i24(100)),
+            ])
+            .into(),
+            block_number: Some(U64::from(100)),
+            removed: Some(true),
+            ..Default::default()
+        };
+
+        pool.update_pool_from_swap_log(&swap_log, Arc::new(provider))
+            .await
+            .unwrap();
+
+        assert_eq!(pool.sqrt_price, sqrt_price);
+        assert_eq!(pool.tick, -25);
+        assert_eq!(pool.liquidity_net, -1000);
+        assert_eq!(pool.last_synced_block, 101);
+    }
+
+    #[tokio::test]
+    async fn test_update_pool_from_old_swap_log() {
+        let tick_info = |liquidity_net: i128| {
+            Bytes::from(ethers::abi::encode(&[
+                Token::Uint(U256::zero()),
+                Token::Int(I256::from(liquidity_net).into_raw()),
+                Token::Uint(U256::zero()),
+                Token::Uint(U256::zero()),
+                Token::Int(U256::zero()),
+                Token::Uint(U256::zero()),
+                Token::Uint(U256::zero()),
+                Token::Bool(true),
+            ]))
+        };
+
+        let middleware = Arc::new(Provider::new(BlockPinnedClient {
+            historical_response: tick_info(-1000),
+            latest_response: tick_info(5000),
+        }));
+
+        let mut pool = mock_pool();
+        let swap_log = Log {
+            address: pool.address,
+            topics: vec![SWAP_EVENT_SIGNATURE],
+            data: ethers::abi::encode(&[
+                Token::Int(U256::zero()),
+                Token::Int(U256::zero()),
+                Token::Uint(U256::one() << 96),
+                Token::Uint(U256::from(pool.liquidity)),
+                Token::Int(encode_i24(-10)),
+            ])
+            .into(),
+            block_number: Some(U64::from(100)),
+            ..Default::default()
+        };
+
+        pool.update_pool_from_swap_log(&swap_log, middleware)
+            .await
+            .unwrap();
+
+        assert_eq!(pool.tick, -10);
+        assert_eq!(pool.liquidity_net, -1000);
+    }
+
+    #[test]
+    fn test_impermanent_loss() {
+        let pool = mock_pool();
+        let price = pool.try_calculate_price(pool.token_a).unwrap();
+
+        assert!(pool.impermanent_loss(price, -1000, 1000).unwrap().abs() < 1e-12);
+
+        //The price has dropped from 1.05 to 1, which concentrated liquidity amplifies compared to a full range position
+        let concentrated_loss = pool.impermanent_loss(1.05, -1000, 1000).unwrap();
+        let full_range_loss = pool.impermanent_loss(1.05, -887270, 887270).unwrap();
+        assert!(concentrated_loss > 0.0);
+        assert!(concentrated_loss > full_range_loss);
+
+        //Reversed or empty ranges are rejected rather than panicking
+        assert!(matches!(
+            pool.impermanent_loss(1.05, 1000, -1000),
+            Err(ArithmeticError::InvalidTickRange(1000, -1000))
+        ));
+        assert!(matches!(
+            pool.impermanent_loss(1.05, 1000, 1000),
+            Err(ArithmeticError::InvalidTickRange(1000, 1000))
+        ));
+    }
+
+    #[test]
+    fn test_try_calculate_price_uninitialized_pool() {
+        let pool = UniswapV3Pool {
+            sqrt_price: U256::zero(),
+            ..mock_pool()
+        };
+
+        assert!(matches!(
+            pool.try_calculate_price(pool.token_a),
+            Err(ArithmeticError::UniswapV3MathError(_))
+        ));
+        assert!(pool.try_calculate_price(pool.token_b).is_err());
+    }
+
+    #[test]
+    fn test_is_active_liquidity_zero() {
+        let pool = UniswapV3Pool {
+            liquidity: 0,
+            ..mock_pool()
+        };
+        assert!(pool.is_active_liquidity_zero());
+        assert!(!mock_pool().is_active_liquidity_zero());
+    }
+
+    #[tokio::test]
+    async fn test_simulate_swap_pure() {
+        let (provider, mock) = Provider::mocked();
+
+        let ticks = [
+            (true, -10, 500_000_000_000_000_000),
+            (false, -20, 0),
+            (true, -30, 400_000_000_000_000_000),
+            (false, -887270, 0),
+        ];
+        mock.push::<Bytes, _>(mock_tick_data_response(&ticks, 1))
+            .unwrap();
+
+        let pool = mock_pool();
+        let amount_in = U256::from(5_000_000_000_000_000_u128);
+        let swap_result = pool
+            .simulate_swap_detailed(pool.token_a, amount_in, Arc::new(provider))
+            .await
+            .unwrap();
+
+        let ticks = ticks
+            .iter()
+            .map(|&(initialized, tick, liquidity_net)| TickData {
+                initialized,
+                tick,
+                liquidity_net,
+            })
+            .collect::<Vec<TickData>>();
+
+        assert_eq!(
+            simulate_swap_pure(
+                pool.sqrt_price,
+                pool.liquidity,
+                pool.tick,
+                pool.tick_spacing,
+                pool.fee,
+                &ticks,
+                true,
+                amount_in,
+            )
+            .unwrap(),
+            swap_result
+        );
+
+        let result = simulate_swap_pure(
+            pool.sqrt_price,
+            pool.liquidity,
+            pool.tick,
+            pool.tick_spacing,
+            pool.fee,
+            &ticks,
+            true,
+            U256::MAX,
+        );
+        assert!(
+            matches!(result, Err(ArithmeticError::AmountTooLarge(amount)) if amount == U256::MAX)
+        );
+
+        let result = simulate_swap_pure(
+            pool.sqrt_price,
+            pool.liquidity,
+            pool.tick,
+            pool.tick_spacing,
+            1_000_000,
+            &ticks,
+            true,
+            amount_in,
+        );
+        assert!(matches!(
+            result,
+            Err(ArithmeticError::InvalidFee(1_000_000))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_get_tick_data_concurrently() {
+        let pool = mock_pool();
+        let other_pool = UniswapV3Pool {
+            address: H160::from_low_u64_be(4),
+            tick: 100,
+            ..mock_pool()
+        };
+
+        let responses = [
+            (pool.address, true, [(true, -10, 1000), (true, -20, 2000)]),
+            (pool.address, false, [(true, 20, -1000), (false, 2560, 0)]),
+            (
+                other_pool.address,
+                true,
+                [(true, 100, 3000), (true, 50, 1000)],
+            ),
+            (
+                other_pool.address,
+                false,
+                [(true, 120, -1000), (true, 200, -2000)],
+            ),
+        ]
+        .into_iter()
+        .map(|(address, zero_for_one, ticks)| {
+            ((address, zero_for_one), mock_tick_data_response(&ticks, 5))
+        })
+        .collect();
+
+        let client = Arc::new(TickDataClient {
+            block_number: U64::from(5),
+            responses,
+            requests: Default::default(),
+        });
+        let middleware = Arc::new(Provider::new(client.clone()));
+
+        let tick_data = crate::batch_requests::uniswap_v3::get_tick_data_concurrently(
+            &[other_pool, pool],
+            2,
+            middleware,
+        )
+        .await
+        .unwrap();
+
+        //Results are aligned with the pools, sorted by tick
+        let ticks = tick_data
+            .iter()
+            .map(|(tick_data, block_number)| {
+                assert_eq!(*block_number, U64::from(5));
+                tick_data
+                    .iter()
+                    .map(|tick_data| tick_data.tick)
+                    .collect::<Vec<i32>>()
+            })
+            .collect::<Vec<Vec<i32>>>();
+
+        assert_eq!(
+            ticks,
+            vec![vec![50, 100, 120, 200], vec![-20, -10, 20, 2560]]
+        );
+
+        //One request per pool and direction, all pinned to the block fetched up front
+        let mut requests = client.requests.lock().unwrap().clone();
+        requests.sort_by_key(|(address, zero_for_one, _)| (*address, *zero_for_one));
+        assert_eq!(
+            requests,
+            vec![
+                (pool.address, false, serde_json::json!("0x5")),
+                (pool.address, true, serde_json::json!("0x5")),
+                (other_pool.address, false, serde_json::json!("0x5")),
+                (other_pool.address, true, serde_json::json!("0x5")),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_frozen_pool() {
+        let (provider, mock) = Provider::mocked();
+        let middleware = Arc::new(provider);
+
+        let ticks_below = [
+            (true, -10, 500_000_000_000_000_000),
+            (true, -30, 400_000_000_000_000_000),
+            (false, -887270, 0),
+        ];
+        let ticks_above = [(true, 20, -300_000_000_000_000_000), (false, 887270, 0)];
+
+        //Responses are popped in reverse order, freeze fetches the block number and then the ticks below before the ticks above
+        mock.push::<Bytes, _>(mock_tick_data_response(&ticks_above, 1))
+            .unwrap();
+        mock.push::<Bytes, _>(mock_tick_data_response(&ticks_below, 1))
+            .unwrap();
+        mock.push::<Bytes, _>(mock_tick_data_response(&ticks_above, 1))
+            .unwrap();
+        mock.push::<Bytes, _>(mock_tick_data_response(&ticks_below, 1))
+            .unwrap();
+        mock.push(U64::from(1)).unwrap();
+
+        let pool = mock_pool();
+        let frozen_pool = pool.freeze(10, middleware.clone()).await.unwrap();
+        assert_eq!(
+            frozen_pool
+                .ticks
+                .iter()
+                .map(|tick_data| tick_data.tick)
+                .collect::<Vec<i32>>(),
+            vec![-887270, -30, -10, 20, 887270]
+        );
+
+        let amount_in = U256::from(2_000_000_000_000_000_u128);
+        assert_eq!(
+            frozen_pool.simulate_swap(pool.token_a, amount_in).unwrap(),
+            pool.simulate_swap(pool.token_a, amount_in, middleware.clone())
+                .await
+                .unwrap()
+        );
+        assert_eq!(
+            frozen_pool.simulate_swap(pool.token_b, amount_in).unwrap(),
+            pool.simulate_swap(pool.token_b, amount_in, middleware)
+                .await
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_simulate_swap_from_state() {
+        let tick_data = [
+            (false, -887270, 0),
+            (true, -30, 400_000_000_000_000_000),
+            (true, -10, 500_000_000_000_000_000),
+            (true, 20, -300_000_000_000_000_000),
+            (false, 887270, 0),
+        ]
+        .map(|(initialized, tick, liquidity_net)| TickData {
+            initialized,
+            tick,
+            liquidity_net,
+        });
+
+        let pool = mock_pool();
+        let frozen_pool = FrozenPool {
+            token_a: pool.token_a,
+            token_b: pool.token_b,
+            fee: pool.fee,
+            tick_spacing: pool.tick_spacing,
+            sqrt_price: pool.sqrt_price,
+            tick: pool.tick,
+            liquidity: pool.liquidity,
+            ticks: tick_data.to_vec(),
+        };
+        let amount_in = U256::from(2_000_000_000_000_000_u128);
+
+        //Starting from the stored state matches the offline simulation of the pool
+        for token_in in [pool.token_a, pool.token_b] {
+            assert_eq!(
+                pool.simulate_swap_from_state(
+                    pool.sqrt_price,
+                    pool.tick,
+                    pool.liquidity,
+                    token_in,
+                    amount_in,
+                    &tick_data,
+                )
+                .unwrap(),
+                frozen_pool.simulate_swap(token_in, amount_in).unwrap()
+            );
+        }
+
+        //Doubling the liquidity, as after a hypothetical mint around the current price, gives a better quote
+        assert!(
+            pool.simulate_swap_from_state(
+                pool.sqrt_price,
+                pool.tick,
+                pool.liquidity * 2,
+                pool.token_a,
+                amount_in,
+                &tick_data,
+            )
+            .unwrap()
+                > frozen_pool.simulate_swap(pool.token_a, amount_in).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_update_pool_from_initialize_log() {
+        assert_eq!(
+            INITIALIZE_EVENT_SIGNATURE,
+            H256::from(ethers::utils::keccak256("Initialize(uint160,int24)"))
+        );
+
+        let mut pool = UniswapV3Pool {
+            sqrt_price: U256::zero(),
+            tick: 0,
+            ..mock_pool()
+        };
+
+        let result = pool
+            .simulate_swap(
+                pool.token_a,
+                U256::from(1_000_000),
+                Arc::new(Provider::mocked().0),
+            )
+            .await;
+        assert!(matches!(result, Err(CFMMError::PoolNotInitialized(_))));
+
+        //Initialized at a price of 0.25 token_b per token_a
+        let sqrt_price = U256::one() << 95;
+        let initialize_log = Log {
+            address: pool.address,
+            topics: vec![INITIALIZE_EVENT_SIGNATURE],
+            data: ethers::abi::encode(&[Token::Uint(sqrt_price), Token::Int(encode_i24(-13864))])
+                .into(),
+            ..Default::default()
+        };
+        pool.update_pool_from_initialize_log::<Provider<MockProvider>>(&initialize_log)
+            .unwrap();
+
+        assert_eq!(pool.sqrt_price, sqrt_price);
+        assert_eq!(pool.tick, -13864);
+        assert!((pool.try_calculate_price(pool.token_a).unwrap() - 0.25).abs() < 1e-4);
+    }
+
+    #[tokio::test]
+    async fn test_simulate_swap_amount_too_large() {
+        let (provider, _mock) = Provider::mocked();
+        let middleware = Arc::new(provider);
+        let mut pool = mock_pool();
+
+        let result = pool
+            .simulate_swap(pool.token_a, U256::MAX, middleware.clone())
+            .await;
+        assert!(matches!(result, Err(CFMMError::AmountTooLarge(amount)) if amount == U256::MAX));
+
+        let result = pool
+            .simulate_swap_mut(pool.token_a, U256::MAX, middleware)
+            .await;
+        assert!(matches!(result, Err(CFMMError::AmountTooLarge(_))));
+        assert_eq!(pool, mock_pool());
+    }
+
+    #[test]
+    fn test_swap_amounts_near_overflow() {
+        let pool = mock_pool();
+
+        //The largest exact input amount is consumed without wrapping the amount remaining
+        let amount_in = I256::MAX.into_raw();
+        let result = simulate_swap_pure(
+            pool.sqrt_price,
+            pool.liquidity,
+            pool.tick,
+            pool.tick_spacing,
+            pool.fee,
+            &[],
+            true,
+            amount_in,
+        )
+        .unwrap();
+        assert!(!result.amount_in.is_zero() && result.amount_in < amount_in);
+        assert!(!result.amount_out.is_zero());
+
+        //A step that would push the amount calculated past I256::MIN is an error rather than wrapping around to a positive amount
+        let mut current_state = CurrentState {
+            amount_specified_remaining: I256::from(100),
+            amount_calculated: I256::MIN + I256::one(),
+            sqrt_price_x_96: pool.sqrt_price,
+            tick: pool.tick,
+            liquidity: pool.liquidity,
+            initialized_ticks_crossed: 0,
+        };
+        let step = StepComputations {
+            amount_in: U256::from(90),
+            fee_amount: U256::from(10),
+            amount_out: U256::from(2),
+            ..Default::default()
+        };
+        assert!(matches!(
+            apply_step_amounts(&mut current_state, &step),
+            Err(ArithmeticError::AmountOverflow(_))
+        ));
+
+        //A step amount that does not fit in an I256 is an error rather than being reinterpreted as negative
+        let step = StepComputations {
+            amount_in: U256::MAX - 1,
+            fee_amount: U256::one(),
+            ..Default::default()
+        };
+        assert!(matches!(
+            apply_step_amounts(&mut current_state, &step),
+            Err(ArithmeticError::AmountOverflow(_))
+        ));
+    }
+
+    #[cfg(test)]
+    proptest::proptest! {
+        #[test]
+        fn test_simulate_swap_pure_bounds(
+            tick in -887272..=887272_i32,
+            liquidity in proptest::prelude::any::<u128>(),
+            tick_spacing in proptest::sample::select(vec![1, 10, 60, 200]),
+            fee in proptest::sample::select(vec![100_u32, 500, 3000, 10000]),
+            tick_data in proptest::collection::vec(
+                (proptest::prelude::any::<bool>(), 1..10_000_i32, proptest::prelude::any::<i128>()),
+                0..10,
+            ),
+            zero_for_one in proptest::prelude::any::<bool>(),
+            amount_in in proptest::prelude::any::<u128>(),
+        ) {
+            let sqrt_price = uniswap_v3_math::tick_math::get_sqrt_ratio_at_tick(tick).unwrap();
+            let amount_in = U256::from(amount_in);
+
+            //Ticks are laid out in the direction of the swap, each a random number of tick spacings past the previous one
+            let mut next_tick = tick / tick_spacing * tick_spacing;
+            let ticks = tick_data
+                .into_iter()
+                .map(|(initialized, spacings, liquidity_net)| {
+                    let offset = spacings * tick_spacing;
+                    next_tick = if zero_for_one {
+                        next_tick.saturating_sub(offset)
+                    } else {
+                        next_tick.saturating_add(offset)
+                    };
+
+                    TickData {
+                        initialized,
+                        tick: next_tick,
+                        liquidity_net,
+                    }
+                })
+                .collect::<Vec<TickData>>();
+
+            let result = simulate_swap_pure(
+                sqrt_price,
+                liquidity,
+                tick,
+                tick_spacing,
+                fee,
+                &ticks,
+                zero_for_one,
+                amount_in,
+            );
+
+            if let Ok(result) = result {
+                proptest::prop_assert!(result.amount_in <= amount_in);
+
+                //The price only moves against the trader, so the output can not exceed the amount in valued at the starting price
+                let q96 = U256::one() << 96;
+                let max_amount_out = if zero_for_one {
+                    uniswap_v3_math::full_math::mul_div_rounding_up(amount_in, sqrt_price, q96)
+                        .and_then(|amount| uniswap_v3_math::full_math::mul_div_rounding_up(amount, sqrt_price, q96))
+                } else {
+                    uniswap_v3_math::full_math::mul_div_rounding_up(amount_in, q96, sqrt_price)
+                        .and_then(|amount| uniswap_v3_math::full_math::mul_div_rounding_up(amount, q96, sqrt_price))
+                };
+
+                if let Ok(max_amount_out) = max_amount_out {
+                    proptest::prop_assert!(result.amount_out <= max_amount_out);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_new_with_identical_tokens() {
+        let token = H160::from_low_u64_be(2);
+
+        let result = UniswapV3Pool::try_new(
+            H160::from_low_u64_be(1),
+            token,
+            18,
+            token,
+            18,
+            500,
+            0,
+            U256::one() << 96,
+            0,
+            10,
+            0,
+        );
+
+        assert!(matches!(
+            result,
+            Err(PoolError::IdenticalTokens(address)) if address == H160::from_low_u64_be(1)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_get_pool_data_with_identical_tokens() {
+        let (provider, mock) = Provider::mocked();
+        let token = H160::from_low_u64_be(2);
+
+        mock.push::<Bytes, _>(Bytes::from(ethers::abi::encode(&[Token::Array(vec![
+            Token::Tuple(vec![
+                Token::Address(token),
+                Token::Uint(U256::from(18)),
+                Token::Address(token),
+                Token::Uint(U256::from(18)),
+                Token::Uint(U256::zero()),
+                Token::Uint(U256::one() << 96),
+                Token::Int(U256::zero()),
+                Token::Int(U256::from(10)),
+                Token::Uint(U256::from(500)),
+                Token::Int(U256::zero()),
+            ]),
+        ])])))
+        .unwrap();
+
+        let mut pool = UniswapV3Pool {
+            address: H160::from_low_u64_be(1),
+            ..Default::default()
+        };
+
+        let result = pool.get_pool_data(Arc::new(provider)).await;
+
+        assert!(matches!(result, Err(CFMMError::IdenticalTokens(_))));
+    }
+
+    #[tokio::test]
+    async fn test_simulate_swap_with_protocol_fee() {
+        let (provider, mock) = Provider::mocked();
+        let middleware = Arc::new(provider);
+        let pool = mock_pool();
+
+        //Responses are popped in reverse order, the tick data for both swaps is requested after slot0
+        let ticks = [(true, -10, 1_000_000)];
+        mock.push::<Bytes, _>(mock_tick_data_response(&ticks, 1))
+            .unwrap();
+        mock.push::<Bytes, _>(mock_tick_data_response(&ticks, 1))
+            .unwrap();
+
+        //slot0 with feeProtocol set to 1/4 of the LP fee for both tokens
+        mock.push::<Bytes, _>(Bytes::from(ethers::abi::encode(&[
+            Token::Uint(pool.sqrt_price),
+            Token::Int(U256::zero()),
+            Token::Uint(U256::zero()),
+            Token::Uint(U256::one()),
+            Token::Uint(U256::one()),
+            Token::Uint(U256::from(4 | 4 << 4)),
+            Token::Bool(true),
+        ])))
+        .unwrap();
+
+        let amount_in = U256::from(1_000_000_000_u128);
+        let (amount_out, protocol_fee) = pool
+            .simulate_swap_with_protocol_fee(pool.token_a, amount_in, middleware.clone())
+            .await
+            .unwrap();
+
+        //The LP fee is 0.05% of the amount in, a quarter of which goes to the protocol
+        assert_eq!(protocol_fee, U256::from(125_000));
+        assert_eq!(
+            amount_out,
+            pool.simulate_swap(pool.token_a, amount_in, middleware)
+                .await
+                .unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_all_initialized_ticks() {
+        let (provider, mock) = Provider::mocked();
+        let pool = UniswapV3Pool {
+            tick_spacing: 200,
+            ..mock_pool()
+        };
+
+        //A tick spacing of 200 covers compressed ticks -4436 to 4436, which spans words -18 to 17
+        let mut words = vec![U256::zero(); 36];
+        words[10] = U256::from(0b1011);
+        words[20] = U256::one() << 255 | U256::one();
+        let expected_count = words
+            .iter()
+            .map(|word| (0..256).filter(|&bit| word.bit(bit)).count())
+            .sum::<usize>();
+
+        //Responses are popped in reverse order, the ticks are requested after every word
+        for _ in 0..expected_count {
+            mock.push::<Bytes, _>(Bytes::from(ethers::abi::encode(&[
+                Token::Uint(U256::from(1000)),
+                Token::Int(U256::from(1000)),
+                Token::Uint(U256::zero()),
+                Token::Uint(U256::zero()),
+                Token::Int(U256::zero()),
+                Token::Uint(U256::zero()),
+                Token::Uint(U256::zero()),
+                Token::Bool(true),
+            ])))
+            .unwrap();
+        }
+        for word in words.iter().rev() {
+            mock.push::<Bytes, _>(Bytes::from(ethers::abi::encode(&[Token::Uint(*word)])))
+                .unwrap();
+        }
+        mock.push(U64::from(1)).unwrap();
+
+        let ticks = pool
+            .get_all_initialized_ticks(Arc::new(provider))
+            .await
+            .unwrap();
+
+        assert_eq!(ticks.len(), expected_count);
+        assert!(ticks
+            .iter()
+            .all(|(tick, liquidity_net)| tick % 200 == 0 && *liquidity_net == 1000));
+        assert!(ticks.windows(2).all(|pair| pair[0].0 < pair[1].0));
+    }
+
+    #[tokio::test]
+    async fn test_relative_depth() {
+        let (provider, mock) = Provider::mocked();
+        let middleware = Arc::new(provider);
+
+        for _ in 0..4 {
+            mock.push::<Bytes, _>(mock_tick_data_response(&[(false, -887270, 0)], 1))
+                .unwrap();
+        }
+
+        let deep_pool = UniswapV3Pool {
+            fee: 3000,
+            ..mock_pool()
+        };
+
+        //Cheaper fee tier but 100x less liquidity
+        let shallow_pool = UniswapV3Pool {
+            address: H160::from_low_u64_be(4),
+            liquidity: 10_000_000_000_000_000,
+            fee: 500,
+            ..mock_pool()
+        };
+
+        let amount_in = U256::from(1_000_000_000_000_000_u128);
+
+        let depth = deep_pool
+            .relative_depth(
+                &shallow_pool,
+                deep_pool.token_a,
+                amount_in,
+                middleware.clone(),
+            )
+            .await
+            .unwrap();
+        assert!(depth > 1.05);
+
+        let depth = shallow_pool
+            .relative_depth(&deep_pool, deep_pool.token_a, amount_in, middleware)
+            .await
+            .unwrap();
+        assert!(depth < 0.95);
+    }
+
+    #[tokio::test]
+    async fn test_usable_tick_bounds() {
+        let pool = UniswapV3Pool {
+            tick_spacing: 200,
+            ..mock_pool()
+        };
+
+        assert_eq!(pool.usable_tick_bounds(), (-887200, 887200));
+        assert_eq!(mock_pool().usable_tick_bounds(), (-887270, 887270));
+
+        //The swap stops at the usable min tick rather than the min tick
+        let (provider, mock) = Provider::mocked();
+        mock.push::<Bytes, _>(mock_tick_data_response(&[(false, -887272, 0)], 1))
+            .unwrap();
+
+        let (_, trace) = pool
+            .simulate_swap_trace(pool.token_a, U256::MAX >> 1, Arc::new(provider))
+            .await
+            .unwrap();
+
+        assert_eq!(trace.len(), 1);
+        assert_eq!(trace[0].tick_next, -887200);
+    }
+
+    #[tokio::test]
+    async fn test_simulate_swap_mut_cancellation_leaves_pool_unchanged() {
+        let mock = MockProvider::new();
+
+        //Tick data that is exhausted before the amount is consumed, forcing a refetch that never resolves
+        mock.push::<Bytes, _>(mock_tick_data_response(&[(true, -10, 1_000_000)], 1))
+            .unwrap();
+
+        let middleware = Arc::new(Provider::new(StallingClient {
+            mock,
+            max_requests: 1,
+            requests: std::sync::atomic::AtomicUsize::new(0),
+        }));
+
+        let mut pool = mock_pool();
+        let original_pool = pool;
+
+        let result = tokio::time::timeout(
+            std::time::Duration::from_millis(100),
+            pool.simulate_swap_mut(
+                pool.token_a,
+                U256::from(1_000_000_000_000_000_u128),
+                middleware,
+            ),
+        )
+        .await;
+
+        assert!(result.is_err());
+        assert_eq!(pool, original_pool);
+    }
+
+    #[tokio::test]
+    async fn test_simulate_swap_exact_output() {
+        let (middleware, mock) = Provider::mocked();
+        for _ in 0..3 {
+            mock.push::<Bytes, _>(mock_tick_data_response(
+                &[(true, -10, 0), (false, -887270, 0)],
+                1,
+            ))
+            .unwrap();
+        }
+        let middleware = Arc::new(middleware);
+
+        let pool = mock_pool();
+        let amount_out = U256::from(1_000_000_000_000_000_u128);
+
+        let amount_in = pool
+            .simulate_swap_exact_output(pool.token_a, amount_out, middleware.clone())
+            .await
+            .unwrap();
+
+        //Swapping the amount in forward receives at least amount_out, while one less does not
+        assert!(
+            pool.simulate_swap(pool.token_a, amount_in, middleware.clone())
+                .await
+                .unwrap()
+                >= amount_out
+        );
+        assert!(
+            pool.simulate_swap(pool.token_a, amount_in - 1, middleware.clone())
+                .await
+                .unwrap()
+                < amount_out
+        );
+
+        //The pool holds less than 1e18 of token_b between the current price and the min sqrt price
+        let (middleware, mock) = Provider::mocked();
+        mock.push::<Bytes, _>(mock_tick_data_response(
+            &[(true, -10, 0), (false, -887270, 0)],
+            1,
+        ))
+        .unwrap();
+
+        assert!(matches!(
+            pool.simulate_swap_exact_output(
+                pool.token_a,
+                U256::from(2_000_000_000_000_000_000_u128),
+                Arc::new(middleware),
+            )
+            .await,
+            Err(CFMMError::InsufficientLiquidity(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_verify_decimals() {
+        let decimals_response =
+            |decimals: u8| Bytes::from(ethers::abi::encode(&[Token::Uint(U256::from(decimals))]));
+
+        //token_a reports 6 decimals on-chain, token_b reports 18
+        let (middleware, mock) = Provider::mocked();
+        for _ in 0..2 {
+            mock.push::<Bytes, _>(decimals_response(18)).unwrap();
+            mock.push::<Bytes, _>(decimals_response(6)).unwrap();
+        }
+        let middleware = Arc::new(middleware);
+
+        let stale_pool = mock_pool();
+        assert!(!stale_pool
+            .verify_decimals(middleware.clone())
+            .await
+            .unwrap());
+
+        let pool = UniswapV3Pool {
+            token_a_decimals: 6,
+            ..mock_pool()
+        };
+        assert!(pool.verify_decimals(middleware).await.unwrap());
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn test_decode_initialized_ticks() {
+        use super::decode_initialized_ticks;
+
+        let words = vec![
+            (3, U256::one() << 255 | U256::from(5)),
+            (-1, U256::MAX),
+            (0, U256::zero()),
+            (-3466, U256::one() << 200),
+            (0, U256::one()),
+        ];
+
+        for tick_spacing in [1, 10, 60, 200] {
+            let mut expected = vec![];
+            for (word_pos, word) in &words {
+                for bit_pos in 0..256 {
+                    if word.bit(bit_pos) {
+                        expected.push((*word_pos as i32 * 256 + bit_pos as i32) * tick_spacing);
+                    }
+                }
+            }
+            expected.sort();
+
+            assert_eq!(decode_initialized_ticks(&words, tick_spacing), expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_simulate_swap_pending() {
+        let (middleware, mock) = Provider::mocked();
+
+        //Quote against the current pool
+        mock.push::<Bytes, _>(mock_tick_data_response(
+            &[(true, -10, 0), (false, -887270, 0)],
+            1,
+        ))
+        .unwrap();
+        //Quote against the pool after the pending swap
+        mock.push::<Bytes, _>(mock_tick_data_response(
+            &[(true, -110, 0), (false, -887270, 0)],
+            1,
+        ))
+        .unwrap();
+        //Liquidity net of tick -100
+        mock.push::<Bytes, _>(Bytes::from(ethers::abi::encode(&[
+            Token::Uint(U256::zero()),
+            Token::Int(U256::zero()),
+            Token::Uint(U256::zero()),
+            Token::Uint(U256::zero()),
+            Token::Int(U256::zero()),
+            Token::Uint(U256::zero()),
+            Token::Uint(U256::zero()),
+            Token::Bool(false),
+        ])))
+        .unwrap();
+        let middleware = Arc::new(middleware);
+
+        let pool = mock_pool();
+
+        //Pending swap of token_a for token_b that moves the pool to tick -100
+        let pending_swap = Log {
+            address: pool.address,
+            topics: vec![SWAP_EVENT_SIGNATURE],
+            data: ethers::abi::encode(&[
+                Token::Int(U256::zero()),
+                Token::Int(U256::zero()),
+                Token::Uint(uniswap_v3_math::tick_math::get_sqrt_ratio_at_tick(-100).unwrap()),
+                Token::Uint(U256::from(pool.liquidity)),
+                Token::Int(encode_i24(-100)),
+            ])
+            .into(),
+            ..Default::default()
+        };
+        //Swaps in other pools do not affect the quote
+        let other_pool_swap = Log {
+            address: H160::from_low_u64_be(99),
+            ..pending_swap.clone()
+        };
+
+        let amount_in = U256::from(10_u128.pow(15));
+        let pending_amount_out = pool
+            .simulate_swap_pending(
+                pool.token_a,
+                amount_in,
+                &[pending_swap, other_pool_swap],
+                middleware.clone(),
+            )
+            .await
+            .unwrap();
+        let amount_out = pool
+            .simulate_swap(pool.token_a, amount_in, middleware)
+            .await
+            .unwrap();
+
+        //The pending swap lowered the price of token_a by about 1%, and the pool itself is left untouched
+        assert!(pending_amount_out < amount_out);
+        let ratio = pending_amount_out.as_u128() as f64 / amount_out.as_u128() as f64;
+        assert!((ratio - 1.0001_f64.powi(-100)).abs() < 1e-3);
+        assert_eq!(pool, mock_pool());
+    }
+
+    #[tokio::test]
+    async fn test_simulate_swap_does_not_converge() {
+        //Every refetch returns ticks at the current price, so each step swaps nothing and the price never moves
+        let tick_data = mock_tick_data_response(&vec![(false, 0, 0); 1000], 1);
+        let middleware = Arc::new(Provider::new(BlockPinnedClient {
+            historical_response: tick_data.clone(),
+            latest_response: tick_data,
+        }));
+
+        //A wide tick spacing keeps the number of steps before the guard trips small
+        let mut pool = UniswapV3Pool {
+            tick_spacing: 200,
+            ..mock_pool()
+        };
+        assert!(matches!(
+            pool.simulate_swap(pool.token_a, U256::from(10_u128.pow(15)), middleware.clone())
+                .await,
+            Err(CFMMError::SimulationDidNotConverge(address)) if address == pool.address
+        ));
+        assert!(matches!(
+            pool.simulate_swap_mut(pool.token_a, U256::from(10_u128.pow(15)), middleware)
+                .await,
+            Err(CFMMError::SimulationDidNotConverge(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_at_block() {
+        let sync_response = |tick: i32, liquidity_net: i128| {
+            Bytes::from(ethers::abi::encode(&[Token::Tuple(vec![
+                Token::Uint(U256::from(mock_pool().liquidity)),
+                Token::Uint(uniswap_v3_math::tick_math::get_sqrt_ratio_at_tick(tick).unwrap()),
+                Token::Int(encode_i24(tick)),
+                Token::Int(I256::from(liquidity_net).into_raw()),
+            ])]))
+        };
+
+        //The pool was at tick -25 at the historical block and is at tick 100 now
+        let middleware = Arc::new(Provider::new(BlockPinnedClient {
+            historical_response: sync_response(-25, -1000),
+            latest_response: sync_response(100, 5000),
+        }));
+
+        let pool = mock_pool();
+        let historical_pool = pool.at_block(U64::from(100), middleware).await.unwrap();
+
+        assert_eq!(historical_pool.tick, -25);
+        assert_eq!(historical_pool.liquidity_net, -1000);
+        assert_eq!(historical_pool.last_synced_block, 100);
+        assert!(
+            (historical_pool.try_calculate_price(pool.token_a).unwrap() - 1.0001_f64.powi(-25))
+                .abs()
+                < 1e-9
+        );
+        assert_eq!(pool, mock_pool());
+    }
+
+    #[tokio::test]
+    async fn test_estimate_collectible() {
+        let tick_info = |fee_growth_outside_0: U256, fee_growth_outside_1: U256| {
+            Bytes::from(ethers::abi::encode(&[
+                Token::Uint(U256::zero()),
+                Token::Int(U256::zero()),
+                Token::Uint(fee_growth_outside_0),
+                Token::Uint(fee_growth_outside_1),
+                Token::Int(U256::zero()),
+                Token::Uint(U256::zero()),
+                Token::Uint(U256::zero()),
+                Token::Bool(true),
+            ]))
+        };
+        let q128 = U256::one() << 128;
+
+        let (middleware, mock) = Provider::mocked();
+        //Upper tick, above the current tick
+        mock.push::<Bytes, _>(tick_info(q128 * 2, q128)).unwrap();
+        //Lower tick, at or below the current tick
+        mock.push::<Bytes, _>(tick_info(q128 * 3, q128 * 4))
+            .unwrap();
+        //Fee growth global of token_1 and token_0
+        mock.push::<Bytes, _>(Bytes::from(ethers::abi::encode(&[Token::Uint(q128 * 20)])))
+            .unwrap();
+        mock.push::<Bytes, _>(Bytes::from(ethers::abi::encode(&[Token::Uint(q128 * 10)])))
+            .unwrap();
+
+        //The position last collected when the fee growth inside was 1 and 5, and already has 7 and 0 owed
+        let pool = mock_pool();
+        let collectible = pool
+            .estimate_collectible(
+                -100,
+                100,
+                1000,
+                (q128, q128 * 5),
+                (7, 0),
+                Arc::new(middleware),
+            )
+            .await
+            .unwrap();
+
+        //The fee growth inside is 10 - 3 - 2 = 5 and 20 - 4 - 1 = 15, so the position earned 4 and 10 per unit of liquidity
+        assert_eq!(collectible, (U256::from(4007), U256::from(10000)));
+    }
+
+    #[test]
+    fn test_parse_swap_event() {
+        let sender = H160::from_str("0xE592427A0AEce92De3Edee1F18E0157C05861564").unwrap();
+        let recipient = H160::from_str("0x3fC91A3afd70395Cd496C647d5a6CC9D4B2b7FAD").unwrap();
+        let sqrt_price = U256::from_dec_str("1893972181206428470391398591213436").unwrap();
+
+        //Swap of 1 WETH for 1749.27 USDC in the USDC/WETH 0.05% pool
+        let swap_log = Log {
+            address: H160::from_str("0x88e6A0c2dDD26FEEb64F039a2c41296FcB3f5640").unwrap(),
+            topics: vec![
+                SWAP_EVENT_SIGNATURE,
+                H256::from(sender),
+                H256::from(recipient),
+            ],
+            data: ethers::abi::encode(&[
+                Token::Int(I256::from(-1_749_270_000_i64).into_raw()),
+                Token::Int(I256::from(10_i64.pow(18)).into_raw()),
+                Token::Uint(sqrt_price),
+                Token::Uint(U256::from(8_361_930_284_923_434_u128)),
+                Token::Int(encode_i24(201_226)),
+            ])
+            .into(),
+            ..Default::default()
+        };
+
+        let swap_event = parse_swap_event::<Provider<MockProvider>>(&swap_log).unwrap();
+        assert_eq!(swap_event.amount0, I256::from(-1_749_270_000_i64));
+        assert_eq!(swap_event.amount1, I256::from(10_i64.pow(18)));
+        assert_eq!(swap_event.sqrt_price, sqrt_price);
+        assert_eq!(swap_event.liquidity, 8_361_930_284_923_434);
+        assert_eq!(swap_event.tick, 201_226);
+        assert_eq!(swap_event.sender, sender);
+        assert_eq!(swap_event.recipient, recipient);
+
+        //A log without the indexed addresses is not a Swap event
+        let swap_log = Log {
+            topics: vec![SWAP_EVENT_SIGNATURE],
+            ..swap_log
+        };
+        assert!(matches!(
+            parse_swap_event::<Provider<MockProvider>>(&swap_log),
+            Err(CFMMError::AbiDecode(_, topic)) if topic == SWAP_EVENT_SIGNATURE
+        ));
+    }
+
+    #[test]
+    fn test_diff() {
+        let pool = mock_pool();
+        let later = UniswapV3Pool {
+            sqrt_price: uniswap_v3_math::tick_math::get_sqrt_ratio_at_tick(-100).unwrap(),
+            tick: -100,
+            liquidity: pool.liquidity - 400,
+            ..pool
+        };
+
+        let diff = pool.diff(&later).unwrap();
+        assert!((diff.price_change_pct - (1.0001_f64.powi(-100) - 1.0) * 100.0).abs() < 1e-9);
+        assert_eq!(diff.tick_delta, -100);
+        assert_eq!(diff.liquidity_delta, -400);
+
+        let unchanged = pool.diff(&pool).unwrap();
+        assert_eq!(unchanged, PoolDiff::default());
+
+        //Snapshots of different pools can not be compared
+        let other_pool = UniswapV3Pool {
+            address: H160::from_low_u64_be(99),
+            ..later
+        };
+        assert!(pool.diff(&other_pool).is_none());
+    }
+
+    #[test]
+    fn test_looks_like_stable_pair() {
+        //USDC/USDT 0.01% pool
+        let usdc_usdt = UniswapV3Pool {
+            address: H160::from_str("0x3416cF6C708Da44DB2624D63ea0AAef7113527C6").unwrap(),
+            token_a: H160::from_str("0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48").unwrap(),
+            token_a_decimals: 6,
+            token_b: H160::from_str("0xdAC17F958D2ee523a2206206994597C13D831ec7").unwrap(),
+            token_b_decimals: 6,
+            sqrt_price: uniswap_v3_math::tick_math::get_sqrt_ratio_at_tick(-3).unwrap(),
+            tick: -3,
+            fee: 100,
+            tick_spacing: 1,
+            ..Default::default()
+        };
+        assert!(usdc_usdt.looks_like_stable_pair());
+
+        //USDC/WETH 0.05% pool
+        let usdc_weth = UniswapV3Pool {
+            address: H160::from_str("0x88e6A0c2dDD26FEEb64F039a2c41296FcB3f5640").unwrap(),
+            token_a: H160::from_str("0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48").unwrap(),
+            token_a_decimals: 6,
+            token_b: H160::from_str("0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2").unwrap(),
+            token_b_decimals: 18,
+            sqrt_price: uniswap_v3_math::tick_math::get_sqrt_ratio_at_tick(201_226).unwrap(),
+            tick: 201_226,
+            fee: 500,
+            tick_spacing: 10,
+            ..Default::default()
+        };
+        assert!(!usdc_weth.looks_like_stable_pair());
+
+        //A USDC/USDT pool on the 0.3% tier is only stable with a higher fee threshold
+        let usdc_usdt = UniswapV3Pool {
+            fee: 3000,
+            ..usdc_usdt
+        };
+        assert!(!usdc_usdt.looks_like_stable_pair());
+        assert!(usdc_usdt.looks_like_stable_pair_within(50, 3000));
+        assert!(!usdc_usdt.looks_like_stable_pair_within(2, 3000));
+    }
+
+    #[test]
+    fn test_tick_data_from_tick() {
+        let tick = Tick::from((
+            700_000_000_000_000_000_u128,
+            500_000_000_000_000_000_i128,
+            U256::from(7),
+            U256::from(11),
+            -42_i64,
+            U256::zero(),
+            0,
+            true,
+        ));
+        assert_eq!(tick.tick_cumulative_outside, I256::from(-42).into_raw());
+
+        let tick_data = TickData::from((-10, tick));
+        assert_eq!(
+            tick_data,
+            TickData {
+                initialized: true,
+                tick: -10,
+                liquidity_net: 500_000_000_000_000_000,
+            }
+        );
+
+        //Crossing the converted tick downwards removes its liquidity net from the active liquidity
+        let pool = mock_pool();
+        let swap_result = simulate_swap_pure(
+            pool.sqrt_price,
+            pool.liquidity,
+            pool.tick,
+            pool.tick_spacing,
+            pool.fee,
+            &[tick_data, TickData::from((-887270, Tick::default()))],
+            true,
+            U256::from(5_000_000_000_000_000_u128),
+        )
+        .unwrap();
+        assert!(swap_result.tick_after < -10);
+        assert_eq!(swap_result.ticks_crossed, 1);
+        assert_eq!(
+            swap_result.liquidity_after,
+            pool.liquidity - 500_000_000_000_000_000
+        );
+    }
+
+    #[test]
+    fn test_apply_step_amounts_clamps_remaining() {
+        let mut current_state = CurrentState {
+            amount_specified_remaining: I256::from(100),
+            amount_calculated: I256::zero(),
+            sqrt_price_x_96: U256::one() << 96,
+            tick: 0,
+            liquidity: 0,
+            initialized_ticks_crossed: 0,
+        };
+        let step = StepComputations {
+            amount_in: U256::from(95),
+            fee_amount: U256::from(6),
+            amount_out: U256::from(90),
+            ..Default::default()
+        };
+
+        //Consuming one more than the amount remaining ends the exact input swap instead of turning it into an exact output swap
+        apply_step_amounts(&mut current_state, &step).unwrap();
+        assert_eq!(current_state.amount_specified_remaining, I256::zero());
+        assert_eq!(current_state.amount_calculated, I256::from(-90));
+
+        let mut current_state = CurrentState {
+            amount_specified_remaining: I256::from(-89),
+            amount_calculated: I256::zero(),
+            sqrt_price_x_96: U256::one() << 96,
+            tick: 0,
+            liquidity: 0,
+            initialized_ticks_crossed: 0,
+        };
+        apply_step_amounts(&mut current_state, &step).unwrap();
+        assert_eq!(current_state.amount_specified_remaining, I256::zero());
+        assert_eq!(current_state.amount_calculated, I256::from(101));
+    }
+
+    #[tokio::test]
+    async fn test_simulate_swap_exactly_to_tick_boundary() {
+        let (middleware, mock) = Provider::mocked();
+        mock.push::<Bytes, _>(mock_tick_data_response(
+            &[(true, -10, 0), (false, -887270, 0)],
+            1,
+        ))
+        .unwrap();
+
+        //Amount in, including the fee, that moves the price exactly to tick -10
+        let pool = mock_pool();
+        let (_, amount_in, _, fee_amount) = uniswap_v3_math::swap_math::compute_swap_step(
+            pool.sqrt_price,
+            uniswap_v3_math::tick_math::get_sqrt_ratio_at_tick(-10).unwrap(),
+            pool.liquidity,
+            I256::MAX,
+            pool.fee,
+        )
+        .unwrap();
+
+        let (_, trace) = pool
+            .simulate_swap_trace(pool.token_a, amount_in + fee_amount, Arc::new(middleware))
+            .await
+            .unwrap();
+
+        //The swap ends on the step that reaches the boundary without an extra step past it
+        assert_eq!(trace.len(), 1);
+        assert_eq!(trace[0].tick_next, -10);
+        assert_eq!(
+            trace[0].sqrt_price_next_x96,
+            uniswap_v3_math::tick_math::get_sqrt_ratio_at_tick(-10).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_age() {
+        let (middleware, mock) = Provider::mocked();
+        mock.push::<Bytes, _>(Bytes::from(ethers::abi::encode(&[Token::Tuple(vec![
+            Token::Uint(U256::from(mock_pool().liquidity)),
+            Token::Uint(mock_pool().sqrt_price),
+            Token::Int(encode_i24(0)),
+            Token::Int(U256::zero()),
+        ])])))
+        .unwrap();
+        mock.push(U64::from(7)).unwrap();
+
+        let mut pool = mock_pool();
+        assert_eq!(pool.age(), None);
+
+        pool.sync_pool(Arc::new(middleware)).await.unwrap();
+        assert!(pool.age().unwrap() < std::time::Duration::from_secs(5));
+
+        //The sync time survives serialization
+        let json = serde_json::to_string(&pool).unwrap();
+        assert_eq!(
+            serde_json::from_str::<UniswapV3Pool>(&json)
+                .unwrap()
+                .synced_at,
+            pool.synced_at
+        );
+    }
+
+    #[test]
+    fn test_amounts_for_liquidity() {
+        let pool = mock_pool();
+        let liquidity = 10_u128.pow(18);
+        let sqrt_price_lower = 1.0001_f64.powi(-100).sqrt();
+        let sqrt_price_upper = 1.0001_f64.powi(100).sqrt();
+
+        //Within the range the position holds L * (1 / sqrt(P) - 1 / sqrt(Pb)) of token_a and L * (sqrt(P) - sqrt(Pa)) of token_b
+        let (amount_a, amount_b) = pool.amounts_for_liquidity(-100, 100, liquidity).unwrap();
+        let expected_a = liquidity as f64 * (1.0 - 1.0 / sqrt_price_upper);
+        let expected_b = liquidity as f64 * (1.0 - sqrt_price_lower);
+        assert!((amount_a.as_u128() as f64 / expected_a - 1.0).abs() < 1e-9);
+        assert!((amount_b.as_u128() as f64 / expected_b - 1.0).abs() < 1e-9);
+
+        //The order of the ticks does not matter
+        assert_eq!(
+            pool.amounts_for_liquidity(100, -100, liquidity).unwrap(),
+            (amount_a, amount_b)
+        );
+
+        //Above the current price the position is all token_a, below it the position is all token_b
+        let (amount_a, amount_b) = pool.amounts_for_liquidity(100, 200, liquidity).unwrap();
+        assert!(!amount_a.is_zero());
+        assert!(amount_b.is_zero());
+
+        let (amount_a, amount_b) = pool.amounts_for_liquidity(-200, -100, liquidity).unwrap();
+        assert!(amount_a.is_zero());
+        assert!(!amount_b.is_zero());
+
+        //A range starting at the current price is also all token_a
+        let (_, amount_b) = pool.amounts_for_liquidity(0, 100, liquidity).unwrap();
+        assert!(amount_b.is_zero());
+
+        //LiquidityAmounts.getAmountsForLiquidity at a price of 1 for ticks -60 and 60 with 1e18 liquidity, evaluated with exact integer math
+        assert_eq!(
+            pool.amounts_for_liquidity(-60, 60, liquidity).unwrap(),
+            (
+                U256::from(2_995_354_955_910_780_u128),
+                U256::from(2_995_354_955_910_780_u128)
+            )
+        );
+    }
+
+    #[test]
+    fn test_liquidity_for_amounts() {
+        let pool = mock_pool();
+        let liquidity = 10_u128.pow(18);
+
+        for (tick_lower, tick_upper) in [(-100, 100), (-60, 600), (100, 200), (-200, -100)] {
+            let (amount_a, amount_b) = pool
+                .amounts_for_liquidity(tick_lower, tick_upper, liquidity)
+                .unwrap();
+
+            //Both directions round down, so the round trip can lose a few units of liquidity but never gain any
+            let round_trip = pool
+                .liquidity_for_amounts(tick_lower, tick_upper, amount_a, amount_b)
+                .unwrap();
+            assert!(round_trip <= liquidity);
+            assert!(liquidity - round_trip < 1_000_000);
+        }
+
+        //Within the range, the token that runs out first limits the liquidity
+        let (amount_a, amount_b) = pool.amounts_for_liquidity(-100, 100, liquidity).unwrap();
+        let limited = pool
+            .liquidity_for_amounts(-100, 100, amount_a / 2, amount_b)
+            .unwrap();
+        assert!(limited.abs_diff(liquidity / 2) < 1_000_000);
+
+        //Amounts too large to fit in a u128 of liquidity are an error
+        assert!(matches!(
+            pool.liquidity_for_amounts(-1, 1, U256::from(u128::MAX), U256::from(u128::MAX)),
+            Err(ArithmeticError::LiquidityOverflow(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_is_locked() {
+        let slot_0 = |unlocked: bool| {
+            Bytes::from(ethers::abi::encode(&[
+                Token::Uint(U256::one() << 96),
+                Token::Int(U256::zero()),
+                Token::Uint(U256::zero()),
+                Token::Uint(U256::one()),
+                Token::Uint(U256::one()),
+                Token::Uint(U256::zero()),
+                Token::Bool(unlocked),
+            ]))
+        };
+
+        let (middleware, mock) = Provider::mocked();
+        mock.push::<Bytes, _>(slot_0(false)).unwrap();
+        mock.push::<Bytes, _>(slot_0(true)).unwrap();
+        let middleware = Arc::new(middleware);
+
+        let pool = mock_pool();
+        assert!(!pool.is_locked(middleware.clone()).await.unwrap());
+        assert!(pool.is_locked(middleware).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_depth_at_bps() {
+        let (middleware, mock) = Provider::mocked();
+        //Each depth fetches the tick data above the current tick to buy token_a, then below it to sell token_a
+        for _ in 0..2 {
+            mock.push::<Bytes, _>(mock_tick_data_response(
+                &[(true, -10, 0), (false, -887270, 0)],
+                1,
+            ))
+            .unwrap();
+            mock.push::<Bytes, _>(mock_tick_data_response(
+                &[(true, 10, 0), (false, 887270, 0)],
+                1,
+            ))
+            .unwrap();
+        }
+        let middleware = Arc::new(middleware);
+
+        let pool = mock_pool();
+
+        let (bought_narrow, sold_narrow) = pool
+            .depth_at_bps(50, pool.token_a, middleware.clone())
+            .await
+            .unwrap();
+        let (bought_wide, sold_wide) = pool
+            .depth_at_bps(200, pool.token_a, middleware)
+            .await
+            .unwrap();
+
+        assert!(!bought_narrow.is_zero() && !sold_narrow.is_zero());
+        assert!(bought_wide > bought_narrow);
+        assert!(sold_wide > sold_narrow);
+
+        //With constant liquidity, buying until the price is 2% higher takes L * (1 - 1 / sqrt(1.02)) of token_a out of the pool
+        let expected_bought = 1e18 * (1.0 - 1.0 / 1.02_f64.sqrt());
+        assert!((bought_wide.as_u128() as f64 / expected_bought - 1.0).abs() < 1e-6);
+    }
+
+    #[tokio::test]
+    async fn test_simulate_swap_on_tick_boundary() {
+        //Liquidity of the positions starting at tick 0, which is only active while the pool's tick is at or above 0
+        let liquidity_net: i128 = 400_000_000_000_000_000;
+        let amount_in = U256::from(1_000_000_000_000_000_u128);
+
+        //The pool sits exactly on tick 0's sqrt price after swapping up to it, so its liquidity includes tick 0's positions.
+        //Like the pool contract, swapping down must cross tick 0 before any amount is swapped.
+        let (middleware, mock) = Provider::mocked();
+        for _ in 0..2 {
+            mock.push::<Bytes, _>(mock_tick_data_response(
+                &[(true, 0, liquidity_net), (false, -887270, 0)],
+                1,
+            ))
+            .unwrap();
+        }
+        let middleware = Arc::new(middleware);
+
+        let mut pool = UniswapV3Pool {
+            liquidity_net,
+            ..mock_pool()
+        };
+        assert_eq!(
+            uniswap_v3_math::tick_math::get_sqrt_ratio_at_tick(pool.tick).unwrap(),
+            pool.sqrt_price
+        );
+
+        let expected = simulate_swap_pure(
+            pool.sqrt_price,
+            pool.liquidity - liquidity_net as u128,
+            -1,
+            pool.tick_spacing,
+            pool.fee,
+            &[TickData {
+                initialized: false,
+                tick: -887270,
+                liquidity_net: 0,
+            }],
+            true,
+            amount_in,
+        )
+        .unwrap();
+
+        assert_eq!(
+            pool.simulate_swap(pool.token_a, amount_in, middleware.clone())
+                .await
+                .unwrap(),
+            expected.amount_out
+        );
+        assert_eq!(
+            pool.simulate_swap_mut(pool.token_a, amount_in, middleware)
+                .await
+                .unwrap(),
+            expected.amount_out
+        );
+        assert_eq!(pool.liquidity, 600_000_000_000_000_000);
+        assert_eq!(pool.tick, expected.tick_after);
+
+        //After swapping down to tick 0's sqrt price the pool is on tick -1, without tick 0's positions.
+        //Swapping up must cross tick 0 back before any amount is swapped.
+        let (middleware, mock) = Provider::mocked();
+        for _ in 0..2 {
+            mock.push::<Bytes, _>(mock_tick_data_response(
+                &[(true, 0, liquidity_net), (false, 887270, 0)],
+                1,
+            ))
+            .unwrap();
+        }
+        let middleware = Arc::new(middleware);
+
+        let mut pool = UniswapV3Pool {
+            tick: -1,
+            liquidity_net: 0,
+            ..mock_pool()
+        };
+
+        let expected = simulate_swap_pure(
+            pool.sqrt_price,
+            pool.liquidity + liquidity_net as u128,
+            0,
+            pool.tick_spacing,
+            pool.fee,
+            &[TickData {
+                initialized: false,
+                tick: 887270,
+                liquidity_net: 0,
+            }],
+            false,
+            amount_in,
+        )
+        .unwrap();
+
+        assert_eq!(
+            pool.simulate_swap(pool.token_b, amount_in, middleware.clone())
+                .await
+                .unwrap(),
+            expected.amount_out
+        );
+        assert_eq!(
+            pool.simulate_swap_mut(pool.token_b, amount_in, middleware)
+                .await
+                .unwrap(),
+            expected.amount_out
+        );
+        assert_eq!(pool.liquidity, 1_400_000_000_000_000_000);
+        assert_eq!(pool.tick, expected.tick_after);
+    }
+
+    #[tokio::test]
+    async fn test_simulate_swap_mut_matches_simulate_swap() {
+        let ticks_below = [
+            (true, -10, 100_000_000_000_000_000),
+            (true, -20, 200_000_000_000_000_000),
+            (true, -100, 300_000_000_000_000_000),
+            (false, -887270, 0),
+        ];
+        let ticks_above = [
+            (true, 10, -100_000_000_000_000_000),
+            (true, 30, -200_000_000_000_000_000),
+            (true, 100, -300_000_000_000_000_000),
+            (false, 887270, 0),
+        ];
+
+        for amount_in in [
+            1_000_000_000_000_000_u128,
+            10_000_000_000_000_000,
+            100_000_000_000_000_000,
+        ] {
+            for zero_for_one in [true, false] {
+                let ticks: &[(bool, i32, i128)] = if zero_for_one {
+                    &ticks_below
+                } else {
+                    &ticks_above
+                };
+
+                let (middleware, mock) = Provider::mocked();
+                for _ in 0..2 {
+                    mock.push::<Bytes, _>(mock_tick_data_response(ticks, 1))
+                        .unwrap();
+                }
+                let middleware = Arc::new(middleware);
+
+                let mut pool = mock_pool();
+                let token_in = if zero_for_one {
+                    pool.token_a
+                } else {
+                    pool.token_b
+                };
+                let amount_in = U256::from(amount_in);
+
+                let amount_out = pool
+                    .simulate_swap(token_in, amount_in, middleware.clone())
+                    .await
+                    .unwrap();
+
+                assert!(!amount_out.is_zero());
+                assert_eq!(
+                    pool.simulate_swap_mut(token_in, amount_in, middleware)
+                        .await
+                        .unwrap(),
+                    amount_out
+                );
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_simulate_swap_entry_points_agree() {
+        let ticks_below = [
+            (true, -10, 100_000_000_000_000_000),
+            (true, -20, 200_000_000_000_000_000),
+            (true, -100, 300_000_000_000_000_000),
+            (false, -887270, 0),
+        ];
+        let ticks_above = [
+            (true, 10, -100_000_000_000_000_000),
+            (true, 30, -200_000_000_000_000_000),
+            (true, 100, -300_000_000_000_000_000),
+            (false, 887270, 0),
+        ];
+
+        for amount_in in [
+            0_u128,
+            50_000_000_000_000,
+            1_000_000_000_000_000,
+            10_000_000_000_000_000,
+            100_000_000_000_000_000,
+        ] {
+            for zero_for_one in [true, false] {
+                let ticks: &[(bool, i32, i128)] = if zero_for_one {
+                    &ticks_below
+                } else {
+                    &ticks_above
+                };
+
+                let (middleware, mock) = Provider::mocked();
+                for _ in 0..3 {
+                    mock.push::<Bytes, _>(mock_tick_data_response(ticks, 1))
+                        .unwrap();
+                }
+                let middleware = Arc::new(middleware);
+
+                let mut pool = mock_pool();
+                let token_in = if zero_for_one {
+                    pool.token_a
+                } else {
+                    pool.token_b
+                };
+                let amount_in = U256::from(amount_in);
+
+                let (expected_amount_out, expected_state, expected_liquidity_net) = pool
+                    .simulate_exact_input(token_in, amount_in, 150, None, middleware.clone())
+                    .await
+                    .unwrap();
+
+                assert_eq!(
+                    pool.simulate_swap(token_in, amount_in, middleware.clone())
+                        .await
+                        .unwrap(),
+                    expected_amount_out
+                );
+                assert_eq!(
+                    pool.simulate_swap_mut(token_in, amount_in, middleware)
+                        .await
+                        .unwrap(),
+                    expected_amount_out
+                );
+
+                assert_eq!(pool.tick, expected_state.tick);
+                assert_eq!(pool.sqrt_price, expected_state.sqrt_price_x_96);
+                assert_eq!(pool.liquidity, expected_state.liquidity);
+                assert_eq!(pool.liquidity_net, expected_liquidity_net);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_robust_price() {
+        let observe_response = |tick_cumulatives: [i64; 2]| {
+            Bytes::from(ethers::abi::encode(&[
+                Token::Array(
+                    tick_cumulatives
+                        .iter()
+                        .map(|tick_cumulative| Token::Int(I256::from(*tick_cumulative).into_raw()))
+                        .collect(),
+                ),
+                Token::Array(vec![Token::Uint(U256::zero()), Token::Uint(U256::zero())]),
+            ]))
+        };
+        let slot_0_response = |tick: i32| {
+            Bytes::from(ethers::abi::encode(&[
+                Token::Uint(uniswap_v3_math::tick_math::get_sqrt_ratio_at_tick(tick).unwrap()),
+                Token::Int(encode_i24(tick)),
+                Token::Uint(U256::zero()),
+                Token::Uint(U256::one()),
+                Token::Uint(U256::one()),
+                Token::Uint(U256::zero()),
+                Token::Bool(true),
+            ]))
+        };
+
+        //Responses are popped last in first out: slot0, then the observations
+        let (middleware, mock) = Provider::mocked();
+        //The spot price at tick 105 is within 5 bps of the TWAP at tick 100
+        mock.push::<Bytes, _>(observe_response([1_000_000, 1_060_000]))
+            .unwrap();
+        mock.push::<Bytes, _>(slot_0_response(105)).unwrap();
+        //The mean tick of -600_001 / 600 rounds down to -1001, 1 bps away from the spot price at tick -1000
+        mock.push::<Bytes, _>(observe_response([0, -600_001]))
+            .unwrap();
+        mock.push::<Bytes, _>(slot_0_response(-1000)).unwrap();
+        //The TWAP is at tick 100, but the spot price has been pushed to tick 5000
+        mock.push::<Bytes, _>(observe_response([1_000_000, 1_060_000]))
+            .unwrap();
+        mock.push::<Bytes, _>(slot_0_response(5000)).unwrap();
+        let middleware = Arc::new(middleware);
+
+        let pool = mock_pool();
+
+        let price = pool
+            .robust_price(pool.token_a, 600, 50, middleware.clone())
+            .await
+            .unwrap();
+        assert_eq!(price, pool.price_at_tick(100));
+
+        let price = pool
+            .robust_price(pool.token_b, 600, 0, middleware.clone())
+            .await
+            .unwrap();
+        assert_eq!(price, 1.0 / pool.price_at_tick(-1001));
+
+        let price = pool
+            .robust_price(pool.token_a, 600, 50, middleware)
+            .await
+            .unwrap();
+        assert_eq!(price, pool.price_at_tick(105));
+    }
+
+    #[tokio::test]
+    async fn test_simulate_swap_at_block() {
+        let historical_ticks = [(true, -10, 500_000_000_000_000_000), (false, -887270, 0)];
+        let latest_ticks = [(true, -10, -500_000_000_000_000_000), (false, -887270, 0)];
+
+        let middleware = Arc::new(Provider::new(BlockPinnedClient {
+            historical_response: mock_tick_data_response(&historical_ticks, 1),
+            latest_response: mock_tick_data_response(&latest_ticks, 2),
+        }));
+
+        let pool = mock_pool();
+        let amount_in = U256::from(5_000_000_000_000_000_u128);
+
+        let amount_out = pool
+            .simulate_swap_at_block(pool.token_a, amount_in, U64::from(1), middleware.clone())
+            .await
+            .unwrap();
+
+        //The swap crosses tick -10, so it is only simulated against the historical liquidity if the tick data is read at the block
+        let tick_data = historical_ticks
+            .iter()
+            .map(|&(initialized, tick, liquidity_net)| TickData {
+                initialized,
+                tick,
+                liquidity_net,
+            })
+            .collect::<Vec<TickData>>();
+        let expected = simulate_swap_pure(
+            pool.sqrt_price,
+            pool.liquidity,
+            pool.tick,
+            pool.tick_spacing,
+            pool.fee,
+            &tick_data,
+            true,
+            amount_in,
+        )
+        .unwrap();
+
+        assert_eq!(amount_out, expected.amount_out);
+        assert_ne!(
+            amount_out,
+            pool.simulate_swap(pool.token_a, amount_in, middleware)
+                .await
+                .unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_swap_until_price() {
+        let (middleware, mock) = Provider::mocked();
+        for _ in 0..2 {
+            mock.push::<Bytes, _>(mock_tick_data_response(
+                &[(true, -10, 0), (false, -887270, 0)],
+                1,
+            ))
+            .unwrap();
+        }
+        let middleware = Arc::new(middleware);
+
+        let mut pool = mock_pool();
+
+        //Selling token_a can only lower its price
+        assert!(matches!(
+            pool.swap_until_price(pool.token_a, 1.01, pool.token_a, middleware.clone())
+                .await,
+            Err(CFMMError::InvalidSqrtPriceLimit(_))
+        ));
+
+        //Raising the price of token_b to 1 / 0.99 is the same as lowering the price of token_a to 0.99
+        let (amount_in, amount_out) = pool
+            .swap_until_price(pool.token_a, 1.0 / 0.99, pool.token_b, middleware.clone())
+            .await
+            .unwrap();
+        assert!(!amount_out.is_zero());
+
+        assert_eq!(
+            pool.simulate_swap_mut(pool.token_a, amount_in, middleware)
+                .await
+                .unwrap(),
+            amount_out
+        );
+        assert!((pool.try_calculate_price(pool.token_a).unwrap() - 0.99).abs() < 1e-4);
+    }
+
+    #[tokio::test]
+    async fn test_marginal_price_after() {
+        let (middleware, mock) = Provider::mocked();
+        for _ in 0..2 {
+            mock.push::<Bytes, _>(mock_tick_data_response(
+                &[(true, -10, 0), (false, -887270, 0)],
+                1,
+            ))
+            .unwrap();
+        }
+        let middleware = Arc::new(middleware);
+
+        let pool = mock_pool();
+        let amount_in = U256::from(1_000_000_000_000_000_u128);
+        let spot_price = pool.try_calculate_price(pool.token_a).unwrap();
+
+        //Selling token_a lowers its price
+        let marginal_price = pool
+            .marginal_price_after(pool.token_a, amount_in, pool.token_a, middleware.clone())
+            .await
+            .unwrap();
+        assert!(marginal_price < spot_price);
+
+        //The average execution price, even net of the fee, is between the spot price before and after the swap
+        let amount_out = pool
+            .simulate_swap(pool.token_a, amount_in, middleware)
+            .await
+            .unwrap();
+        let execution_price = amount_out.as_u128() as f64 / amount_in.as_u128() as f64;
+        assert!(execution_price < spot_price);
+        assert!(execution_price > marginal_price);
+    }
+
+    #[tokio::test]
+    async fn test_quote_unit() {
+        let (middleware, mock) = Provider::mocked();
+        mock.push::<Bytes, _>(mock_tick_data_response(
+            &[(true, 10, 0), (false, 887270, 0)],
+            1,
+        ))
+        .unwrap();
+        mock.push::<Bytes, _>(mock_tick_data_response(
+            &[(true, -10, 0), (false, -887270, 0)],
+            1,
+        ))
+        .unwrap();
+        let middleware = Arc::new(middleware);
+
+        //Deep enough that the price impact of a unit swap is negligible next to the 0.05% fee
+        let pool = UniswapV3Pool {
+            liquidity: 10_u128.pow(24),
+            ..mock_pool()
+        };
+        let price = pool.try_calculate_price(pool.token_a).unwrap();
+
+        let unit_price = pool
+            .quote_unit(pool.token_a, pool.token_a, middleware.clone())
+            .await
+            .unwrap();
+        assert!((unit_price - price * (1.0 - 0.0005)).abs() < 1e-5);
+
+        //Buying token_a with token_b pays the fee on top of the price
+        let unit_price = pool
+            .quote_unit(pool.token_b, pool.token_a, middleware)
+            .await
+            .unwrap();
+        assert!((unit_price - price / (1.0 - 0.0005)).abs() < 1e-5);
+    }
+
+    #[tokio::test]
+    async fn test_simulate_swap_display() {
+        let (middleware, mock) = Provider::mocked();
+        mock.push::<Bytes, _>(mock_tick_data_response(
+            &[(true, -10, 0), (false, -887270, 0)],
+            1,
+        ))
+        .unwrap();
+
+        let pool = mock_pool();
+        let display = pool
+            .simulate_swap_display(
+                pool.token_a,
+                U256::from(1_000_000_000_000_u128),
+                Arc::new(middleware),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(display.amount_in_human, 0.000001);
+        assert!((display.price - display.amount_out_human / display.amount_in_human).abs() < 1e-12);
+        assert!((display.fee_human - 0.000001 * 0.0005).abs() < 1e-18);
+
+        //The price impact of a small swap is mostly the 0.05% fee
+        assert!(display.price_impact > 0.0005);
+        assert!(display.price_impact < 0.0006);
+        assert!(
+            (display.price
+                - pool.try_calculate_price(pool.token_a).unwrap() * (1.0 - display.price_impact))
+                .abs()
+                < 1e-12
+        );
+    }
+
+    #[tokio::test]
+    async fn test_apply_delta() {
+        //Sync data where the price and tick moved but the liquidity did not
+        let sqrt_price = uniswap_v3_math::tick_math::get_sqrt_ratio_at_tick(-25).unwrap();
+        let sync_response = Bytes::from(ethers::abi::encode(&[Token::Tuple(vec![
+            Token::Uint(U256::from(mock_pool().liquidity)),
+            Token::Uint(sqrt_price),
+            Token::Int(encode_i24(-25)),
+            Token::Int(I256::from(-1000).into_raw()),
+        ])]));
+
+        let (middleware, mock) = Provider::mocked();
+        for _ in 0..2 {
+            mock.push::<Bytes, _>(sync_response.clone()).unwrap();
+            mock.push(U64::from(7)).unwrap();
+        }
+        let middleware = Arc::new(middleware);
+
+        let mut synced_pool = mock_pool();
+        synced_pool.sync_pool(middleware.clone()).await.unwrap();
+
+        let mut pool = mock_pool();
+        let delta = pool.sync_pool_diff(middleware).await.unwrap();
+
+        assert_eq!(
+            delta,
+            PoolDelta {
+                sqrt_price: Some(sqrt_price),
+                tick: Some(-25),
+                liquidity: None,
+                liquidity_net: Some(-1000),
+                block_number: 7,
+                synced_at: delta.synced_at,
+            }
+        );
+        assert!(delta.synced_at.is_some());
+        assert_eq!(pool, mock_pool());
+
+        //The pools were synced at different times but are otherwise the same
+        pool.apply_delta(delta);
+        assert_eq!(
+            pool,
+            UniswapV3Pool {
+                synced_at: delta.synced_at,
+                ..synced_pool
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_simulate_swap_mut_keeps_liquidity_net_of_current_tick() {
+        let liquidity_net_10 = 500_000_000_000_000_000_i128;
+        let liquidity_net_9 = -200_000_000_000_000_000_i128;
+
+        let mut pool = UniswapV3Pool {
+            tick: 12,
+            tick_spacing: 1,
+            sqrt_price: uniswap_v3_math::tick_math::get_sqrt_ratio_at_tick(12).unwrap(),
+            liquidity_net: 0,
+            ..mock_pool()
+        };
+
+        //Cross tick 10 and stop inside the range of tick 9
+        let (middleware, mock) = Provider::mocked();
+        mock.push::<Bytes, _>(mock_tick_data_response(
+            &[
+                (true, 10, liquidity_net_10),
+                (true, 9, liquidity_net_9),
+                (true, 0, 0),
+            ],
+            1,
+        ))
+        .unwrap();
+        pool.simulate_swap_mut(
+            pool.token_a,
+            U256::from(115_000_000_000_000_u128),
+            Arc::new(middleware),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(pool.tick, 9);
+        assert_eq!(pool.liquidity_net, liquidity_net_9);
+
+        //A fresh sync returns the on-chain liquidityNet of the tick the pool is at
+        let (middleware, mock) = Provider::mocked();
+        mock.push::<Bytes, _>(Bytes::from(ethers::abi::encode(&[Token::Tuple(vec![
+            Token::Uint(U256::from(pool.liquidity)),
+            Token::Uint(pool.sqrt_price),
+            Token::Int(encode_i24(pool.tick)),
+            Token::Int(I256::from(liquidity_net_9).into_raw()),
+        ])])))
+        .unwrap();
+        mock.push(U64::from(1)).unwrap();
+
+        let mut synced_pool = UniswapV3Pool {
+            tick: 12,
+            tick_spacing: 1,
+            ..mock_pool()
+        };
+        synced_pool.sync_pool(Arc::new(middleware)).await.unwrap();
+
+        assert_eq!(
+            pool,
+            UniswapV3Pool {
+                last_synced_block: pool.last_synced_block,
+                synced_at: pool.synced_at,
+                ..synced_pool
+            }
+        );
+
+        //Simulating from the mutated pool matches simulating from the synced pool
+        let (middleware, mock) = Provider::mocked();
+        for _ in 0..2 {
+            mock.push::<Bytes, _>(mock_tick_data_response(
+                &[(true, 9, liquidity_net_9), (true, 0, 0)],
+                1,
+            ))
+            .unwrap();
+        }
+        let middleware = Arc::new(middleware);
+
+        let amount_in = U256::from(10_000_000_000_000_u128);
+        assert_eq!(
+            pool.simulate_swap(pool.token_a, amount_in, middleware.clone())
+                .await
+                .unwrap(),
+            synced_pool
+                .simulate_swap(synced_pool.token_a, amount_in, middleware)
+                .await
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_fee_tier() {
+        for (fee_tier, fee, tick_spacing) in [
+            (FeeTier::Lowest, 100, 1),
+            (FeeTier::Low, 500, 10),
+            (FeeTier::Medium, 3000, 60),
+            (FeeTier::High, 10000, 200),
+        ] {
+            assert_eq!(fee_tier.to_u32(), fee);
+            assert_eq!(FeeTier::from_u32(fee), fee_tier);
+            assert_eq!(fee_tier.tick_spacing(), Some(tick_spacing));
+            assert_eq!(FeeTier::from_tick_spacing(tick_spacing), Some(fee_tier));
+        }
+
+        assert_eq!(FeeTier::from_u32(2500), FeeTier::Custom(2500));
+        assert_eq!(FeeTier::Custom(2500).to_u32(), 2500);
+        assert_eq!(FeeTier::Custom(2500).tick_spacing(), None);
+        assert_eq!(FeeTier::from_tick_spacing(50), None);
+
+        assert_eq!(mock_pool().fee_tier(), FeeTier::Low);
+    }
+
+    #[test]
+    fn test_compute_pool_address() {
+        let factory = H160::from_str("0x1F98431c8aD98523631AE4a59f267346ea31F984").unwrap();
+        let usdc = H160::from_str("0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48").unwrap();
+        let weth = H160::from_str("0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2").unwrap();
+
+        let expected = H160::from_str("0x88e6A0c2dDD26FEEb64F039a2c41296FcB3f5640").unwrap();
+        assert_eq!(compute_pool_address(factory, usdc, weth, 500), expected);
+        assert_eq!(compute_pool_address(factory, weth, usdc, 500), expected);
+        assert_ne!(compute_pool_address(factory, usdc, weth, 3000), expected);
+    }
+
+    #[test]
+    fn test_swap_router_exact_input_single_calldata() {
+        let params = ExactInputSingleParams {
+            token_in: H160::from_low_u64_be(2),
+            token_out: H160::from_low_u64_be(3),
+            fee: 500,
+            recipient: H160::from_low_u64_be(4),
+            amount_in: U256::from(1_000_000),
+            amount_out_minimum: U256::from(990_000),
+            sqrt_price_limit_x96: U256::zero(),
+        };
+
+        let calldata = swap_router_exact_input_single_calldata(params.clone());
+
+        //exactInputSingle((address,address,uint24,address,uint256,uint256,uint160))
+        assert_eq!(calldata[..4], [0x04, 0xe4, 0x5a, 0xaf]);
+
+        let decoded = ExactInputSingleCall::decode(&calldata).unwrap();
+        assert_eq!(decoded.params, params);
+    }
+
+    #[test]
+    fn test_exact_input_calldata() {
+        let tokens = [
+            H160::from_low_u64_be(2),
+            H160::from_low_u64_be(3),
+            H160::from_low_u64_be(4),
+        ];
+        let path = encode_path(&tokens, &[500, 3000]);
+
+        assert_eq!(path.len(), 20 + 3 + 20 + 3 + 20);
+        assert_eq!(path[..20], *tokens[0].as_bytes());
+        assert_eq!(path[20..23], [0x00, 0x01, 0xf4]);
+        assert_eq!(path[43..46], [0x00, 0x0b, 0xb8]);
+        assert_eq!(path[46..], *tokens[2].as_bytes());
+
+        let recipient = H160::from_low_u64_be(5);
+        let deadline = U256::from(1_700_000_000);
+        let calldata = exact_input_calldata(
+            path.clone(),
+            recipient,
+            deadline,
+            U256::from(1_000_000),
+            U256::from(990_000),
+        );
+
+        //multicall(uint256,bytes[])
+        assert_eq!(calldata[..4], [0x5a, 0xe4, 0x01, 0xdc]);
+
+        let multicall = MulticallCall::decode(&calldata).unwrap();
+        assert_eq!(multicall.deadline, deadline);
+        assert_eq!(multicall.data.len(), 1);
+
+        //exactInput((bytes,address,uint256,uint256))
+        assert_eq!(multicall.data[0][..4], [0xb8, 0x58, 0x18, 0x3f]);
+
+        let exact_input = ExactInputCall::decode(&multicall.data[0]).unwrap();
+        assert_eq!(exact_input.params.path.to_vec(), path);
+        assert_eq!(exact_input.params.recipient, recipient);
+        assert_eq!(exact_input.params.amount_in, U256::from(1_000_000));
+        assert_eq!(exact_input.params.amount_out_minimum, U256::from(990_000));
+    }
+
+    #[tokio::test]
+    async fn test_quote_curve() {
+        let (provider, mock) = Provider::mocked();
+        let middleware = Arc::new(provider);
+
+        let mut ticks = (1..=20)
+            .map(|i| (true, -10 * i, 1_000_000_000_000_000 * i as i128))
+            .collect::<Vec<(bool, i32, i128)>>();
+        ticks.push((false, -887270, 0));
+
+        let sizes = [
+            U256::from(1_000_000_000_000_000_u128),
+            U256::from(100_000_000_000_000_u128),
+            U256::from(5_000_000_000_000_000_u128),
+        ];
+
+        //Responses are popped in reverse order, each individual simulate_swap fetches all of the tick data at once
+        for _ in sizes {
+            mock.push::<Bytes, _>(mock_tick_data_response(&ticks, 1))
+                .unwrap();
+        }
+
+        //quote_curve's first fetch ends at tick -20, which the largest size crosses, so the rest is fetched separately
+        mock.push::<Bytes, _>(mock_tick_data_response(&ticks[2..], 1))
+            .unwrap();
+        mock.push::<Bytes, _>(mock_tick_data_response(&ticks[..2], 1))
+            .unwrap();
+
+        let pool = mock_pool();
+        let curve = pool
+            .quote_curve(pool.token_a, &sizes, middleware.clone())
+            .await
+            .unwrap();
+
+        for (size, amount_out) in sizes.iter().zip(curve.iter()) {
+            assert_eq!(
+                pool.simulate_swap(pool.token_a, *size, middleware.clone())
+                    .await
+                    .unwrap(),
+                *amount_out
+            );
+        }
+
+        //Larger sizes always get more out
+        assert!(curve[1] < curve[0] && curve[0] < curve[2]);
+    }
+
+    #[tokio::test]
+    async fn test_simulate_swaps_sequential() {
+        let (provider, mock) = Provider::mocked();
+        let middleware = Arc::new(provider);
+
+        //The first swap leaves the pool between tick -20 and -10, so the second swap's tick data starts at -20
+        let second_swap_ticks = (2..=20)
+            .map(|i| (false, -10 * i, 0))
+            .collect::<Vec<(bool, i32, i128)>>();
+        mock.push::<Bytes, _>(mock_tick_data_response(&second_swap_ticks, 1))
+            .unwrap();
+        let first_swap_ticks = (1..=20)
+            .map(|i| (false, -10 * i, 0))
+            .collect::<Vec<(bool, i32, i128)>>();
+        mock.push::<Bytes, _>(mock_tick_data_response(&first_swap_ticks, 1))
+            .unwrap();
+
+        let mut pool = mock_pool();
+        let amount_in = U256::from(500_000_000_000_000_u128);
+
+        let amounts_out = pool
+            .simulate_swaps_sequential(
+                &[(pool.token_a, amount_in), (pool.token_a, amount_in)],
+                middleware,
+            )
+            .await
+            .unwrap();
+
+        //The first swap pushes the price down, so the same amount in buys less token_b the second time
+        assert_eq!(amounts_out.len(), 2);
+        assert!(amounts_out[1] < amounts_out[0]);
+        assert!(pool.tick < -10 && pool.tick >= -20);
+    }
+
+    #[tokio::test]
+    async fn test_simulate_roundtrip_sanity() {
+        let (provider, mock) = Provider::mocked();
+        let middleware = Arc::new(provider);
+
+        let mut pool = mock_pool();
+        pool.tick_spacing = 1;
+
+        let ticks_below = (1..=20)
+            .map(|i| (false, -10 * i, 0))
+            .collect::<Vec<(bool, i32, i128)>>();
+        let ticks_above = (1..=20)
+            .map(|i| (false, 10 * i, 0))
+            .collect::<Vec<(bool, i32, i128)>>();
+
+        //Consistent tick data in both directions loses no more than the fees
+        mock.push::<Bytes, _>(mock_tick_data_response(&ticks_above, 1))
+            .unwrap();
+        mock.push::<Bytes, _>(mock_tick_data_response(&ticks_below, 1))
+            .unwrap();
+
+        assert!(pool
+            .simulate_roundtrip_sanity(middleware.clone())
+            .await
+            .unwrap());
+
+        //The swap in leaves the pool at tick -2. On the way back the pool reports an initialized tick at -1 that was not there
+        //on the way down, past which most of the liquidity disappears
+        let asymmetric_ticks_above = [
+            (
+                true,
+                -1,
+                -(1_000_000_000_000_000_000 - 1_000_000_000_000_000),
+            ),
+            (false, 887272, 0),
+        ];
+        mock.push::<Bytes, _>(mock_tick_data_response(&asymmetric_ticks_above, 1))
+            .unwrap();
+        mock.push::<Bytes, _>(mock_tick_data_response(&ticks_below, 1))
+            .unwrap();
+
+        assert!(!pool.simulate_roundtrip_sanity(middleware).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_get_pool_data_falls_back_when_batch_request_reverts() {
+        let (provider, mock) = Provider::mocked();
+        let encode = |tokens: &[Token]| Bytes::from(ethers::abi::encode(tokens));
+        let slot_0 = encode(&[
+            Token::Uint(U256::one() << 96),
+            Token::Int(U256::zero()),
+            Token::Uint(U256::zero()),
+            Token::Uint(U256::zero()),
+            Token::Uint(U256::zero()),
+            Token::Uint(U256::from(4 | 4 << 4)),
+            Token::Bool(true),
+        ]);
+
+        let token_a = H160::from_low_u64_be(2);
+        let token_b = H160::from_low_u64_be(3);
+
+        //Responses are popped in reverse order: fee, ticks
+        mock.push::<Bytes, _>(encode(&[Token::Uint(U256::from(500))]))
+            .unwrap();
+        mock.push::<Bytes, _>(encode(&[
+            Token::Uint(U256::zero()),
+            Token::Int(U256::from(1000)),
+            Token::Uint(U256::zero()),
+            Token::Uint(U256::zero()),
+            Token::Int(U256::zero()),
+            Token::Uint(U256::zero()),
+            Token::Uint(U256::zero()),
+            Token::Bool(true),
+        ]))
+        .unwrap();
+
+        //tickSpacing, liquidity, slot0
+        mock.push::<Bytes, _>(encode(&[Token::Int(U256::from(10))]))
+            .unwrap();
+        mock.push::<Bytes, _>(encode(&[Token::Uint(U256::from(1_000_000))]))
+            .unwrap();
+        mock.push::<Bytes, _>(slot_0).unwrap();
+
+        //token_b decimals, token_a decimals, token1, token0
+        mock.push::<Bytes, _>(encode(&[Token::Uint(U256::from(6))]))
+            .unwrap();
+        mock.push::<Bytes, _>(encode(&[Token::Uint(U256::from(18))]))
+            .unwrap();
+        mock.push::<Bytes, _>(encode(&[Token::Address(token_b)]))
+            .unwrap();
+        mock.push::<Bytes, _>(encode(&[Token::Address(token_a)]))
+            .unwrap();
+
+        //The batch request reverts
+        mock.push_response(MockResponse::Error(JsonRpcError {
+            code: 3,
+            message: String::from("execution reverted"),
+            data: None,
+        }));
+
+        let mut pool = UniswapV3Pool {
+            address: H160::from_low_u64_be(1),
+            ..Default::default()
+        };
 
-    pub fn swap_calldata(
-        &self,
-        recipient: H160,
-        zero_for_one: bool,
-        amount_specified: I256,
-        sqrt_price_limit_x_96: U256,
-        calldata: Vec<u8>,
-    ) -> Bytes {
-        let input_tokens = vec![
-            Token::Address(recipient),
-            Token::Bool(zero_for_one),
-            Token::Int(amount_specified.into_raw()),
-            Token::Uint(sqrt_price_limit_x_96),
-            Token::Bytes(calldata),
-        ];
+        pool.get_pool_data(Arc::new(provider)).await.unwrap();
 
-        abi::IUNISWAPV3POOL_ABI
-            .function("swap")
-            .unwrap()
-            .encode_input(&input_tokens)
-            .expect("Could not encode swap calldata")
+        assert_eq!(pool.tokens(), (token_a, token_b));
+        assert_eq!(pool.decimals(), (18, 6));
+        assert_eq!(pool.sqrt_price, U256::one() << 96);
+        assert_eq!(pool.liquidity, 1_000_000);
+        assert_eq!(pool.tick_spacing, 10);
+        assert_eq!(pool.liquidity_net, 1000);
+        assert_eq!(pool.fee, 500);
     }
-}
 
-pub struct CurrentState {
-    amount_specified_remaining: I256,
-    amount_calculated: I256,
-    sqrt_price_x_96: U256,
-    tick: i32,
-    liquidity: u128,
-}
+    #[tokio::test]
+    async fn test_get_pool_data_with_fee_fallback() {
+        let (provider, mock) = Provider::mocked();
+        let reverted = || {
+            MockResponse::Error(JsonRpcError {
+                code: 3,
+                message: String::from("execution reverted"),
+                data: None,
+            })
+        };
+        let encode = |tokens: &[Token]| Bytes::from(ethers::abi::encode(tokens));
+
+        let token_a = H160::from_low_u64_be(2);
+        let token_b = H160::from_low_u64_be(3);
+
+        //Responses are popped in reverse order, the fee call reverts
+        mock.push_response(reverted());
+
+        //ticks
+        mock.push::<Bytes, _>(encode(&[
+            Token::Uint(U256::zero()),
+            Token::Int(U256::from(1000)),
+            Token::Uint(U256::zero()),
+            Token::Uint(U256::zero()),
+            Token::Int(U256::zero()),
+            Token::Uint(U256::zero()),
+            Token::Uint(U256::zero()),
+            Token::Bool(true),
+        ]))
+        .unwrap();
 
-#[derive(Default)]
-pub struct StepComputations {
-    pub sqrt_price_start_x_96: U256,
-    pub tick_next: i32,
-    pub initialized: bool,
-    pub sqrt_price_next_x96: U256,
-    pub amount_in: U256,
-    pub amount_out: U256,
-    pub fee_amount: U256,
-}
+        //tickSpacing, liquidity
+        mock.push::<Bytes, _>(encode(&[Token::Int(U256::from(60))]))
+            .unwrap();
+        mock.push::<Bytes, _>(encode(&[Token::Uint(U256::from(1_000_000))]))
+            .unwrap();
 
-const MIN_TICK: i32 = -887272;
-const MAX_TICK: i32 = 887272;
+        //slot0
+        mock.push::<Bytes, _>(encode(&[
+            Token::Uint(U256::one() << 96),
+            Token::Int(U256::zero()),
+            Token::Uint(U256::zero()),
+            Token::Uint(U256::zero()),
+            Token::Uint(U256::zero()),
+            Token::Uint(U256::zero()),
+            Token::Bool(true),
+        ]))
+        .unwrap();
 
-pub struct Tick {
-    pub liquidity_gross: u128,
-    pub liquidity_net: i128,
-    pub fee_growth_outside_0_x_128: U256,
-    pub fee_growth_outside_1_x_128: U256,
-    pub tick_cumulative_outside: U256,
-    pub seconds_per_liquidity_outside_x_128: U256,
-    pub seconds_outside: u32,
-    pub initialized: bool,
-}
+        //token_b decimals, token_a decimals, token1, token0
+        mock.push::<Bytes, _>(encode(&[Token::Uint(U256::from(6))]))
+            .unwrap();
+        mock.push::<Bytes, _>(encode(&[Token::Uint(U256::from(18))]))
+            .unwrap();
+        mock.push::<Bytes, _>(encode(&[Token::Address(token_b)]))
+            .unwrap();
+        mock.push::<Bytes, _>(encode(&[Token::Address(token_a)]))
+            .unwrap();
 
-mod test {
-    #[allow(unused)]
-    use crate::abi::IUniswapV3Pool;
+        //The batch request reverts
+        mock.push_response(reverted());
 
-    #[allow(unused)]
-    use super::UniswapV3Pool;
-    #[allow(unused)]
-    use ethers::providers::Middleware;
+        let mut pool = UniswapV3Pool {
+            address: H160::from_low_u64_be(1),
+            ..Default::default()
+        };
 
-    #[allow(unused)]
-    use ethers::{
-        prelude::abigen,
-        providers::{Http, Provider},
-        types::{H160, U256},
-    };
-    #[allow(unused)]
-    use std::error::Error;
-    #[allow(unused)]
-    use std::{str::FromStr, sync::Arc};
+        pool.get_pool_data_with_fee_fallback(None, Arc::new(provider))
+            .await
+            .unwrap();
 
-    abigen!(
-        IQuoter,
-    r#"[
-        function quoteExactInputSingle(address tokenIn, address tokenOut,uint24 fee, uint256 amountIn, uint160 sqrtPriceLimitX96) external returns (uint256 amountOut)
-    ]"#;);
+        assert_eq!(pool.tokens(), (token_a, token_b));
+        assert_eq!(pool.decimals(), (18, 6));
+        assert_eq!(pool.sqrt_price, U256::one() << 96);
+        assert_eq!(pool.liquidity, 1_000_000);
+        assert_eq!(pool.tick_spacing, 60);
+        assert_eq!(pool.liquidity_net, 1000);
+        assert_eq!(pool.fee, 3000);
+    }
 
     #[tokio::test]
     async fn test_simulate_swap_0() {
@@ -865,10 +7212,7 @@ mod test {
         .await
         .unwrap();
 
-        let quoter = IQuoter::new(
-            H160::from_str("0xb27308f9f90d607463bb33ea1bebb41c27ce5ab6").unwrap(),
-            middleware.clone(),
-        );
+        let quoter = QuoterV2::new(QUOTER_V2_ADDRESS, middleware.clone());
 
         let amount_in = U256::from_dec_str("100000000").unwrap(); // 100 USDC
 
@@ -878,16 +7222,8 @@ mod test {
             .await
             .unwrap();
 
-        let expected_amount_out = quoter
-            .quote_exact_input_single(
-                pool.token_a,
-                pool.token_b,
-                pool.fee,
-                amount_in,
-                U256::zero(),
-            )
-            .block(current_block)
-            .call()
+        let (expected_amount_out, ..) = quoter
+            .quote_exact_input_single(&pool, pool.token_a, amount_in, Some(current_block))
             .await
             .unwrap();
 
@@ -907,10 +7243,7 @@ mod test {
         .await
         .unwrap();
 
-        let quoter = IQuoter::new(
-            H160::from_str("0xb27308f9f90d607463bb33ea1bebb41c27ce5ab6").unwrap(),
-            middleware.clone(),
-        );
+        let quoter = QuoterV2::new(QUOTER_V2_ADDRESS, middleware.clone());
 
         let amount_in_1 = U256::from_dec_str("10000000000").unwrap(); // 10_000 USDC
 
@@ -920,16 +7253,8 @@ mod test {
             .await
             .unwrap();
 
-        let expected_amount_out_1 = quoter
-            .quote_exact_input_single(
-                pool.token_a,
-                pool.token_b,
-                pool.fee,
-                amount_in_1,
-                U256::zero(),
-            )
-            .block(current_block)
-            .call()
+        let (expected_amount_out_1, ..) = quoter
+            .quote_exact_input_single(&pool, pool.token_a, amount_in_1, Some(current_block))
             .await
             .unwrap();
 
@@ -949,10 +7274,7 @@ mod test {
         .await
         .unwrap();
 
-        let quoter = IQuoter::new(
-            H160::from_str("0xb27308f9f90d607463bb33ea1bebb41c27ce5ab6").unwrap(),
-            middleware.clone(),
-        );
+        let quoter = QuoterV2::new(QUOTER_V2_ADDRESS, middleware.clone());
 
         let amount_in_2 = U256::from_dec_str("10000000000000").unwrap(); // 10_000_000 USDC
 
@@ -962,16 +7284,8 @@ mod test {
             .await
             .unwrap();
 
-        let expected_amount_out_2 = quoter
-            .quote_exact_input_single(
-                pool.token_a,
-                pool.token_b,
-                pool.fee,
-                amount_in_2,
-                U256::zero(),
-            )
-            .block(current_block)
-            .call()
+        let (expected_amount_out_2, ..) = quoter
+            .quote_exact_input_single(&pool, pool.token_a, amount_in_2, Some(current_block))
             .await
             .unwrap();
 
@@ -991,10 +7305,7 @@ mod test {
         .await
         .unwrap();
 
-        let quoter = IQuoter::new(
-            H160::from_str("0xb27308f9f90d607463bb33ea1bebb41c27ce5ab6").unwrap(),
-            middleware.clone(),
-        );
+        let quoter = QuoterV2::new(QUOTER_V2_ADDRESS, middleware.clone());
 
         let amount_in_3 = U256::from_dec_str("100000000000000").unwrap(); // 100_000_000 USDC
 
@@ -1007,20 +7318,87 @@ mod test {
             .await
             .unwrap();
 
-        let expected_amount_out_3 = quoter
-            .quote_exact_input_single(
+        let (expected_amount_out_3, ..) = quoter
+            .quote_exact_input_single(&pool, pool.token_a, amount_in_3, Some(current_block))
+            .await
+            .unwrap();
+
+        assert_eq!(amount_out_3, expected_amount_out_3);
+    }
+
+    #[tokio::test]
+    async fn test_max_swappable_in() {
+        let rpc_endpoint = std::env::var("ETHEREUM_MAINNET_ENDPOINT")
+            .expect("Could not get ETHEREUM_MAINNET_ENDPOINT");
+        let middleware = Arc::new(Provider::<Http>::try_from(rpc_endpoint).unwrap());
+
+        let pool = UniswapV3Pool::new_from_address(
+            H160::from_str("0x88e6A0c2dDD26FEEb64F039a2c41296FcB3f5640").unwrap(),
+            middleware.clone(),
+        )
+        .await
+        .unwrap();
+
+        let max_amount_in = pool
+            .max_swappable_in(pool.token_a, middleware.clone())
+            .await
+            .unwrap();
+
+        assert!(!max_amount_in.is_zero());
+
+        let amount_out = pool
+            .simulate_swap(pool.token_a, max_amount_in, middleware.clone())
+            .await
+            .unwrap();
+
+        //Anything above the max swappable amount is only partially filled
+        let amount_out_partial_fill = pool
+            .simulate_swap(
                 pool.token_a,
-                pool.token_b,
-                pool.fee,
-                amount_in_3,
-                U256::zero(),
+                max_amount_in + max_amount_in / 100,
+                middleware.clone(),
             )
-            .block(current_block)
-            .call()
             .await
             .unwrap();
 
-        assert_eq!(amount_out_3, expected_amount_out_3);
+        assert_eq!(amount_out, amount_out_partial_fill);
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_prices_at_block() {
+        let rpc_endpoint = std::env::var("ETHEREUM_MAINNET_ENDPOINT")
+            .expect("Could not get ETHEREUM_MAINNET_ENDPOINT");
+        let middleware = Arc::new(Provider::<Http>::try_from(rpc_endpoint).unwrap());
+
+        //USDC/WETH 0.05% and 0.3% pools
+        let mut pools = vec![];
+        for address in [
+            "0x88e6A0c2dDD26FEEb64F039a2c41296FcB3f5640",
+            "0x8ad599c3A0ff1De082011EFDDc58f1908eb6e6D8",
+        ] {
+            pools.push(
+                UniswapV3Pool::new_from_address(
+                    H160::from_str(address).unwrap(),
+                    middleware.clone(),
+                )
+                .await
+                .unwrap(),
+            );
+        }
+
+        let weth = H160::from_str("0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2").unwrap();
+
+        let prices = snapshot_prices_at_block(&pools, weth, U64::from(16515398), middleware)
+            .await
+            .into_iter()
+            .map(|price| price.unwrap())
+            .collect::<Vec<f64>>();
+
+        //Price of WETH in USDC at the block
+        for price in &prices {
+            assert!(*price > 1000.0 && *price < 2500.0);
+        }
+        assert!((prices[0] - prices[1]).abs() / prices[0] < 0.01);
     }
 
     #[tokio::test]
@@ -1087,6 +7465,157 @@ mod test {
         assert_eq!(pool.tick_spacing, 10);
     }
 
+    #[tokio::test]
+    async fn test_fetch_creation_block() {
+        let rpc_endpoint = std::env::var("ETHEREUM_MAINNET_ENDPOINT")
+            .expect("Could not get ETHEREUM_MAINNET_ENDPOINT");
+        let middleware = Arc::new(Provider::<Http>::try_from(rpc_endpoint).unwrap());
+
+        let mut pool = UniswapV3Pool::new_from_address(
+            H160::from_str("0x88e6A0c2dDD26FEEb64F039a2c41296FcB3f5640").unwrap(),
+            middleware.clone(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(pool.created_at_block, None);
+
+        let creation_block = pool
+            .fetch_creation_block(
+                H160::from_str("0x1F98431c8aD98523631AE4a59f267346ea31F984").unwrap(),
+                middleware,
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(creation_block, 12376729);
+        assert_eq!(pool.created_at_block, Some(12376729));
+    }
+
+    #[tokio::test]
+    async fn test_get_all_fee_tier_pools() {
+        let rpc_endpoint = std::env::var("ETHEREUM_MAINNET_ENDPOINT")
+            .expect("Could not get ETHEREUM_MAINNET_ENDPOINT");
+        let middleware = Arc::new(Provider::<Http>::try_from(rpc_endpoint).unwrap());
+
+        let factory = H160::from_str("0x1F98431c8aD98523631AE4a59f267346ea31F984").unwrap();
+        let usdc = H160::from_str("0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48").unwrap();
+        let weth = H160::from_str("0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2").unwrap();
+
+        let pools = get_all_fee_tier_pools(usdc, weth, factory, middleware)
+            .await
+            .unwrap();
+
+        let fees = pools.iter().map(|pool| pool.fee).collect::<Vec<_>>();
+        assert!(fees.contains(&500));
+        assert!(fees.contains(&3000));
+        for pool in pools {
+            assert_eq!((pool.token_a, pool.token_b), (usdc, weth));
+            assert!(!pool.sqrt_price.is_zero());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_pools_from_token_pairs() {
+        let rpc_endpoint = std::env::var("ETHEREUM_MAINNET_ENDPOINT")
+            .expect("Could not get ETHEREUM_MAINNET_ENDPOINT");
+        let middleware = Arc::new(Provider::<Http>::try_from(rpc_endpoint).unwrap());
+
+        let factory = H160::from_str("0x1F98431c8aD98523631AE4a59f267346ea31F984").unwrap();
+        let usdc = H160::from_str("0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48").unwrap();
+        let weth = H160::from_str("0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2").unwrap();
+
+        let pools = get_pools_from_token_pairs(
+            &[
+                (usdc, weth, 500),
+                (weth, usdc, 3000),
+                //Not an enabled fee tier
+                (usdc, weth, 1234),
+                (usdc, H160::from_low_u64_be(1), 500),
+            ],
+            factory,
+            middleware,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(pools.len(), 4);
+
+        let pool = pools[0].unwrap();
+        assert_eq!(
+            pool.address,
+            H160::from_str("0x88e6A0c2dDD26FEEb64F039a2c41296FcB3f5640").unwrap()
+        );
+        assert_eq!((pool.token_a, pool.token_b, pool.fee), (usdc, weth, 500));
+        assert_eq!((pool.token_a_decimals, pool.token_b_decimals), (6, 18));
+        assert!(!pool.sqrt_price.is_zero());
+
+        let pool = pools[1].unwrap();
+        assert_eq!(
+            pool.address,
+            H160::from_str("0x8ad599c3A0ff1De082011EFDDc58f1908eb6e6D8").unwrap()
+        );
+        assert_eq!(pool.fee, 3000);
+
+        assert!(pools[2].is_none());
+        assert!(pools[3].is_none());
+    }
+
+    #[tokio::test]
+    async fn test_verify_provenance() {
+        let rpc_endpoint = std::env::var("ETHEREUM_MAINNET_ENDPOINT")
+            .expect("Could not get ETHEREUM_MAINNET_ENDPOINT");
+        let middleware = Arc::new(Provider::<Http>::try_from(rpc_endpoint).unwrap());
+
+        let factory = H160::from_str("0x1F98431c8aD98523631AE4a59f267346ea31F984").unwrap();
+        let pool = UniswapV3Pool::new_from_address(
+            H160::from_str("0x88e6A0c2dDD26FEEb64F039a2c41296FcB3f5640").unwrap(),
+            middleware.clone(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(pool.get_factory(middleware.clone()).await.unwrap(), factory);
+        assert!(pool
+            .verify_provenance(factory, middleware.clone())
+            .await
+            .unwrap());
+
+        //The same pool does not verify against a different factory
+        assert!(!pool
+            .verify_provenance(H160::from_low_u64_be(1), middleware)
+            .await
+            .unwrap());
+    }
+
+    #[test]
+    fn test_getters() {
+        let pool = UniswapV3Pool {
+            address: H160::from_low_u64_be(1),
+            token_a: H160::from_low_u64_be(2),
+            token_a_decimals: 6,
+            token_b: H160::from_low_u64_be(3),
+            token_b_decimals: 18,
+            liquidity: 1_000_000,
+            sqrt_price: U256::from(2) << 96,
+            fee: 3000,
+            tick: 13_863,
+            tick_spacing: 60,
+            ..Default::default()
+        };
+
+        assert_eq!(pool.address(), H160::from_low_u64_be(1));
+        assert_eq!(pool.fee(), 3000);
+        assert_eq!(pool.sqrt_price(), U256::from(2) << 96);
+        assert_eq!(pool.tick(), 13_863);
+        assert_eq!(pool.liquidity(), 1_000_000);
+        assert_eq!(
+            pool.tokens(),
+            (H160::from_low_u64_be(2), H160::from_low_u64_be(3))
+        );
+        assert_eq!(pool.decimals(), (6, 18));
+    }
+
     #[tokio::test]
     async fn test_sync_pool() {
         let rpc_endpoint = std::env::var("ETHEREUM_MAINNET_ENDPOINT")
@@ -1149,6 +7678,28 @@ mod test {
         assert_eq!(649198362624067396, r_1);
     }
 
+    #[tokio::test]
+    async fn test_virtual_reserves_at_block() {
+        let rpc_endpoint = std::env::var("ETHEREUM_MAINNET_ENDPOINT")
+            .expect("Could not get ETHEREUM_MAINNET_ENDPOINT");
+        let middleware = Arc::new(Provider::<Http>::try_from(rpc_endpoint).unwrap());
+
+        let mut pool = UniswapV3Pool {
+            address: H160::from_str("0x88e6A0c2dDD26FEEb64F039a2c41296FcB3f5640").unwrap(),
+            ..Default::default()
+        };
+
+        pool.get_pool_data(middleware.clone()).await.unwrap();
+
+        let (r_0, r_1) = pool
+            .virtual_reserves_at_block(U64::from(16515398), middleware)
+            .await
+            .expect("Could not calculate virtual reserves");
+
+        assert_eq!(1067543429906214084651, r_0);
+        assert_eq!(649198362624067396, r_1);
+    }
+
     #[tokio::test]
     async fn test_calculate_price() {
         let rpc_endpoint = std::env::var("ETHEREUM_MAINNET_ENDPOINT")
@@ -1170,9 +7721,9 @@ mod test {
         let sqrt_price = block_pool.slot_0().block(16515398).call().await.unwrap().0;
         pool.sqrt_price = sqrt_price;
 
-        let float_price_a = pool.calculate_price(pool.token_a);
+        let float_price_a = pool.try_calculate_price(pool.token_a).unwrap();
 
-        let float_price_b = pool.calculate_price(pool.token_b);
+        let float_price_b = pool.try_calculate_price(pool.token_b).unwrap();
 
         dbg!(pool);
 
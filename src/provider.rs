@@ -0,0 +1,215 @@
+use std::fmt::Debug;
+
+use async_trait::async_trait;
+use ethers::{
+    providers::{JsonRpcClient, Middleware},
+    types::{transaction::eip2718::TypedTransaction, BlockId, Bytes, Filter, Log, U64},
+};
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::errors::PoolProviderError;
+
+//The only calls the crate makes to a node. Backends that are not a Middleware, such as a retrying or caching layer or a local
+//state database, can implement this and be used anywhere the crate takes a middleware by wrapping them in a PoolProviderClient.
+#[async_trait]
+pub trait PoolProvider: Send + Sync + Debug {
+    type Error: std::error::Error + Send + Sync + 'static;
+
+    //Executes the call at the given block, or at the latest block if block is None
+    async fn call(
+        &self,
+        tx: &TypedTransaction,
+        block: Option<BlockId>,
+    ) -> Result<Bytes, Self::Error>;
+
+    async fn get_logs(&self, filter: &Filter) -> Result<Vec<Log>, Self::Error>;
+
+    async fn get_block_number(&self) -> Result<U64, Self::Error>;
+}
+
+#[async_trait]
+impl<M> PoolProvider for M
+where
+    M: Middleware,
+    M::Error: 'static,
+{
+    type Error = M::Error;
+
+    async fn call(
+        &self,
+        tx: &TypedTransaction,
+        block: Option<BlockId>,
+    ) -> Result<Bytes, Self::Error> {
+        Middleware::call(self, tx, block).await
+    }
+
+    async fn get_logs(&self, filter: &Filter) -> Result<Vec<Log>, Self::Error> {
+        Middleware::get_logs(self, filter).await
+    }
+
+    async fn get_block_number(&self) -> Result<U64, Self::Error> {
+        Middleware::get_block_number(self).await
+    }
+}
+
+//Serves the JSON-RPC requests the crate makes from a PoolProvider, so that Provider::new(PoolProviderClient::new(pool_provider))
+//is a middleware backed by it. Requests for any other method are an UnsupportedMethod error.
+#[derive(Debug)]
+pub struct PoolProviderClient<P> {
+    pool_provider: P,
+}
+
+impl<P: PoolProvider> PoolProviderClient<P> {
+    pub fn new(pool_provider: P) -> PoolProviderClient<P> {
+        PoolProviderClient { pool_provider }
+    }
+}
+
+#[async_trait]
+impl<P: PoolProvider> JsonRpcClient for PoolProviderClient<P> {
+    type Error = PoolProviderError;
+
+    async fn request<T, R>(&self, method: &str, params: T) -> Result<R, PoolProviderError>
+    where
+        T: Serialize + Send + Sync + Debug,
+        R: DeserializeOwned + Send,
+    {
+        let params = serde_json::to_value(params)?;
+        let provider_error = |error: P::Error| PoolProviderError::Provider(Box::new(error));
+
+        let result = match method {
+            "eth_call" => {
+                let tx: TypedTransaction = serde_json::from_value(params[0].clone())?;
+                let block: Option<BlockId> = serde_json::from_value(params[1].clone())?;
+
+                serde_json::to_value(
+                    self.pool_provider
+                        .call(&tx, block)
+                        .await
+                        .map_err(provider_error)?,
+                )?
+            }
+            "eth_getLogs" => {
+                let filter: Filter = serde_json::from_value(params[0].clone())?;
+
+                serde_json::to_value(
+                    self.pool_provider
+                        .get_logs(&filter)
+                        .await
+                        .map_err(provider_error)?,
+                )?
+            }
+            "eth_blockNumber" => serde_json::to_value(
+                self.pool_provider
+                    .get_block_number()
+                    .await
+                    .map_err(provider_error)?,
+            )?,
+            _ => return Err(PoolProviderError::UnsupportedMethod(method.to_string())),
+        };
+
+        Ok(serde_json::from_value(result)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use async_trait::async_trait;
+    use ethers::{
+        providers::{JsonRpcClient, Middleware, Provider},
+        types::{transaction::eip2718::TypedTransaction, BlockId, Bytes, Filter, Log, U256, U64},
+    };
+
+    use super::{PoolProvider, PoolProviderClient};
+    use crate::{
+        errors::PoolProviderError,
+        pool::uniswap_v3::{simulate_swap_pure, TickData},
+        test_utils::{mock_pool, mock_tick_data_response},
+    };
+
+    //In-memory fixture that answers every call with the tick data of a single pool at a fixed block
+    #[derive(Debug)]
+    struct TickDataFixture {
+        ticks: Vec<(bool, i32, i128)>,
+        block_number: u64,
+    }
+
+    #[async_trait]
+    impl PoolProvider for TickDataFixture {
+        type Error = std::io::Error;
+
+        async fn call(
+            &self,
+            _tx: &TypedTransaction,
+            _block: Option<BlockId>,
+        ) -> Result<Bytes, Self::Error> {
+            Ok(mock_tick_data_response(&self.ticks, self.block_number))
+        }
+
+        async fn get_logs(&self, _filter: &Filter) -> Result<Vec<Log>, Self::Error> {
+            Ok(vec![])
+        }
+
+        async fn get_block_number(&self) -> Result<U64, Self::Error> {
+            Ok(U64::from(self.block_number))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_simulate_swap_with_pool_provider() {
+        let ticks = vec![(true, -10, 500_000_000_000_000_000), (false, -887270, 0)];
+        let middleware = Arc::new(Provider::new(PoolProviderClient::new(TickDataFixture {
+            ticks: ticks.clone(),
+            block_number: 7,
+        })));
+
+        let pool = mock_pool();
+        let amount_in = U256::from(5_000_000_000_000_000_u128);
+
+        let amount_out = pool
+            .simulate_swap(pool.token_a, amount_in, middleware.clone())
+            .await
+            .unwrap();
+
+        //The quote matches simulating against the fixture's tick data directly
+        let tick_data = ticks
+            .iter()
+            .map(|(initialized, tick, liquidity_net)| TickData {
+                initialized: *initialized,
+                tick: *tick,
+                liquidity_net: *liquidity_net,
+            })
+            .collect::<Vec<TickData>>();
+        let swap_result = simulate_swap_pure(
+            pool.sqrt_price,
+            pool.liquidity,
+            pool.tick,
+            pool.tick_spacing,
+            pool.fee,
+            &tick_data,
+            true,
+            amount_in,
+        )
+        .unwrap();
+        assert_eq!(amount_out, swap_result.amount_out);
+
+        assert_eq!(
+            Middleware::get_block_number(middleware.as_ref())
+                .await
+                .unwrap(),
+            U64::from(7)
+        );
+
+        //Methods outside of PoolProvider are not supported
+        let client = PoolProviderClient::new(TickDataFixture {
+            ticks,
+            block_number: 7,
+        });
+        assert!(matches!(
+            client.request::<_, U256>("eth_chainId", ()).await,
+            Err(PoolProviderError::UnsupportedMethod(method)) if method == "eth_chainId"
+        ));
+    }
+}
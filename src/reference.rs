@@ -0,0 +1,110 @@
+use std::sync::Arc;
+
+use ethers::{
+    providers::Middleware,
+    types::{H160, U256, U64},
+};
+
+use crate::{abi, errors::CFMMError, pool::UniswapV3Pool};
+
+pub const QUOTER_V2_ADDRESS: H160 = H160([
+    97, 255, 224, 20, 186, 23, 152, 158, 116, 60, 95, 108, 178, 27, 249, 105, 117, 48, 178, 30,
+]);
+
+//Wrapper around the Uniswap V3 QuoterV2 contract, used to cross-check simulated swaps against on-chain quotes
+pub struct QuoterV2<M: Middleware> {
+    quoter: abi::IUniswapV3QuoterV2<M>,
+}
+
+impl<M: Middleware> QuoterV2<M> {
+    pub fn new(address: H160, middleware: Arc<M>) -> Self {
+        QuoterV2 {
+            quoter: abi::IUniswapV3QuoterV2::new(address, middleware),
+        }
+    }
+
+    //Returns (amount_out, sqrt_price_after, ticks_crossed, gas_estimate) for an exact input swap through the pool
+    pub async fn quote_exact_input_single(
+        &self,
+        pool: &UniswapV3Pool,
+        token_in: H160,
+        amount_in: U256,
+        block_number: Option<U64>,
+    ) -> Result<(U256, U256, u32, U256), CFMMError<M>> {
+        let token_out = if token_in == pool.token_a {
+            pool.token_b
+        } else {
+            pool.token_a
+        };
+
+        let mut call = self
+            .quoter
+            .quote_exact_input_single(abi::QuoteExactInputSingleParams {
+                token_in,
+                token_out,
+                amount_in,
+                fee: pool.fee,
+                sqrt_price_limit_x96: U256::zero(),
+            });
+
+        if let Some(block_number) = block_number {
+            call = call.block(block_number);
+        }
+
+        Ok(call.call().await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{str::FromStr, sync::Arc};
+
+    use ethers::{
+        providers::{Http, Middleware, Provider},
+        types::{H160, U256},
+    };
+
+    use super::{QuoterV2, QUOTER_V2_ADDRESS};
+    use crate::pool::UniswapV3Pool;
+
+    #[test]
+    fn test_quoter_v2_address() {
+        assert_eq!(
+            QUOTER_V2_ADDRESS,
+            H160::from_str("0x61fFE014bA17989E743c5F6cB21bF9697530B21e").unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_simulate_swap_detailed_matches_quoter() {
+        let rpc_endpoint = std::env::var("ETHEREUM_MAINNET_ENDPOINT")
+            .expect("Could not get ETHEREUM_MAINNET_ENDPOINT");
+        let middleware = Arc::new(Provider::<Http>::try_from(rpc_endpoint).unwrap());
+
+        let pool = UniswapV3Pool::new_from_address(
+            H160::from_str("0x88e6A0c2dDD26FEEb64F039a2c41296FcB3f5640").unwrap(),
+            middleware.clone(),
+        )
+        .await
+        .unwrap();
+
+        let quoter = QuoterV2::new(QUOTER_V2_ADDRESS, middleware.clone());
+
+        let amount_in = U256::from_dec_str("10000000000000").unwrap(); // 10_000_000 USDC
+
+        let current_block = middleware.get_block_number().await.unwrap();
+        let swap_result = pool
+            .simulate_swap_detailed(pool.token_a, amount_in, middleware.clone())
+            .await
+            .unwrap();
+
+        let (amount_out, sqrt_price_after, ticks_crossed, _) = quoter
+            .quote_exact_input_single(&pool, pool.token_a, amount_in, Some(current_block))
+            .await
+            .unwrap();
+
+        assert_eq!(swap_result.amount_out, amount_out);
+        assert_eq!(swap_result.sqrt_price_after, sqrt_price_after);
+        assert_eq!(swap_result.ticks_crossed, ticks_crossed);
+    }
+}
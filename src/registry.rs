@@ -0,0 +1,35 @@
+use std::collections::HashMap;
+
+use ethers::types::H160;
+
+//Maps token symbols to addresses so that pools can be queried by symbol in scripts
+#[derive(Clone, Debug, Default)]
+pub struct TokenRegistry {
+    tokens: HashMap<String, H160>,
+}
+
+impl TokenRegistry {
+    pub fn new() -> TokenRegistry {
+        TokenRegistry::default()
+    }
+
+    //Registers the address for a symbol, returning the address that was previously registered for it if any
+    pub fn insert(&mut self, symbol: impl Into<String>, address: H160) -> Option<H160> {
+        self.tokens.insert(symbol.into(), address)
+    }
+
+    pub fn get(&self, symbol: &str) -> Option<H160> {
+        self.tokens.get(symbol).copied()
+    }
+}
+
+impl<S: Into<String>> FromIterator<(S, H160)> for TokenRegistry {
+    fn from_iter<I: IntoIterator<Item = (S, H160)>>(iter: I) -> Self {
+        TokenRegistry {
+            tokens: iter
+                .into_iter()
+                .map(|(symbol, address)| (symbol.into(), address))
+                .collect(),
+        }
+    }
+}
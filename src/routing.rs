@@ -0,0 +1,426 @@
+use std::{collections::HashMap, sync::Arc};
+
+use ethers::{
+    providers::Middleware,
+    types::{H160, U256},
+};
+
+use crate::{errors::CFMMError, pool::Pool};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Route {
+    pub pools: Vec<Pool>,
+    pub amount_in: U256,
+    pub amount_out: U256,
+}
+
+//Partial path explored while searching for a route
+struct PathState {
+    amount: U256,
+    pool_indices: Vec<usize>,
+    tokens: Vec<H160>,
+}
+
+//Maps each token to the indices of the pools that contain it
+pub fn build_token_graph(pools: &[Pool]) -> HashMap<H160, Vec<usize>> {
+    let mut token_graph: HashMap<H160, Vec<usize>> = HashMap::new();
+
+    for (i, pool) in pools.iter().enumerate() {
+        let (token_a, token_b) = pool.tokens();
+        token_graph.entry(token_a).or_default().push(i);
+        token_graph.entry(token_b).or_default().push(i);
+    }
+
+    token_graph
+}
+
+//Finds the route from token_in to token_out with at most max_hops pools that yields the largest amount out.
+//Paths never revisit a token, and at each hop only the best path reaching each token is explored further.
+pub async fn find_best_route<M: Middleware>(
+    pools: &[Pool],
+    token_in: H160,
+    token_out: H160,
+    amount_in: U256,
+    max_hops: usize,
+    middleware: Arc<M>,
+) -> Result<Route, CFMMError<M>> {
+    let token_graph = build_token_graph(pools);
+
+    let mut frontier = vec![PathState {
+        amount: amount_in,
+        pool_indices: vec![],
+        tokens: vec![token_in],
+    }];
+    let mut best_route: Option<PathState> = None;
+
+    for _ in 0..max_hops {
+        let mut next_frontier: HashMap<H160, PathState> = HashMap::new();
+
+        for path in frontier {
+            let token = *path.tokens.last().unwrap();
+
+            for &pool_index in token_graph.get(&token).into_iter().flatten() {
+                let pool = &pools[pool_index];
+                let next_token = match pool.get_other_token(token) {
+                    Some(next_token) => next_token,
+                    None => continue,
+                };
+
+                if path.tokens.contains(&next_token) {
+                    continue;
+                }
+
+                let amount_out = pool
+                    .simulate_swap(token, path.amount, middleware.clone())
+                    .await?;
+
+                if amount_out.is_zero() {
+                    continue;
+                }
+
+                let mut pool_indices = path.pool_indices.clone();
+                pool_indices.push(pool_index);
+                let mut tokens = path.tokens.clone();
+                tokens.push(next_token);
+
+                let next_path = PathState {
+                    amount: amount_out,
+                    pool_indices,
+                    tokens,
+                };
+
+                if next_token == token_out {
+                    let is_best = match &best_route {
+                        Some(best_route) => amount_out > best_route.amount,
+                        None => true,
+                    };
+
+                    if is_best {
+                        best_route = Some(next_path);
+                    }
+                } else {
+                    let is_best = match next_frontier.get(&next_token) {
+                        Some(best_path) => amount_out > best_path.amount,
+                        None => true,
+                    };
+
+                    if is_best {
+                        next_frontier.insert(next_token, next_path);
+                    }
+                }
+            }
+        }
+
+        frontier = next_frontier.into_values().collect();
+    }
+
+    match best_route {
+        Some(best_route) => Ok(Route {
+            pools: best_route.pool_indices.iter().map(|&i| pools[i]).collect(),
+            amount_in,
+            amount_out: best_route.amount,
+        }),
+        None => Err(CFMMError::NoRouteFound(token_in, token_out)),
+    }
+}
+
+//Finds the route from token_in to token_out with at most max_hops pools that needs the smallest amount in to receive amount_out.
+//Paths are explored backwards from token_out, computing the amount in each hop needs with an exact output simulation.
+//Pools that can not fill the amount out of a hop are skipped, and at each hop only the cheapest path reaching each token is explored further.
+pub async fn find_route_exact_output<M: Middleware>(
+    pools: &[Pool],
+    token_in: H160,
+    token_out: H160,
+    amount_out: U256,
+    max_hops: usize,
+    middleware: Arc<M>,
+) -> Result<Route, CFMMError<M>> {
+    let token_graph = build_token_graph(pools);
+
+    let mut frontier = vec![PathState {
+        amount: amount_out,
+        pool_indices: vec![],
+        tokens: vec![token_out],
+    }];
+    let mut best_route: Option<PathState> = None;
+
+    for _ in 0..max_hops {
+        let mut next_frontier: HashMap<H160, PathState> = HashMap::new();
+
+        for path in frontier {
+            let token = *path.tokens.last().unwrap();
+
+            for &pool_index in token_graph.get(&token).into_iter().flatten() {
+                let pool = &pools[pool_index];
+                let previous_token = match pool.get_other_token(token) {
+                    Some(previous_token) => previous_token,
+                    None => continue,
+                };
+
+                if path.tokens.contains(&previous_token) {
+                    continue;
+                }
+
+                let amount_in = match pool
+                    .simulate_swap_exact_output(previous_token, path.amount, middleware.clone())
+                    .await
+                {
+                    Ok(amount_in) => amount_in,
+                    Err(CFMMError::InsufficientLiquidity(_)) => continue,
+                    Err(err) => return Err(err),
+                };
+
+                let mut pool_indices = path.pool_indices.clone();
+                pool_indices.push(pool_index);
+                let mut tokens = path.tokens.clone();
+                tokens.push(previous_token);
+
+                let previous_path = PathState {
+                    amount: amount_in,
+                    pool_indices,
+                    tokens,
+                };
+
+                if previous_token == token_in {
+                    let is_best = match &best_route {
+                        Some(best_route) => amount_in < best_route.amount,
+                        None => true,
+                    };
+
+                    if is_best {
+                        best_route = Some(previous_path);
+                    }
+                } else {
+                    let is_best = match next_frontier.get(&previous_token) {
+                        Some(best_path) => amount_in < best_path.amount,
+                        None => true,
+                    };
+
+                    if is_best {
+                        next_frontier.insert(previous_token, previous_path);
+                    }
+                }
+            }
+        }
+
+        frontier = next_frontier.into_values().collect();
+    }
+
+    match best_route {
+        //The pools were collected from token_out back to token_in
+        Some(best_route) => Ok(Route {
+            pools: best_route
+                .pool_indices
+                .iter()
+                .rev()
+                .map(|&i| pools[i])
+                .collect(),
+            amount_in: best_route.amount,
+            amount_out,
+        }),
+        None => Err(CFMMError::NoRouteFound(token_in, token_out)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{str::FromStr, sync::Arc};
+
+    use ethers::{
+        providers::{MockProvider, Provider},
+        types::{H160, U256},
+    };
+
+    use super::{build_token_graph, find_best_route, find_route_exact_output};
+    use crate::{
+        pool::{Pool, UniswapV2Pool, UniswapV3Pool},
+        simulate_route,
+    };
+
+    #[test]
+    fn test_build_token_graph() {
+        let usdc = H160::from_str("0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48").unwrap();
+        let weth = H160::from_str("0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2").unwrap();
+        let dai = H160::from_str("0x6b175474e89094c44da98b954eedeac495271d0f").unwrap();
+
+        let pools = vec![
+            //USDC/WETH
+            Pool::UniswapV2(UniswapV2Pool {
+                address: H160::from_str("0xB4e16d0168e52d35CaCD2c6185b44281Ec28C9Dc").unwrap(),
+                token_a: usdc,
+                token_b: weth,
+                ..Default::default()
+            }),
+            //DAI/WETH
+            Pool::UniswapV2(UniswapV2Pool {
+                address: H160::from_str("0xA478c2975Ab1Ea89e8196811F51A7B7Ade33eB11").unwrap(),
+                token_a: dai,
+                token_b: weth,
+                ..Default::default()
+            }),
+            //USDC/WETH
+            Pool::UniswapV3(UniswapV3Pool {
+                address: H160::from_str("0x88e6A0c2dDD26FEEb64F039a2c41296FcB3f5640").unwrap(),
+                token_a: usdc,
+                token_b: weth,
+                ..Default::default()
+            }),
+            //DAI/USDC
+            Pool::UniswapV3(UniswapV3Pool {
+                address: H160::from_str("0x5777d92f208679DB4b9778590Fa3CAB3aC9e2168").unwrap(),
+                token_a: dai,
+                token_b: usdc,
+                ..Default::default()
+            }),
+        ];
+
+        let token_graph = build_token_graph(&pools);
+
+        assert_eq!(token_graph.len(), 3);
+        assert_eq!(token_graph[&usdc], vec![0, 2, 3]);
+        assert_eq!(token_graph[&weth], vec![0, 1, 2]);
+        assert_eq!(token_graph[&dai], vec![1, 3]);
+    }
+
+    #[tokio::test]
+    async fn test_find_best_route() {
+        let usdc = H160::from_str("0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48").unwrap();
+        let weth = H160::from_str("0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2").unwrap();
+        let dai = H160::from_str("0x6b175474e89094c44da98b954eedeac495271d0f").unwrap();
+
+        //Shallow USDC/WETH pool
+        let usdc_weth = Pool::UniswapV2(UniswapV2Pool {
+            address: H160::from_low_u64_be(1),
+            token_a: usdc,
+            token_a_decimals: 6,
+            token_b: weth,
+            token_b_decimals: 18,
+            reserve_0: 1_000_000_000_000,
+            reserve_1: 100_000_000_000_000_000_000,
+            fee: 300,
+        });
+
+        //Deep USDC/DAI and DAI/WETH pools
+        let usdc_dai = Pool::UniswapV2(UniswapV2Pool {
+            address: H160::from_low_u64_be(2),
+            token_a: usdc,
+            token_a_decimals: 6,
+            token_b: dai,
+            token_b_decimals: 18,
+            reserve_0: 10_000_000_000_000,
+            reserve_1: 10_000_000_000_000_000_000_000_000,
+            fee: 300,
+        });
+
+        let dai_weth = Pool::UniswapV2(UniswapV2Pool {
+            address: H160::from_low_u64_be(3),
+            token_a: dai,
+            token_a_decimals: 18,
+            token_b: weth,
+            token_b_decimals: 18,
+            reserve_0: 10_000_000_000_000_000_000_000_000,
+            reserve_1: 10_000_000_000_000_000_000_000,
+            fee: 300,
+        });
+
+        let pools = vec![usdc_weth, usdc_dai, dai_weth];
+        let middleware = Arc::new(Provider::<MockProvider>::mocked().0);
+
+        //1000 USDC
+        let amount_in = U256::from(1_000_000_000);
+        let route = find_best_route(&pools, usdc, weth, amount_in, 3, middleware.clone())
+            .await
+            .unwrap();
+
+        assert_eq!(route.pools, vec![usdc_dai, dai_weth]);
+
+        let direct_amount_out = usdc_weth
+            .simulate_swap(usdc, amount_in, middleware.clone())
+            .await
+            .unwrap();
+        assert!(route.amount_out > direct_amount_out);
+
+        let route_amount_out = simulate_route(usdc, amount_in, &route.pools, middleware)
+            .await
+            .unwrap();
+        assert_eq!(route.amount_out, route_amount_out);
+    }
+
+    #[tokio::test]
+    async fn test_find_route_exact_output() {
+        let usdc = H160::from_str("0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48").unwrap();
+        let weth = H160::from_str("0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2").unwrap();
+        let dai = H160::from_str("0x6b175474e89094c44da98b954eedeac495271d0f").unwrap();
+
+        //Shallow USDC/WETH pool
+        let usdc_weth = Pool::UniswapV2(UniswapV2Pool {
+            address: H160::from_low_u64_be(1),
+            token_a: usdc,
+            token_a_decimals: 6,
+            token_b: weth,
+            token_b_decimals: 18,
+            reserve_0: 1_000_000_000_000,
+            reserve_1: 100_000_000_000_000_000_000,
+            fee: 300,
+        });
+
+        //Deep USDC/DAI and DAI/WETH pools
+        let usdc_dai = Pool::UniswapV2(UniswapV2Pool {
+            address: H160::from_low_u64_be(2),
+            token_a: usdc,
+            token_a_decimals: 6,
+            token_b: dai,
+            token_b_decimals: 18,
+            reserve_0: 10_000_000_000_000,
+            reserve_1: 10_000_000_000_000_000_000_000_000,
+            fee: 300,
+        });
+
+        let dai_weth = Pool::UniswapV2(UniswapV2Pool {
+            address: H160::from_low_u64_be(3),
+            token_a: dai,
+            token_a_decimals: 18,
+            token_b: weth,
+            token_b_decimals: 18,
+            reserve_0: 10_000_000_000_000_000_000_000_000,
+            reserve_1: 10_000_000_000_000_000_000_000,
+            fee: 300,
+        });
+
+        let pools = vec![usdc_weth, usdc_dai, dai_weth];
+        let middleware = Arc::new(Provider::<MockProvider>::mocked().0);
+
+        //1 WETH
+        let amount_out = U256::from(1_000_000_000_000_000_000_u128);
+        let route = find_route_exact_output(&pools, usdc, weth, amount_out, 3, middleware.clone())
+            .await
+            .unwrap();
+
+        assert_eq!(route.pools, vec![usdc_dai, dai_weth]);
+        assert_eq!(route.amount_out, amount_out);
+
+        let direct_amount_in = usdc_weth
+            .simulate_swap_exact_output(usdc, amount_out, middleware.clone())
+            .await
+            .unwrap();
+        assert!(route.amount_in < direct_amount_in);
+
+        //Swapping the computed amount in forward receives at least amount_out
+        let route_amount_out = simulate_route(usdc, route.amount_in, &route.pools, middleware)
+            .await
+            .unwrap();
+        assert!(route_amount_out >= amount_out);
+
+        //More WETH than the pools hold can not be routed
+        assert!(find_route_exact_output(
+            &pools,
+            usdc,
+            weth,
+            U256::from(1_000_000_000_000_000_000_000_000_u128),
+            3,
+            Arc::new(Provider::<MockProvider>::mocked().0),
+        )
+        .await
+        .is_err());
+    }
+}
@@ -0,0 +1,47 @@
+//Fixtures shared by the unit tests of every module that mocks the provider
+use ethers::{
+    abi::Token,
+    types::{Bytes, H160, I256, U256},
+};
+
+use crate::pool::{uniswap_v3::encode_i24, UniswapV3Pool};
+
+//Pool sitting at tick 0 with 1e18 of active liquidity, used for tests that mock the provider
+pub(crate) fn mock_pool() -> UniswapV3Pool {
+    UniswapV3Pool {
+        address: H160::from_low_u64_be(1),
+        token_a: H160::from_low_u64_be(2),
+        token_a_decimals: 18,
+        token_b: H160::from_low_u64_be(3),
+        token_b_decimals: 18,
+        liquidity: 1_000_000_000_000_000_000,
+        sqrt_price: U256::one() << 96,
+        fee: 500,
+        tick: 0,
+        tick_spacing: 10,
+        liquidity_net: 0,
+        last_synced_block: 0,
+        created_at_block: None,
+        synced_at: None,
+    }
+}
+
+//ABI encodes the return data of the GetUniswapV3TickDataBatchRequest contract
+pub(crate) fn mock_tick_data_response(ticks: &[(bool, i32, i128)], block_number: u64) -> Bytes {
+    let tick_data = ticks
+        .iter()
+        .map(|(initialized, tick, liquidity_net)| {
+            Token::Tuple(vec![
+                Token::Bool(*initialized),
+                Token::Int(encode_i24(*tick)),
+                Token::Int(I256::from(*liquidity_net).into_raw()),
+            ])
+        })
+        .collect();
+
+    ethers::abi::encode(&[
+        Token::Array(tick_data),
+        Token::Uint(U256::from(block_number)),
+    ])
+    .into()
+}
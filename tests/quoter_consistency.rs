@@ -0,0 +1,87 @@
+//Compares simulate_swap against the on-chain QuoterV2 for every pool in a JSON fixture, for a grid of sizes in both directions.
+//Runs only when ETHEREUM_MAINNET_ENDPOINT is set. Set UNISWAP_V3_QUOTER_FIXTURE to use a different pool list.
+use std::{fs, sync::Arc};
+
+use cfmms::{
+    pool::{to_wei, UniswapV3Pool},
+    reference::{QuoterV2, QUOTER_V2_ADDRESS},
+};
+use ethers::{
+    providers::{Http, Middleware, Provider},
+    types::H160,
+};
+use serde::Deserialize;
+
+const DEFAULT_FIXTURE: &str = "tests/fixtures/uniswap_v3_quoter_pools.json";
+
+#[derive(Deserialize)]
+struct QuoterFixture {
+    //Swap sizes in whole units of token_in
+    sizes: Vec<f64>,
+    pools: Vec<H160>,
+}
+
+fn load_fixture() -> QuoterFixture {
+    let path = std::env::var("UNISWAP_V3_QUOTER_FIXTURE")
+        .unwrap_or_else(|_| format!("{}/{}", env!("CARGO_MANIFEST_DIR"), DEFAULT_FIXTURE));
+
+    let fixture = fs::read_to_string(&path)
+        .unwrap_or_else(|e| panic!("Could not read quoter fixture {path}: {e}"));
+
+    serde_json::from_str(&fixture)
+        .unwrap_or_else(|e| panic!("Could not parse quoter fixture {path}: {e}"))
+}
+
+#[tokio::test]
+async fn test_simulate_swap_matches_quoter_for_fixture_pools() {
+    let Ok(rpc_endpoint) = std::env::var("ETHEREUM_MAINNET_ENDPOINT") else {
+        eprintln!("ETHEREUM_MAINNET_ENDPOINT is not set, skipping quoter consistency test");
+        return;
+    };
+    let middleware = Arc::new(Provider::<Http>::try_from(rpc_endpoint).unwrap());
+
+    let fixture = load_fixture();
+    let quoter = QuoterV2::new(QUOTER_V2_ADDRESS, middleware.clone());
+
+    //Collect every mismatch so that a single run reports all failing pools, sizes and directions
+    let mut mismatches = vec![];
+
+    for pool_address in fixture.pools {
+        //The pool state, tick data and quotes are all read at the same block so that a new block can not land in between
+        let block_number = middleware.get_block_number().await.unwrap();
+
+        let pool = UniswapV3Pool::new_from_address(pool_address, middleware.clone())
+            .await
+            .unwrap_or_else(|e| panic!("Could not get pool data for {pool_address:?}: {e}"))
+            .at_block(block_number, middleware.clone())
+            .await
+            .unwrap_or_else(|e| panic!("Could not sync pool {pool_address:?}: {e}"));
+
+        for (token_in, decimals) in [
+            (pool.token_a, pool.token_a_decimals),
+            (pool.token_b, pool.token_b_decimals),
+        ] {
+            for size in fixture.sizes.iter() {
+                let amount_in = to_wei(*size, decimals);
+
+                let simulated = pool
+                    .simulate_swap_at_block(token_in, amount_in, block_number, middleware.clone())
+                    .await
+                    .unwrap_or_else(|e| panic!("Could not simulate swap on {pool_address:?}: {e}"));
+
+                let (quoted, _, _, _) = quoter
+                    .quote_exact_input_single(&pool, token_in, amount_in, Some(block_number))
+                    .await
+                    .unwrap_or_else(|e| panic!("Could not quote swap on {pool_address:?}: {e}"));
+
+                if simulated != quoted {
+                    mismatches.push(format!(
+                        "pool {pool_address:?}, token_in {token_in:?}, amount_in {amount_in}: simulated {simulated}, quoted {quoted}"
+                    ));
+                }
+            }
+        }
+    }
+
+    assert!(mismatches.is_empty(), "{}", mismatches.join("\n"));
+}
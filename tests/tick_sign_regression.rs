@@ -0,0 +1,205 @@
+//Regression grid for tick sign handling: compares simulate_swap against the on-chain QuoterV2 to the wei for pools whose current
+//tick is deeply negative, near zero and deeply positive, in both directions and for swaps small enough to stay in the current tick
+//as well as large enough to cross ticks. Each pool is then also moved onto its nearest initialized tick below the current tick, where
+//the first step of a swap down crosses the tick without swapping anything.
+//Runs only when UNISWAP_V3_FORK_ENDPOINT is set, and expects a node forked at PINNED_FORK_BLOCK so that results are deterministic:
+//    anvil --fork-url $ETHEREUM_MAINNET_ENDPOINT --fork-block-number 17000000
+use std::{ops::RangeInclusive, str::FromStr, sync::Arc};
+
+use cfmms::{
+    batch_requests::uniswap_v3::get_uniswap_v3_tick_data_batch_request,
+    pool::{to_wei, UniswapV3Pool},
+    reference::{QuoterV2, QUOTER_V2_ADDRESS},
+};
+use ethers::{
+    providers::{Http, Middleware, Provider},
+    types::{H160, H256, U256, U64},
+};
+
+const PINNED_FORK_BLOCK: u64 = 17_000_000;
+
+//Swap sizes in whole units of token_in
+const SIZES: [f64; 4] = [0.000001, 0.01, 1.0, 100.0];
+
+struct GridPool {
+    name: &'static str,
+    address: &'static str,
+    //Range that the pool's tick is expected to be in at the pinned block, so that the grid keeps covering every tick sign
+    tick_range: RangeInclusive<i32>,
+}
+
+const GRID: [GridPool; 5] = [
+    GridPool {
+        name: "DAI/USDC 0.01%",
+        address: "0x5777d92f208679DB4b9778590Fa3CAB3aC9e2168",
+        tick_range: -887272..=-100_000,
+    },
+    GridPool {
+        name: "WETH/USDT 0.3%",
+        address: "0x4e68Ccd3E89f51C3074ca5072bbAC773960dFa36",
+        tick_range: -887272..=-100_000,
+    },
+    GridPool {
+        name: "USDC/USDT 0.01%",
+        address: "0x3416cF6C708Da44DB2624D63ea0AAef7113527C6",
+        tick_range: -100..=100,
+    },
+    GridPool {
+        name: "USDC/WETH 0.05%",
+        address: "0x88e6A0c2dDD26FEEb64F039a2c41296FcB3f5640",
+        tick_range: 100_000..=887272,
+    },
+    GridPool {
+        name: "WBTC/WETH 0.3%",
+        address: "0xCBCdF9626bC03E24f779434178A73a0B4bad62eD",
+        tick_range: 100_000..=887272,
+    },
+];
+
+#[tokio::test]
+async fn test_simulate_swap_matches_quoter_across_tick_signs() {
+    let Ok(rpc_endpoint) = std::env::var("UNISWAP_V3_FORK_ENDPOINT") else {
+        eprintln!("UNISWAP_V3_FORK_ENDPOINT is not set, skipping tick sign regression test");
+        return;
+    };
+    let middleware = Arc::new(Provider::<Http>::try_from(rpc_endpoint).unwrap());
+
+    //simulate_swap reads the latest block, so the fork must not have moved past the pinned block
+    let block_number = middleware.get_block_number().await.unwrap();
+    assert_eq!(
+        block_number,
+        U64::from(PINNED_FORK_BLOCK),
+        "UNISWAP_V3_FORK_ENDPOINT must be a node forked at block {PINNED_FORK_BLOCK}"
+    );
+
+    let quoter = QuoterV2::new(QUOTER_V2_ADDRESS, middleware.clone());
+
+    //Collect every mismatch so that a single run reports all failing pools, sizes and directions
+    let mut mismatches = vec![];
+
+    for grid_pool in GRID {
+        let address = H160::from_str(grid_pool.address).unwrap();
+        let pool = UniswapV3Pool::new_from_address(address, middleware.clone())
+            .await
+            .unwrap_or_else(|e| panic!("Could not sync {}: {e}", grid_pool.name));
+
+        assert!(
+            grid_pool.tick_range.contains(&pool.tick),
+            "{} is at tick {}, outside of {:?}",
+            grid_pool.name,
+            pool.tick,
+            grid_pool.tick_range
+        );
+
+        compare_with_quoter(&pool, grid_pool.name, &quoter, &mut mismatches, &middleware).await;
+
+        //Move the pool onto the sqrt price of the nearest initialized tick at or below its tick. The liquidity in range stays the
+        //same, as if the pool had been swapped up to exactly that tick. slot0 is restored once the pool has been compared.
+        let (tick_data, _) = get_uniswap_v3_tick_data_batch_request(
+            &pool,
+            pool.tick,
+            true,
+            150,
+            None,
+            middleware.clone(),
+        )
+        .await
+        .unwrap();
+        let boundary_tick = tick_data
+            .iter()
+            .find(|tick_data| tick_data.initialized && tick_data.tick <= pool.tick)
+            .unwrap_or_else(|| panic!("No initialized tick below {}", grid_pool.name))
+            .tick;
+
+        let snapshot: U256 = middleware.request("evm_snapshot", ()).await.unwrap();
+        set_slot_0(address, boundary_tick, &middleware).await;
+
+        let boundary_pool = UniswapV3Pool::new_from_address(address, middleware.clone())
+            .await
+            .unwrap_or_else(|e| {
+                panic!(
+                    "Could not sync {} on its tick boundary: {e}",
+                    grid_pool.name
+                )
+            });
+        assert_eq!(boundary_pool.tick, boundary_tick);
+        assert_eq!(boundary_pool.liquidity, pool.liquidity);
+
+        compare_with_quoter(
+            &boundary_pool,
+            &format!("{} on tick boundary", grid_pool.name),
+            &quoter,
+            &mut mismatches,
+            &middleware,
+        )
+        .await;
+
+        let reverted: bool = middleware.request("evm_revert", [snapshot]).await.unwrap();
+        assert!(reverted);
+    }
+
+    assert!(mismatches.is_empty(), "{}", mismatches.join("\n"));
+}
+
+//Simulates every size in both directions and records each amount out that differs from the quoter's
+async fn compare_with_quoter(
+    pool: &UniswapV3Pool,
+    name: &str,
+    quoter: &QuoterV2<Provider<Http>>,
+    mismatches: &mut Vec<String>,
+    middleware: &Arc<Provider<Http>>,
+) {
+    for (token_in, decimals) in [
+        (pool.token_a, pool.token_a_decimals),
+        (pool.token_b, pool.token_b_decimals),
+    ] {
+        for size in SIZES {
+            let amount_in = to_wei(size, decimals);
+            if amount_in.is_zero() {
+                continue;
+            }
+
+            let simulated = pool
+                .simulate_swap(token_in, amount_in, middleware.clone())
+                .await
+                .unwrap_or_else(|e| panic!("Could not simulate swap on {name}: {e}"));
+
+            //The fork has not moved past the pinned block, and the boundary state only exists in the fork's latest state
+            let (quoted, _, _, _) = quoter
+                .quote_exact_input_single(pool, token_in, amount_in, None)
+                .await
+                .unwrap_or_else(|e| panic!("Could not quote swap on {name}: {e}"));
+
+            if simulated != quoted {
+                mismatches.push(format!(
+                    "{name} (tick {}), token_in {token_in:?}, amount_in {amount_in}: simulated {simulated}, quoted {quoted}",
+                    pool.tick
+                ));
+            }
+        }
+    }
+}
+
+//Overwrites the sqrt price and tick packed into the lower 184 bits of the pool's slot0 storage slot, keeping the rest of slot0
+async fn set_slot_0(pool: H160, tick: i32, middleware: &Arc<Provider<Http>>) {
+    let slot_0 = middleware
+        .get_storage_at(pool, H256::zero(), None)
+        .await
+        .unwrap();
+
+    let sqrt_price = uniswap_v3_math::tick_math::get_sqrt_ratio_at_tick(tick).unwrap();
+    let tick_bits = U256::from(tick as u32 & 0xFF_FFFF) << 160;
+    let upper_bits = U256::from_big_endian(slot_0.as_bytes()) >> 184 << 184;
+
+    let mut value = [0; 32];
+    (upper_bits | tick_bits | sqrt_price).to_big_endian(&mut value);
+
+    let set: bool = middleware
+        .request(
+            "anvil_setStorageAt",
+            (pool, H256::zero(), H256::from(value)),
+        )
+        .await
+        .unwrap();
+    assert!(set);
+}